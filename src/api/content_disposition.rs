@@ -0,0 +1,370 @@
+//! `Content-Disposition` parsing per RFC 6266, including RFC 5987 / RFC 2231 extended
+//! parameters (`filename*=UTF-8''...` and `filename*0*=` continuations).
+
+use std::collections::BTreeMap;
+
+/// Extracts a safe file name from a raw `Content-Disposition` header value.
+///
+/// `filename*` is preferred over `filename`; the result is reduced to a single path component
+/// with control and reserved characters removed.
+pub(crate) fn filename_from_header(raw: &[u8]) -> Option<String> {
+    let value = decode_header_bytes(raw);
+    let params = parse_params(&value);
+
+    let mut extended = None;
+    let mut plain = None;
+    let mut continuations: BTreeMap<u32, (bool, String)> = BTreeMap::new();
+    for (name, value) in params {
+        match name.as_str() {
+            "filename*" => {
+                if extended.is_none() {
+                    extended = decode_ext_value(&value);
+                }
+            }
+            "filename" => {
+                if plain.is_none() {
+                    plain = Some(value);
+                }
+            }
+            other => {
+                if let Some(rest) = other.strip_prefix("filename*") {
+                    let (index, encoded) = match rest.strip_suffix('*') {
+                        Some(index) => (index, true),
+                        None => (rest, false),
+                    };
+                    if let Ok(index) = index.parse::<u32>() {
+                        continuations.entry(index).or_insert((encoded, value));
+                    }
+                }
+            }
+        }
+    }
+
+    extended
+        .or_else(|| join_continuations(&continuations))
+        .and_then(|name| sanitize_filename(&name))
+        .or_else(|| plain.and_then(|name| sanitize_filename(&name)))
+}
+
+/// Header values are nominally ASCII, but servers do send raw UTF-8 (and occasionally
+/// ISO-8859-1) in `filename=`; accept both.
+fn decode_header_bytes(raw: &[u8]) -> String {
+    match std::str::from_utf8(raw) {
+        Ok(text) => text.to_string(),
+        Err(_) => raw.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Splits `type; name=value; name="quoted; value"` into lower-cased parameter names and
+/// unquoted values. The disposition type itself is skipped.
+fn parse_params(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = value.chars().peekable();
+
+    // Skip the disposition type.
+    for c in chars.by_ref() {
+        if c == ';' {
+            break;
+        }
+    }
+
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace() || *c == ';') {
+            chars.next();
+        }
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c == ';' {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+        if chars.peek().is_none() && name.trim().is_empty() {
+            break;
+        }
+        let name = name.trim().to_ascii_lowercase();
+        if chars.peek() != Some(&'=') {
+            // A bare token without a value carries nothing we can use.
+            if chars.next().is_none() {
+                break;
+            }
+            continue;
+        }
+        chars.next();
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some(escaped) = chars.next() {
+                            value.push(escaped);
+                        }
+                    }
+                    '"' => break,
+                    _ => value.push(c),
+                }
+            }
+            // Discard anything between the closing quote and the next separator.
+            while chars.peek().is_some_and(|c| *c != ';') {
+                chars.next();
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == ';' {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+            value = value.trim().to_string();
+        }
+
+        if !name.is_empty() {
+            params.push((name, value));
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+    }
+    params
+}
+
+/// Decodes an RFC 5987 `ext-value` (`charset'language'pct-encoded`).
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?.trim().to_ascii_lowercase();
+    let _language = parts.next()?;
+    let encoded = parts.next()?;
+    decode_with_charset(&charset, &percent_decode(encoded))
+}
+
+fn decode_with_charset(charset: &str, bytes: &[u8]) -> Option<String> {
+    match charset {
+        "utf-8" | "utf8" | "" => String::from_utf8(bytes.to_vec()).ok(),
+        "iso-8859-1" | "latin1" | "us-ascii" => Some(bytes.iter().map(|&b| b as char).collect()),
+        _ => None,
+    }
+}
+
+/// Joins RFC 2231 continuations (`filename*0*=UTF-8''a`, `filename*1=b`, ...). Only the first
+/// section carries the charset, and only `*`-suffixed sections are percent-encoded.
+fn join_continuations(sections: &BTreeMap<u32, (bool, String)>) -> Option<String> {
+    let (first_encoded, first) = sections.get(&0)?;
+    let mut charset = String::new();
+    let mut bytes = Vec::new();
+    for (expected, (index, (encoded, value))) in sections.iter().enumerate() {
+        if *index as usize != expected {
+            break;
+        }
+        if *index == 0 && *first_encoded {
+            let mut parts = first.splitn(3, '\'');
+            charset = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let _language = parts.next();
+            bytes.extend(percent_decode(parts.next().unwrap_or_default()));
+        } else if *encoded {
+            bytes.extend(percent_decode(value));
+        } else {
+            bytes.extend_from_slice(value.as_bytes());
+        }
+    }
+    decode_with_charset(&charset, &bytes)
+}
+
+pub(crate) fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && let (Some(hi), Some(lo)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2]))
+        {
+            out.push(hi << 4 | lo);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Device names Windows reserves in every directory, with or without an extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Reduces `name` to a single safe path component: directories are dropped, control and
+/// reserved characters removed, trailing dots and spaces trimmed, Windows device names
+/// (`CON`, `NUL.txt`, ...) prefixed with `_`, and `.`/`..` or empty results rejected.
+pub(crate) fn sanitize_filename(name: &str) -> Option<String> {
+    let last = name
+        .rsplit(['/', '\\'])
+        .find(|segment| !segment.trim().is_empty())?;
+    let cleaned: String = last
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*'))
+        .collect();
+    let cleaned = cleaned
+        .trim()
+        .trim_end_matches(['.', ' '])
+        .trim_start_matches('.');
+    if cleaned.is_empty() {
+        return None;
+    }
+    let stem = cleaned.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        return Some(format!("_{}", cleaned));
+    }
+    Some(cleaned.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filename(header: &str) -> Option<String> {
+        filename_from_header(header.as_bytes())
+    }
+
+    #[test]
+    fn quoted_strings_unescape_and_keep_separators() {
+        assert_eq!(
+            filename(r#"attachment; filename="a \"quoted\" name.txt""#).as_deref(),
+            Some("a quoted name.txt")
+        );
+        assert_eq!(
+            filename(r#"attachment; filename="semi;colon\;.txt"; size=3"#).as_deref(),
+            Some("semi;colon;.txt")
+        );
+        assert_eq!(
+            filename(r#"attachment; filename="report.pdf" trailing; size=3"#).as_deref(),
+            Some("report.pdf")
+        );
+    }
+
+    #[test]
+    fn extended_values_decode_charset_and_skip_language() {
+        assert_eq!(
+            filename("attachment; filename*=UTF-8'en'%E2%82%AC%20rates.txt").as_deref(),
+            Some("€ rates.txt")
+        );
+        assert_eq!(
+            filename("attachment; filename*=iso-8859-1'de'%A3%20rates.txt").as_deref(),
+            Some("£ rates.txt")
+        );
+        assert_eq!(
+            filename("attachment; filename*=utf-8''%E5%B0%8F%E8%AF%B4.zip").as_deref(),
+            Some("小说.zip")
+        );
+        assert_eq!(
+            filename("attachment; filename*0*=UTF-8''%E5%B0%8F; filename*1=b.txt").as_deref(),
+            Some("小b.txt")
+        );
+    }
+
+    #[test]
+    fn extended_filename_takes_precedence() {
+        assert_eq!(
+            filename(r#"attachment; filename="plain.txt"; filename*=UTF-8''ext.txt"#).as_deref(),
+            Some("ext.txt")
+        );
+        assert_eq!(
+            filename(r#"attachment; filename*=UTF-8''ext.txt; filename="plain.txt""#).as_deref(),
+            Some("ext.txt")
+        );
+        // An extended value that cannot be decoded falls back to the plain one.
+        assert_eq!(
+            filename(r#"attachment; filename*=x-unknown''ext.txt; filename="plain.txt""#)
+                .as_deref(),
+            Some("plain.txt")
+        );
+    }
+
+    #[test]
+    fn malformed_parameters_are_skipped() {
+        assert_eq!(filename("attachment"), None);
+        assert_eq!(filename("attachment; filename"), None);
+        assert_eq!(filename("attachment; filename="), None);
+        assert_eq!(
+            filename("attachment; =oops; filename=ok.txt").as_deref(),
+            Some("ok.txt")
+        );
+        assert_eq!(
+            filename("attachment; filename*=no-quotes; filename=ok.txt").as_deref(),
+            Some("ok.txt")
+        );
+        assert_eq!(
+            filename(r#"attachment; filename="unterminated.txt"#).as_deref(),
+            Some("unterminated.txt")
+        );
+        assert_eq!(
+            filename("attachment;;  FILENAME = spaced.txt ;").as_deref(),
+            Some("spaced.txt")
+        );
+    }
+
+    #[test]
+    fn path_traversal_is_reduced_to_the_last_component() {
+        assert_eq!(
+            filename(r#"attachment; filename="../../etc/passwd""#).as_deref(),
+            Some("passwd")
+        );
+        assert_eq!(
+            filename(r#"attachment; filename="..\\..\\boot.ini""#).as_deref(),
+            Some("boot.ini")
+        );
+        assert_eq!(
+            filename("attachment; filename*=UTF-8''..%2F..%2Fsecret.txt").as_deref(),
+            Some("secret.txt")
+        );
+        assert_eq!(filename(r#"attachment; filename="..""#), None);
+        assert_eq!(
+            filename(r#"attachment; filename="dir/""#).as_deref(),
+            Some("dir")
+        );
+        assert_eq!(sanitize_filename(".hidden").as_deref(), Some("hidden"));
+    }
+
+    #[test]
+    fn windows_reserved_names_and_trailing_dots() {
+        assert_eq!(sanitize_filename("CON").as_deref(), Some("_CON"));
+        assert_eq!(sanitize_filename("nul.txt").as_deref(), Some("_nul.txt"));
+        assert_eq!(
+            sanitize_filename("Com1.tar.gz").as_deref(),
+            Some("_Com1.tar.gz")
+        );
+        assert_eq!(sanitize_filename("lpt9").as_deref(), Some("_lpt9"));
+        assert_eq!(sanitize_filename("aux .txt").as_deref(), Some("_aux .txt"));
+        assert_eq!(
+            sanitize_filename("console.txt").as_deref(),
+            Some("console.txt")
+        );
+        assert_eq!(sanitize_filename("COM10").as_deref(), Some("COM10"));
+        assert_eq!(sanitize_filename("report. . ").as_deref(), Some("report"));
+        assert_eq!(
+            sanitize_filename("a<b>c:d|e?f*.txt").as_deref(),
+            Some("abcdef.txt")
+        );
+        assert_eq!(sanitize_filename(" . "), None);
+    }
+}
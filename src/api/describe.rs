@@ -0,0 +1,63 @@
+use schemars::{JsonSchema, Schema, json_schema};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::ops;
+use crate::validate::{Validate, Validator};
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct DescribeRequest {
+    /// Operation to describe; every registered operation is listed when omitted.
+    #[serde(default)]
+    op: Option<String>,
+}
+
+impl Validate for DescribeRequest {
+    fn validate(&self, _validator: &mut Validator) {}
+}
+
+pub fn handle_describe_op(request: DescribeRequest) -> Result<Value, String> {
+    match request.op.as_deref().filter(|op| !op.is_empty()) {
+        Some(op) => describe(op),
+        None => describe_all(),
+    }
+}
+
+pub(crate) fn describe(op: &str) -> Result<Value, String> {
+    ops::describe_operation(op).ok_or_else(|| format!("unknown core operation: {}", op))
+}
+
+pub(crate) fn describe_all() -> Result<Value, String> {
+    let described: Vec<Value> = ops::operation_names()
+        .iter()
+        .filter_map(|name| ops::describe_operation(name))
+        .collect();
+    Ok(json!({ "ops": described }))
+}
+
+pub(crate) fn describe_response_schema() -> Schema {
+    let description = json!({
+        "type": "object",
+        "properties": {
+            "name": { "type": "string" },
+            "summary": { "type": "string" },
+            "category": { "type": "string" },
+            "network": { "type": "boolean" },
+            "request_schema": {},
+            "response_schema": {}
+        },
+        "required": [
+            "name", "summary", "category", "network", "request_schema", "response_schema"
+        ]
+    });
+    json_schema!({
+        "oneOf": [
+            description.clone(),
+            {
+                "type": "object",
+                "properties": { "ops": { "type": "array", "items": description } },
+                "required": ["ops"]
+            }
+        ]
+    })
+}
@@ -1,15 +1,17 @@
-use std::time::Duration;
-
 use reqwest::blocking::Client;
-use reqwest::header::{ACCEPT, CONNECTION, HeaderMap, HeaderValue, REFERER, USER_AGENT};
+use reqwest::header::{ACCEPT, CONNECTION, HeaderValue, REFERER};
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_json::Value;
 
-const DIRECTORY_URL: &str = "https://fanqienovel.com/api/reader/directory/detail";
-const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0 Safari/537.36";
+use crate::api::profiles::{self, Identity};
+use crate::config::{self, CoreConfig};
+use crate::http::TracedSend;
+use crate::metrics;
+use crate::validate::{Validate, Validator};
 
-#[derive(Deserialize)]
-struct DirectoryDetailRequest {
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct DirectoryDetailRequest {
     #[serde(default)]
     url: Option<String>,
     book_id: String,
@@ -17,42 +19,61 @@ struct DirectoryDetailRequest {
     user_agent: Option<String>,
     #[serde(default)]
     install_id: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
 }
 
-pub fn handle_directory_detail(payload: &[u8]) -> Result<Value, String> {
-    let req: DirectoryDetailRequest = serde_json::from_slice(payload).map_err(|e| e.to_string())?;
-    if req.book_id.trim().is_empty() {
-        return Err("book_id missing".to_string());
+impl Validate for DirectoryDetailRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .optional_url("url", self.url.as_deref())
+            .numeric("book_id", &self.book_id)
+            .optional_numeric("install_id", self.install_id.as_deref());
     }
+}
 
-    let url = req.url.as_deref().unwrap_or(DIRECTORY_URL);
+pub fn handle_directory_detail(req: DirectoryDetailRequest) -> Result<Value, String> {
+    let config = config::current();
+    let url = req.url.as_deref().unwrap_or(&config.endpoints.directory);
     let api_url = format!("{}?bookId={}", url, req.book_id);
+    let identity = profiles::resolve(req.install_id.as_deref(), req.profile.as_deref())?;
 
     // first attempt
-    match call_directory(&api_url, &req) {
+    let first = tracing::info_span!("attempt", attempt = 1)
+        .in_scope(|| call_directory(&api_url, &req, &identity));
+    match first {
         Ok(v) => Ok(v),
         Err(err) => {
             // simple warm-up and retry once (fanqienovel.com sometimes requires a warm page hit)
-            let _ = warm_page(&req.book_id, &req);
-            call_directory(&api_url, &req).map_err(|e| format!("{}; retry: {}", err, e))
+            tracing::warn!(
+                error = %err,
+                "directory request failed; warming book page and retrying"
+            );
+            let _entered = tracing::info_span!("attempt", attempt = 2).entered();
+            metrics::record_retry();
+            let _ = warm_page(&req.book_id, &req, &identity);
+            call_directory(&api_url, &req, &identity).map_err(|e| format!("{}; retry: {}", err, e))
         }
     }
 }
 
-fn call_directory(api_url: &str, req: &DirectoryDetailRequest) -> Result<Value, String> {
-    let client = build_client().map_err(|e| e.to_string())?;
+fn call_directory(
+    api_url: &str,
+    req: &DirectoryDetailRequest,
+    identity: &Identity,
+) -> Result<Value, String> {
+    let config = config::current();
+    let client = build_client(&config)?;
 
-    let mut headers = HeaderMap::new();
+    let mut headers = identity.headers(req.user_agent.as_deref())?;
     headers.insert(
-        USER_AGENT,
-        HeaderValue::from_str(req.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
-            .map_err(|e| e.to_string())?,
+        ACCEPT,
+        HeaderValue::from_static("application/json, text/plain, */*"),
     );
-    headers.insert(ACCEPT, HeaderValue::from_static("application/json, text/plain, */*"));
     headers.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
     headers.insert(
         REFERER,
-        HeaderValue::from_str(&format!("https://fanqienovel.com/page/{}", req.book_id))
+        HeaderValue::from_str(&format!("{}{}", config.endpoints.book_page, req.book_id))
             .map_err(|e| e.to_string())?,
     );
 
@@ -67,7 +88,7 @@ fn call_directory(api_url: &str, req: &DirectoryDetailRequest) -> Result<Value,
     client
         .get(api_url)
         .headers(headers)
-        .send()
+        .send_traced()
         .map_err(|e| e.to_string())?
         .error_for_status()
         .map_err(|e| e.to_string())?
@@ -75,25 +96,30 @@ fn call_directory(api_url: &str, req: &DirectoryDetailRequest) -> Result<Value,
         .map_err(|e| e.to_string())
 }
 
-fn warm_page(book_id: &str, req: &DirectoryDetailRequest) -> Result<(), String> {
-    let client = build_client().map_err(|e| e.to_string())?;
+fn warm_page(
+    book_id: &str,
+    req: &DirectoryDetailRequest,
+    identity: &Identity,
+) -> Result<(), String> {
+    let config = config::current();
+    let client = build_client(&config)?;
 
-    let mut headers = HeaderMap::new();
+    let mut headers = identity.headers(req.user_agent.as_deref())?;
     headers.insert(
-        USER_AGENT,
-        HeaderValue::from_str(req.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
-            .map_err(|e| e.to_string())?,
+        ACCEPT,
+        HeaderValue::from_static("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
     );
-    headers.insert(ACCEPT, HeaderValue::from_static("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"));
     headers.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
 
     let _ = client
-        .get(format!("https://fanqienovel.com/page/{}", book_id))
+        .get(format!("{}{}", config.endpoints.book_page, book_id))
         .headers(headers)
-        .send();
+        .send_traced();
     Ok(())
 }
 
-fn build_client() -> Result<Client, reqwest::Error> {
-    Client::builder().timeout(Duration::from_secs(15)).build()
+fn build_client(config: &CoreConfig) -> Result<Client, String> {
+    config::client_builder(config.timeouts.directory_ms)?
+        .build()
+        .map_err(|err| err.to_string())
 }
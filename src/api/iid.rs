@@ -1,90 +1,123 @@
-use std::time::Duration;
-
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD as BASE64_STD;
-use reqwest::blocking::Client;
-use reqwest::header::{ACCEPT, CONNECTION, CONTENT_TYPE, USER_AGENT};
+use reqwest::header::{ACCEPT, CONNECTION, CONTENT_TYPE};
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_json::Value;
 
+use crate::api::profiles;
+use crate::config;
+use crate::http::TracedSend;
+use crate::validate::{Validate, Validator};
+
 const CONTENT_TYPE_VALUE: &str = "application/octet-stream;tt-data=a";
 const ACCEPT_VALUE: &str = "application/json, */*";
 const CONNECTION_CLOSE: &str = "close";
-const DEFAULT_AID: &str = "1967";
-const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0 Safari/537.36";
 
-#[derive(Deserialize)]
-struct RegisterRequest {
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct RegisterRequest {
+    #[serde(default = "default_register_url")]
     url: String,
     body_b64: String,
     #[serde(default)]
     user_agent: Option<String>,
+    /// Device profile the registered install ID is bound to; defaults to the configured one.
+    #[serde(default)]
+    profile: Option<String>,
 }
 
-#[derive(Deserialize)]
-struct ActivateRequest {
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct ActivateRequest {
+    #[serde(default = "default_activate_url")]
     url: String,
     tt_info: String,
-    #[serde(default = "default_aid")]
-    aid: String,
+    #[serde(default)]
+    aid: Option<String>,
     #[serde(default)]
     user_agent: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
 }
 
-fn default_aid() -> String {
-    DEFAULT_AID.to_string()
+fn default_register_url() -> String {
+    config::current().endpoints.device_register.clone()
 }
 
-pub fn handle_register(payload: &[u8]) -> Result<Value, String> {
-    let request: RegisterRequest =
-        serde_json::from_slice(payload).map_err(|err| err.to_string())?;
-    if request.url.is_empty() {
-        return Err("device register url missing".to_string());
+fn default_activate_url() -> String {
+    config::current().endpoints.device_activate.clone()
+}
+
+impl Validate for RegisterRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .url("url", &self.url)
+            .non_empty("body_b64", &self.body_b64);
+        if BASE64_STD.decode(&self.body_b64).is_err() {
+            validator.push("body_b64", "must be valid base64");
+        }
     }
+}
+
+impl Validate for ActivateRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .url("url", &self.url)
+            .optional_numeric("aid", self.aid.as_deref());
+    }
+}
+
+pub fn handle_register(request: RegisterRequest) -> Result<Value, String> {
     let body = BASE64_STD
         .decode(request.body_b64)
         .map_err(|err| err.to_string())?;
-    let client = build_client().map_err(|err| err.to_string())?;
+    let identity = profiles::resolve(None, request.profile.as_deref())?;
+    let client = build_client()?;
     let response = client
         .post(&request.url)
-        .header(
-            USER_AGENT,
-            request.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT),
-        )
+        .headers(identity.headers(request.user_agent.as_deref())?)
         .header(CONTENT_TYPE, CONTENT_TYPE_VALUE)
         .header(ACCEPT, ACCEPT_VALUE)
         .header(CONNECTION, CONNECTION_CLOSE)
         .body(body)
-        .send()
+        .send_traced()
         .map_err(|err| err.to_string())?
         .error_for_status()
         .map_err(|err| err.to_string())?
         .json::<Value>()
         .map_err(|err| err.to_string())?;
+    if let Some(install_id) = registered_install_id(&response) {
+        profiles::bind(&install_id, &identity.name)?;
+    }
     Ok(response)
 }
 
-pub fn handle_activate(payload: &[u8]) -> Result<Value, String> {
-    let request: ActivateRequest =
-        serde_json::from_slice(payload).map_err(|err| err.to_string())?;
+/// Extracts the install ID from a device register response, if one was issued.
+pub(crate) fn registered_install_id(response: &Value) -> Option<String> {
+    match response
+        .get("install_id_str")
+        .or_else(|| response.get("install_id"))?
+    {
+        Value::String(text) if !text.is_empty() && text != "0" => Some(text.clone()),
+        Value::Number(number) if number.as_u64().is_some_and(|n| n != 0) => {
+            Some(number.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// An empty `tt_info` returns `null` without activating.
+pub fn handle_activate(request: ActivateRequest) -> Result<Value, String> {
     if request.tt_info.is_empty() {
         return Ok(Value::Null);
     }
-    if request.url.is_empty() {
-        return Err("activate url missing".to_string());
-    }
-    let client = build_client().map_err(|err| err.to_string())?;
+    let identity = profiles::resolve(None, request.profile.as_deref())?;
+    let aid = identity.aid(request.aid.as_deref());
+    let client = build_client()?;
     let response = client
         .get(&request.url)
-        .header(
-            USER_AGENT,
-            request.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT),
-        )
-        .query(&[
-            ("aid", request.aid.as_str()),
-            ("tt_info", request.tt_info.as_str()),
-        ])
-        .send()
+        .headers(identity.headers(request.user_agent.as_deref())?)
+        .query(&[("aid", aid.as_str()), ("tt_info", request.tt_info.as_str())])
+        .send_traced()
         .map_err(|err| err.to_string())?;
     let bytes = response.bytes().map_err(|err| err.to_string())?;
     if bytes.is_empty() {
@@ -96,6 +129,8 @@ pub fn handle_activate(payload: &[u8]) -> Result<Value, String> {
     }
 }
 
-fn build_client() -> Result<Client, reqwest::Error> {
-    Client::builder().timeout(Duration::from_secs(10)).build()
+fn build_client() -> Result<reqwest::blocking::Client, String> {
+    config::client_builder(config::current().timeouts.iid_ms)?
+        .build()
+        .map_err(|err| err.to_string())
 }
@@ -0,0 +1,625 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use reqwest::StatusCode;
+use schemars::{JsonSchema, Schema, json_schema};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use zeroize::Zeroize;
+
+use crate::api::NoPayload;
+use crate::api::iid::{handle_activate, handle_register, registered_install_id};
+use crate::dry_run;
+use crate::validate::{Validate, Validator};
+
+const DEFAULT_MAX_FAILURES: u32 = 3;
+const DEFAULT_COOLDOWN_MS: u64 = 60_000;
+
+static POOL: Lazy<Mutex<InstallIdPool>> = Lazy::new(|| Mutex::new(InstallIdPool::default()));
+
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Strategy {
+    #[default]
+    RoundRobin,
+    LeastRecentlyUsed,
+}
+
+/// Describes how a replacement identity is obtained once a pooled one is retired.
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+struct ReplacementSpec {
+    register_url: String,
+    body_b64: String,
+    #[serde(default)]
+    user_agent: Option<String>,
+    #[serde(default)]
+    activate_url: Option<String>,
+    #[serde(default)]
+    tt_info: Option<String>,
+    #[serde(default)]
+    aid: Option<String>,
+    /// Device profile replacement identities are bound to.
+    #[serde(default)]
+    profile: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct ConfigureRequest {
+    #[serde(default)]
+    strategy: Option<Strategy>,
+    #[serde(default)]
+    max_failures: Option<u32>,
+    #[serde(default)]
+    cooldown_ms: Option<u64>,
+    #[serde(default)]
+    replacement: Option<ReplacementSpec>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct InstallIdsRequest {
+    install_ids: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct ReportRequest {
+    install_id: String,
+    status: u16,
+}
+
+struct Entry {
+    install_id: String,
+    healthy: bool,
+    failures: u32,
+    uses: u64,
+    last_used: Option<Instant>,
+    unhealthy_until: Option<Instant>,
+    last_status: Option<u16>,
+}
+
+impl Entry {
+    fn new(install_id: String) -> Self {
+        Self {
+            install_id,
+            healthy: true,
+            failures: 0,
+            uses: 0,
+            last_used: None,
+            unhealthy_until: None,
+            last_status: None,
+        }
+    }
+
+    fn available(&self, now: Instant) -> bool {
+        self.healthy || self.unhealthy_until.is_some_and(|until| until <= now)
+    }
+}
+
+struct InstallIdPool {
+    entries: Vec<Entry>,
+    retired: Vec<String>,
+    cursor: usize,
+    strategy: Strategy,
+    max_failures: u32,
+    cooldown: Duration,
+    replacement: Option<ReplacementSpec>,
+    /// Retirements still waiting for a replacement identity.
+    pending_replacements: usize,
+    /// Whether a replenisher thread is working through `pending_replacements`.
+    replenishing: bool,
+}
+
+impl Default for InstallIdPool {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            retired: Vec::new(),
+            cursor: 0,
+            strategy: Strategy::default(),
+            max_failures: DEFAULT_MAX_FAILURES,
+            cooldown: Duration::from_millis(DEFAULT_COOLDOWN_MS),
+            replacement: None,
+            pending_replacements: 0,
+            replenishing: false,
+        }
+    }
+}
+
+impl InstallIdPool {
+    fn add(&mut self, install_id: String) -> bool {
+        if install_id.is_empty() || self.entries.iter().any(|e| e.install_id == install_id) {
+            return false;
+        }
+        self.retired.retain(|id| *id != install_id);
+        self.entries.push(Entry::new(install_id));
+        true
+    }
+
+    /// The index of the entry the next acquisition hands out.
+    fn next(&self, now: Instant) -> Option<usize> {
+        match self.strategy {
+            Strategy::RoundRobin => {
+                let len = self.entries.len();
+                (0..len)
+                    .map(|offset| (self.cursor + offset) % len)
+                    .find(|&i| self.entries[i].available(now))
+            }
+            Strategy::LeastRecentlyUsed => self
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.available(now))
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(i, _)| i),
+        }
+    }
+
+    fn acquire(&mut self) -> Option<String> {
+        let now = Instant::now();
+        let index = self.next(now)?;
+        if matches!(self.strategy, Strategy::RoundRobin) {
+            self.cursor = (index + 1) % self.entries.len();
+        }
+        let entry = &mut self.entries[index];
+        entry.uses += 1;
+        entry.last_used = Some(now);
+        Some(entry.install_id.clone())
+    }
+
+    /// Records an upstream status for `install_id`, returning true when the identity was retired.
+    fn report(&mut self, install_id: &str, status: u16) -> bool {
+        let Some(index) = self
+            .entries
+            .iter()
+            .position(|entry| entry.install_id == install_id)
+        else {
+            return false;
+        };
+        let entry = &mut self.entries[index];
+        entry.last_status = Some(status);
+        if is_identity_failure(status) {
+            entry.failures += 1;
+            entry.healthy = false;
+            entry.unhealthy_until = Some(Instant::now() + self.cooldown);
+            if entry.failures >= self.max_failures {
+                let retired = self.entries.remove(index);
+                self.retired.push(retired.install_id);
+                if self.cursor > index {
+                    self.cursor -= 1;
+                }
+                if self.cursor >= self.entries.len() {
+                    self.cursor = 0;
+                }
+                return true;
+            }
+        } else if status < 500 {
+            entry.failures = 0;
+            entry.healthy = true;
+            entry.unhealthy_until = None;
+        }
+        false
+    }
+
+    /// Queues a replacement for a retired identity, returning true when the caller has to start
+    /// the replenisher because none is running.
+    fn queue_replacement(&mut self) -> bool {
+        if self.replacement.is_none() {
+            return false;
+        }
+        self.pending_replacements += 1;
+        !std::mem::replace(&mut self.replenishing, true)
+    }
+
+    /// The spec for the next queued replacement. Once the queue is empty, the replenisher is
+    /// marked stopped and `None` tells it to exit.
+    fn next_replacement(&mut self) -> Option<ReplacementSpec> {
+        let spec = self
+            .replacement
+            .clone()
+            .filter(|_| self.pending_replacements > 0);
+        match spec {
+            Some(_) => self.pending_replacements -= 1,
+            None => {
+                self.pending_replacements = 0;
+                self.replenishing = false;
+            }
+        }
+        spec
+    }
+
+    fn status(&self) -> Value {
+        let now = Instant::now();
+        let entries: Vec<Value> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                json!({
+                    "install_id": entry.install_id,
+                    "healthy": entry.healthy,
+                    "available": entry.available(now),
+                    "failures": entry.failures,
+                    "uses": entry.uses,
+                    "last_status": entry.last_status,
+                })
+            })
+            .collect();
+        json!({
+            "strategy": self.strategy,
+            "max_failures": self.max_failures,
+            "cooldown_ms": self.cooldown.as_millis() as u64,
+            "replacement": self.replacement.is_some(),
+            "pending_replacements": self.pending_replacements,
+            "entries": entries,
+            "retired": self.retired,
+        })
+    }
+}
+
+fn is_identity_failure(status: u16) -> bool {
+    matches!(status, 401 | 403 | 429)
+}
+
+/// Returns the caller supplied install ID, or picks one from the pool when none was given. A dry
+/// run only looks at the ID it would get, leaving the rotation and usage counts alone.
+pub(crate) fn resolve_install_id(explicit: Option<&str>) -> Result<String, String> {
+    if let Some(install_id) = explicit.filter(|s| !s.is_empty()) {
+        return Ok(install_id.to_string());
+    }
+    let mut pool = POOL.lock().unwrap();
+    if pool.entries.is_empty() {
+        return Err("install_id missing and install id pool is empty".to_string());
+    }
+    let picked = if dry_run::active() {
+        pool.next(Instant::now())
+            .map(|index| pool.entries[index].install_id.clone())
+    } else {
+        pool.acquire()
+    };
+    picked.ok_or_else(|| "no healthy install id available".to_string())
+}
+
+/// Drops every pooled and retired install ID; the pool's settings are kept.
+pub(crate) fn clear() {
+    let mut pool = POOL.lock().unwrap();
+    for mut entry in pool.entries.drain(..) {
+        entry.install_id.zeroize();
+    }
+    for mut install_id in pool.retired.drain(..) {
+        install_id.zeroize();
+    }
+    pool.cursor = 0;
+    pool.pending_replacements = 0;
+}
+
+/// Returns every pooled install ID, healthy or not.
+pub(crate) fn install_ids() -> Vec<String> {
+    let pool = POOL.lock().unwrap();
+    pool.entries
+        .iter()
+        .map(|entry| entry.install_id.clone())
+        .collect()
+}
+
+/// Adds install IDs to the pool, returning how many were not already present.
+pub(crate) fn add_install_ids(install_ids: impl IntoIterator<Item = String>) -> usize {
+    let mut pool = POOL.lock().unwrap();
+    install_ids
+        .into_iter()
+        .filter(|install_id| pool.add(install_id.clone()))
+        .count()
+}
+
+/// Feeds an upstream response status back into the pool. Unknown IDs are ignored.
+pub(crate) fn report_status(install_id: &str, status: StatusCode) {
+    record(install_id, status.as_u16());
+}
+
+fn record(install_id: &str, status: u16) {
+    let start_replenisher = {
+        let mut pool = POOL.lock().unwrap();
+        if !pool.report(install_id, status) {
+            return;
+        }
+        pool.queue_replacement()
+    };
+    tracing::warn!(status, "install ID retired after repeated failures");
+    if start_replenisher {
+        std::thread::spawn(replenish);
+    }
+}
+
+/// Registers replacements one at a time until none are queued, so a burst of retirements keeps
+/// a single thread busy instead of starting one per retirement.
+fn replenish() {
+    loop {
+        let Some(spec) = POOL.lock().unwrap().next_replacement() else {
+            return;
+        };
+        match register_replacement(&spec) {
+            Some(install_id) => {
+                POOL.lock().unwrap().add(install_id);
+                tracing::info!("replacement install ID registered");
+            }
+            None => tracing::warn!("replacement install ID registration failed"),
+        }
+    }
+}
+
+fn register_replacement(spec: &ReplacementSpec) -> Option<String> {
+    let request = serde_json::from_value(json!({
+        "url": spec.register_url,
+        "body_b64": spec.body_b64,
+        "user_agent": spec.user_agent,
+        "profile": spec.profile,
+    }))
+    .ok()?;
+    let response = handle_register(request).ok()?;
+    let install_id = registered_install_id(&response)?;
+
+    if let (Some(url), Some(tt_info)) = (
+        spec.activate_url.as_ref(),
+        spec.tt_info.as_ref().filter(|t| !t.is_empty()),
+    ) {
+        let mut activate = json!({
+            "url": url,
+            "tt_info": tt_info,
+            "user_agent": spec.user_agent,
+            "profile": spec.profile,
+        });
+        if let Some(aid) = spec.aid.as_ref() {
+            activate["aid"] = Value::String(aid.clone());
+        }
+        let request = serde_json::from_value(activate).ok()?;
+        let _ = handle_activate(request);
+    }
+    Some(install_id)
+}
+
+impl Validate for ConfigureRequest {
+    fn validate(&self, validator: &mut Validator) {
+        if let Some(max_failures) = self.max_failures {
+            validator.at_least("max_failures", max_failures, 1);
+        }
+        if let Some(replacement) = self.replacement.as_ref() {
+            validator
+                .url("replacement.register_url", &replacement.register_url)
+                .non_empty("replacement.body_b64", &replacement.body_b64)
+                .optional_url(
+                    "replacement.activate_url",
+                    replacement.activate_url.as_deref(),
+                )
+                .optional_numeric("replacement.aid", replacement.aid.as_deref());
+        }
+    }
+}
+
+impl Validate for InstallIdsRequest {
+    fn validate(&self, validator: &mut Validator) {
+        for (index, install_id) in self.install_ids.iter().enumerate() {
+            validator.numeric(&format!("install_ids[{}]", index), install_id);
+        }
+    }
+}
+
+impl Validate for ReportRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .numeric("install_id", &self.install_id)
+            .range("status", self.status, 100, 599);
+    }
+}
+
+pub fn handle_pool_configure(request: ConfigureRequest) -> Result<Value, String> {
+    let mut pool = POOL.lock().unwrap();
+    if let Some(strategy) = request.strategy {
+        pool.strategy = strategy;
+    }
+    if let Some(max_failures) = request.max_failures {
+        pool.max_failures = max_failures.max(1);
+    }
+    if let Some(cooldown_ms) = request.cooldown_ms {
+        pool.cooldown = Duration::from_millis(cooldown_ms);
+    }
+    if let Some(replacement) = request.replacement {
+        pool.replacement = Some(replacement);
+    }
+    Ok(pool.status())
+}
+
+pub fn handle_pool_add(request: InstallIdsRequest) -> Result<Value, String> {
+    let added = add_install_ids(request.install_ids);
+    let size = POOL.lock().unwrap().entries.len();
+    Ok(json!({ "added": added, "size": size }))
+}
+
+pub fn handle_pool_remove(request: InstallIdsRequest) -> Result<Value, String> {
+    let mut pool = POOL.lock().unwrap();
+    let before = pool.entries.len();
+    pool.entries
+        .retain(|entry| !request.install_ids.contains(&entry.install_id));
+    pool.cursor = 0;
+    Ok(json!({ "removed": before - pool.entries.len(), "size": pool.entries.len() }))
+}
+
+pub fn handle_pool_acquire(_request: NoPayload) -> Result<Value, String> {
+    let install_id = resolve_install_id(None)?;
+    Ok(json!({ "install_id": install_id }))
+}
+
+pub fn handle_pool_report(request: ReportRequest) -> Result<Value, String> {
+    record(&request.install_id, request.status);
+    Ok(POOL.lock().unwrap().status())
+}
+
+pub fn handle_pool_status(_request: NoPayload) -> Result<Value, String> {
+    Ok(POOL.lock().unwrap().status())
+}
+
+pub(crate) fn pool_status_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": {
+            "strategy": { "enum": ["round_robin", "least_recently_used"] },
+            "max_failures": { "type": "integer", "minimum": 1 },
+            "cooldown_ms": { "type": "integer", "minimum": 0 },
+            "replacement": { "type": "boolean" },
+            "pending_replacements": { "type": "integer", "minimum": 0 },
+            "entries": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "install_id": { "type": "string" },
+                        "healthy": { "type": "boolean" },
+                        "available": { "type": "boolean" },
+                        "failures": { "type": "integer", "minimum": 0 },
+                        "uses": { "type": "integer", "minimum": 0 },
+                        "last_status": { "type": ["integer", "null"] }
+                    }
+                }
+            },
+            "retired": { "type": "array", "items": { "type": "string" } }
+        },
+        "required": [
+            "strategy",
+            "max_failures",
+            "cooldown_ms",
+            "replacement",
+            "pending_replacements",
+            "entries",
+            "retired"
+        ]
+    })
+}
+
+pub(crate) fn pool_change_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": {
+            "added": { "type": "integer", "minimum": 0 },
+            "removed": { "type": "integer", "minimum": 0 },
+            "size": { "type": "integer", "minimum": 0 }
+        },
+        "required": ["size"]
+    })
+}
+
+pub(crate) fn acquire_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": { "install_id": { "type": "string" } },
+        "required": ["install_id"]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(strategy: Strategy, install_ids: &[&str]) -> InstallIdPool {
+        let mut pool = InstallIdPool {
+            strategy,
+            ..InstallIdPool::default()
+        };
+        for install_id in install_ids {
+            pool.add(install_id.to_string());
+        }
+        pool
+    }
+
+    fn acquire_n(pool: &mut InstallIdPool, n: usize) -> Vec<String> {
+        (0..n).filter_map(|_| pool.acquire()).collect()
+    }
+
+    fn spec() -> ReplacementSpec {
+        ReplacementSpec {
+            register_url: "https://log.example.test/register".to_string(),
+            body_b64: "ZGV2aWNl".to_string(),
+            user_agent: None,
+            activate_url: None,
+            tt_info: None,
+            aid: None,
+            profile: None,
+        }
+    }
+
+    #[test]
+    fn round_robin_rotates_and_skips_unavailable_ids() {
+        let mut pool = pool(Strategy::RoundRobin, &["1", "2", "3"]);
+        assert_eq!(acquire_n(&mut pool, 4), ["1", "2", "3", "1"]);
+
+        assert!(!pool.report("3", 429));
+        assert_eq!(acquire_n(&mut pool, 3), ["2", "1", "2"]);
+        assert_eq!(pool.entries[1].uses, 3);
+    }
+
+    #[test]
+    fn least_recently_used_prefers_unused_ids() {
+        let mut pool = pool(Strategy::LeastRecentlyUsed, &["1", "2"]);
+        assert_eq!(acquire_n(&mut pool, 2), ["1", "2"]);
+        pool.add("3".to_string());
+        assert_eq!(acquire_n(&mut pool, 3), ["3", "1", "2"]);
+    }
+
+    #[test]
+    fn failed_ids_return_after_the_cooldown() {
+        let mut pool = pool(Strategy::RoundRobin, &["1"]);
+        pool.cooldown = Duration::from_secs(60);
+
+        assert!(!pool.report("1", 403));
+        let now = Instant::now();
+        assert_eq!(pool.next(now), None);
+        assert_eq!(pool.acquire(), None);
+        assert_eq!(pool.next(now + pool.cooldown), Some(0));
+
+        // Server errors are not the identity's fault and leave its state alone.
+        assert!(!pool.report("1", 503));
+        assert_eq!(pool.next(now), None);
+
+        assert!(!pool.report("1", 200));
+        assert_eq!(pool.entries[0].failures, 0);
+        assert_eq!(pool.acquire().as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn repeated_failures_retire_an_id() {
+        let mut pool = pool(Strategy::RoundRobin, &["1", "2", "3"]);
+        pool.max_failures = 2;
+        assert_eq!(acquire_n(&mut pool, 2), ["1", "2"]);
+
+        assert!(!pool.report("2", 401));
+        assert!(pool.report("2", 401));
+        assert_eq!(pool.retired, ["2"]);
+        assert!(!pool.report("2", 401), "retired ids are unknown");
+
+        // The cursor still points at the id after the retired one.
+        assert_eq!(acquire_n(&mut pool, 3), ["3", "1", "3"]);
+
+        assert!(pool.add("2".to_string()));
+        assert!(pool.retired.is_empty());
+        assert!(!pool.add("2".to_string()));
+    }
+
+    #[test]
+    fn one_replenisher_serves_every_retirement() {
+        let mut pool = pool(Strategy::RoundRobin, &[]);
+        assert!(
+            !pool.queue_replacement(),
+            "nothing to register without a spec"
+        );
+        assert_eq!(pool.pending_replacements, 0);
+
+        pool.replacement = Some(spec());
+        assert!(pool.queue_replacement());
+        assert!(!pool.queue_replacement());
+        assert!(!pool.queue_replacement());
+        assert_eq!(pool.pending_replacements, 3);
+
+        assert!((0..3).all(|_| pool.next_replacement().is_some()));
+        assert!(pool.next_replacement().is_none());
+        assert!(!pool.replenishing);
+
+        // The next retirement starts a new replenisher.
+        assert!(pool.queue_replacement());
+    }
+}
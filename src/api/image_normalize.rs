@@ -0,0 +1,172 @@
+use std::fs;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STD;
+use schemars::{JsonSchema, Schema, json_schema};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::validate::{Validate, Validator};
+
+const DEFAULT_MAX_DIMENSION: u32 = 1600;
+const DEFAULT_QUALITY: u8 = 85;
+
+/// Options for re-encoding a cover into something every e-reader can display.
+#[derive(Clone, Deserialize, JsonSchema)]
+#[cfg_attr(not(feature = "media_normalize"), allow(dead_code))]
+pub(crate) struct NormalizeOptions {
+    #[serde(default = "default_max_dimension")]
+    pub max_dimension: u32,
+    #[serde(default = "default_quality")]
+    pub quality: u8,
+}
+
+fn default_max_dimension() -> u32 {
+    DEFAULT_MAX_DIMENSION
+}
+
+fn default_quality() -> u8 {
+    DEFAULT_QUALITY
+}
+
+pub(crate) struct NormalizedImage {
+    pub body: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub source_format: &'static str,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct NormalizeRequest {
+    #[serde(default)]
+    body_b64: Option<String>,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    dest_path: Option<String>,
+    #[serde(flatten)]
+    options: NormalizeOptions,
+}
+
+impl Validate for NormalizeRequest {
+    fn validate(&self, validator: &mut Validator) {
+        let has_body = self.body_b64.as_deref().is_some_and(|b| !b.is_empty());
+        let has_path = self.path.as_deref().is_some_and(|p| !p.is_empty());
+        if !has_body && !has_path {
+            validator.push("body_b64", "either body_b64 or path is required");
+        }
+        validator
+            .at_least("max_dimension", self.options.max_dimension, 1)
+            .range("quality", self.options.quality, 1, 100);
+    }
+}
+
+pub fn handle_normalize_image(request: NormalizeRequest) -> Result<Value, String> {
+    let source = match (request.body_b64.as_deref(), request.path.as_deref()) {
+        (Some(body), _) if !body.is_empty() => {
+            BASE64_STD.decode(body).map_err(|err| err.to_string())?
+        }
+        (_, Some(path)) if !path.is_empty() => fs::read(path).map_err(|err| err.to_string())?,
+        _ => return Err("normalize needs body_b64 or path".to_string()),
+    };
+    let image = normalize(&source, &request.options)?;
+    let mut result = json!({
+        "content_type": "image/jpeg",
+        "source_format": image.source_format,
+        "width": image.width,
+        "height": image.height,
+        "size": image.body.len(),
+    });
+    match request.dest_path.as_deref().filter(|p| !p.is_empty()) {
+        Some(dest) => {
+            write_replacing(Path::new(dest), &image.body)?;
+            result["path"] = Value::String(dest.to_string());
+        }
+        None => result["body_b64"] = Value::String(BASE64_STD.encode(&image.body)),
+    }
+    Ok(result)
+}
+
+/// Writes `data` next to `path` and renames it into place.
+pub(crate) fn write_replacing(path: &Path, data: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, data).map_err(|err| err.to_string())?;
+    fs::rename(&tmp, path).map_err(|err| err.to_string())
+}
+
+/// Decodes a JPEG, PNG or WebP image and re-encodes it as a baseline JPEG no larger than
+/// `max_dimension` on either side. Transparency is flattened onto white and all metadata
+/// (EXIF, ICC, XMP) is dropped by the re-encode.
+#[cfg(feature = "media_normalize")]
+pub(crate) fn normalize(
+    source: &[u8],
+    options: &NormalizeOptions,
+) -> Result<NormalizedImage, String> {
+    use image::codecs::jpeg::JpegEncoder;
+    use image::imageops::FilterType;
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    let format = image::guess_format(source).map_err(|err| err.to_string())?;
+    let source_format = match format {
+        ImageFormat::Jpeg => "jpeg",
+        ImageFormat::Png => "png",
+        ImageFormat::WebP => "webp",
+        other => return Err(format!("unsupported image format: {:?}", other)),
+    };
+    let mut decoded =
+        image::load_from_memory_with_format(source, format).map_err(|err| err.to_string())?;
+
+    let max_dimension = options.max_dimension.max(1);
+    if decoded.width() > max_dimension || decoded.height() > max_dimension {
+        decoded = decoded.resize(max_dimension, max_dimension, FilterType::Lanczos3);
+    }
+
+    let rgba = decoded.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let rgb = RgbImage::from_fn(width, height, |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let alpha = a as u16;
+        let blend = |c: u8| ((c as u16 * alpha + 255 * (255 - alpha)) / 255) as u8;
+        Rgb([blend(r), blend(g), blend(b)])
+    });
+
+    let mut body = Vec::new();
+    JpegEncoder::new_with_quality(&mut body, options.quality.clamp(1, 100))
+        .encode_image(&rgb)
+        .map_err(|err| err.to_string())?;
+    Ok(NormalizedImage {
+        body,
+        width,
+        height,
+        source_format,
+    })
+}
+
+#[cfg(not(feature = "media_normalize"))]
+pub(crate) fn normalize(
+    _source: &[u8],
+    _options: &NormalizeOptions,
+) -> Result<NormalizedImage, String> {
+    Err("image normalization requires the `media_normalize` feature".to_string())
+}
+
+pub(crate) fn normalize_response_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": {
+            "content_type": { "const": "image/jpeg" },
+            "source_format": { "enum": ["jpeg", "png", "webp"] },
+            "width": { "type": "integer", "minimum": 1 },
+            "height": { "type": "integer", "minimum": 1 },
+            "size": { "type": "integer", "minimum": 0 },
+            "path": { "type": "string" },
+            "body_b64": { "type": "string", "contentEncoding": "base64" }
+        },
+        "required": ["content_type", "source_format", "width", "height", "size"]
+    })
+}
@@ -1,38 +1,633 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use std::time::Duration;
 
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD as BASE64_STD;
+use reqwest::StatusCode;
 use reqwest::blocking::Client;
+use reqwest::header::{
+    CONTENT_TYPE, ETAG, HeaderMap, HeaderName, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED, REFERER,
+};
+use schemars::{JsonSchema, Schema, json_schema};
 use serde::Deserialize;
 use serde_json::json;
 
-#[derive(Deserialize)]
-struct MediaFetchRequest {
+use crate::api::image_normalize::{self, NormalizeOptions};
+use crate::api::{media_cache, profiles};
+use crate::config;
+use crate::dry_run;
+use crate::http::TracedSend;
+use crate::metrics;
+use crate::progress;
+use crate::validate::{Validate, Validator};
+
+const DEFAULT_MAX_BYTES: u64 = 32 * 1024 * 1024;
+const MAX_CONCURRENCY: usize = 32;
+
+#[derive(Clone, Deserialize, JsonSchema)]
+pub(crate) struct MediaFetchRequest {
     url: String,
     #[serde(default = "default_timeout")]
     timeout_ms: u64,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    referer: Option<String>,
+    #[serde(default)]
+    user_agent: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
+    #[serde(default = "default_max_bytes")]
+    max_bytes: u64,
+    #[serde(default)]
+    cache: Option<bool>,
+    #[serde(default)]
+    normalize: Option<NormalizeOptions>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct MediaFetchManyRequest {
+    items: Vec<MediaFetchItem>,
+    #[serde(default = "default_concurrency")]
+    concurrency: usize,
+    #[serde(default = "default_timeout")]
+    timeout_ms: u64,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    referer: Option<String>,
+    #[serde(default)]
+    user_agent: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
+    #[serde(default = "default_max_bytes")]
+    max_bytes: u64,
+    #[serde(default)]
+    cache: Option<bool>,
+    #[serde(default)]
+    normalize: Option<NormalizeOptions>,
+    #[serde(default)]
+    progress_id: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct MediaFetchItem {
+    url: String,
+    #[serde(default)]
+    dest_path: Option<String>,
+    #[serde(default)]
+    referer: Option<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+}
+
+fn default_concurrency() -> usize {
+    4
 }
 
 fn default_timeout() -> u64 {
-    10_000
+    config::current().timeouts.media_ms
 }
 
-pub fn handle_media_fetch(payload: &[u8]) -> Result<serde_json::Value, String> {
-    let request: MediaFetchRequest =
-        serde_json::from_slice(payload).map_err(|err| err.to_string())?;
-    if request.url.is_empty() {
-        return Err("media fetch url missing".to_string());
+fn default_max_bytes() -> u64 {
+    DEFAULT_MAX_BYTES
+}
+
+impl Validate for MediaFetchRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .url("url", &self.url)
+            .optional_url("referer", self.referer.as_deref())
+            .at_least("timeout_ms", self.timeout_ms, 1)
+            .at_least("max_bytes", self.max_bytes, 1);
     }
-    let client = Client::builder()
-        .timeout(Duration::from_millis(request.timeout_ms.max(1)))
-        .build()
-        .map_err(|err| err.to_string())?;
-    let response = client
+}
+
+impl Validate for MediaFetchManyRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .range("concurrency", self.concurrency, 1, MAX_CONCURRENCY)
+            .optional_url("referer", self.referer.as_deref())
+            .at_least("timeout_ms", self.timeout_ms, 1)
+            .at_least("max_bytes", self.max_bytes, 1);
+        for (index, item) in self.items.iter().enumerate() {
+            validator
+                .url(&format!("items[{}].url", index), &item.url)
+                .optional_url(
+                    &format!("items[{}].referer", index),
+                    item.referer.as_deref(),
+                );
+            if let Some(timeout_ms) = item.timeout_ms {
+                validator.at_least(&format!("items[{}].timeout_ms", index), timeout_ms, 1);
+            }
+        }
+    }
+}
+
+pub fn handle_media_fetch(request: MediaFetchRequest) -> Result<serde_json::Value, String> {
+    let client = build_client(request.timeout_ms)?;
+    let mut media = fetch(&client, &request)?;
+    if let Some(options) = request.normalize.as_ref() {
+        media.normalize(options)?;
+    }
+    Ok(json!({
+        "status": media.status,
+        "content_type": media.content_type,
+        "size": media.body.len(),
+        "final_url": media.final_url,
+        "cache": media.cache,
+        "normalized": media.normalized,
+        "body_b64": BASE64_STD.encode(&media.body),
+    }))
+}
+
+pub fn handle_media_fetch_many(
+    request: MediaFetchManyRequest,
+) -> Result<serde_json::Value, String> {
+    if request.items.is_empty() {
+        return Ok(json!({ "items": [], "succeeded": 0, "failed": 0 }));
+    }
+    // One client serves the whole batch; each request carries its own item's timeout.
+    let client = build_client(request.timeout_ms)?;
+    let workers = request
+        .concurrency
+        .clamp(1, MAX_CONCURRENCY)
+        .min(request.items.len());
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let results: Mutex<Vec<serde_json::Value>> =
+        Mutex::new(vec![serde_json::Value::Null; request.items.len()]);
+
+    let op = metrics::current_op();
+    let dry_run = dry_run::current();
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                let _scope = metrics::enter_op(op.clone());
+                let _dry_run = dry_run::enter(dry_run.clone());
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = request.items.get(index) else {
+                        break;
+                    };
+                    let result = fetch_item(&client, &request, item);
+                    let finished = done.fetch_add(1, Ordering::Relaxed) + 1;
+                    progress::emit(json!({
+                        "op": "media_fetch_many",
+                        "id": request.progress_id,
+                        "index": index,
+                        "url": item.url,
+                        "ok": result["ok"],
+                        "completed": finished,
+                        "total": request.items.len(),
+                    }));
+                    results.lock().unwrap()[index] = result;
+                }
+            });
+        }
+    });
+
+    let items = results.into_inner().unwrap();
+    let succeeded = items.iter().filter(|item| item["ok"] == true).count();
+    Ok(json!({
+        "items": items,
+        "succeeded": succeeded,
+        "failed": items.len() - succeeded,
+    }))
+}
+
+fn fetch_item(
+    client: &Client,
+    batch: &MediaFetchManyRequest,
+    item: &MediaFetchItem,
+) -> serde_json::Value {
+    let mut headers = batch.headers.clone();
+    headers.extend(item.headers.clone());
+    let request = MediaFetchRequest {
+        url: item.url.clone(),
+        timeout_ms: item.timeout_ms.unwrap_or(batch.timeout_ms),
+        headers,
+        referer: item.referer.clone().or_else(|| batch.referer.clone()),
+        user_agent: batch.user_agent.clone(),
+        profile: batch.profile.clone(),
+        max_bytes: batch.max_bytes,
+        cache: batch.cache,
+        normalize: batch.normalize.clone(),
+    };
+    match item.dest_path.as_deref().filter(|p| !p.is_empty()) {
+        Some(path) => {
+            match fetch_to_file(client, &request, Path::new(path)).and_then(|mut media| {
+                if let Some(options) = request.normalize.as_ref() {
+                    media.normalize(options, Path::new(path))?;
+                }
+                Ok(media)
+            }) {
+                Ok(media) => json!({
+                    "url": item.url,
+                    "ok": true,
+                    "status": media.status,
+                    "content_type": media.content_type,
+                    "bytes": media.bytes,
+                    "final_url": media.final_url,
+                    "normalized": media.normalized,
+                    "path": path,
+                }),
+                Err(err) => json!({ "url": item.url, "ok": false, "error": err, "path": path }),
+            }
+        }
+        None => match fetch(client, &request).and_then(|mut media| {
+            if let Some(options) = request.normalize.as_ref() {
+                media.normalize(options)?;
+            }
+            Ok(media)
+        }) {
+            Ok(media) => json!({
+                "url": item.url,
+                "ok": true,
+                "status": media.status,
+                "content_type": media.content_type,
+                "bytes": media.body.len(),
+                "final_url": media.final_url,
+                "cache": media.cache,
+                "normalized": media.normalized,
+                "body_b64": BASE64_STD.encode(&media.body),
+            }),
+            Err(err) => json!({ "url": item.url, "ok": false, "error": err }),
+        },
+    }
+}
+
+struct StreamedMedia {
+    status: u16,
+    content_type: Option<String>,
+    final_url: String,
+    bytes: u64,
+    normalized: bool,
+}
+
+impl StreamedMedia {
+    fn normalize(&mut self, options: &NormalizeOptions, path: &Path) -> Result<(), String> {
+        let source = fs::read(path).map_err(|err| err.to_string())?;
+        let image = image_normalize::normalize(&source, options)?;
+        image_normalize::write_replacing(path, &image.body)?;
+        self.bytes = image.body.len() as u64;
+        self.content_type = Some("image/jpeg".to_string());
+        self.normalized = true;
+        Ok(())
+    }
+}
+
+/// Streams `request.url` into `path` without buffering the body, writing through a `.part`
+/// file so a failed transfer never leaves a truncated file at `path`.
+fn fetch_to_file(
+    client: &Client,
+    request: &MediaFetchRequest,
+    path: &Path,
+) -> Result<StreamedMedia, String> {
+    let mut response = client
         .get(&request.url)
-        .send()
+        .headers(request_headers(request)?)
+        .timeout(request_timeout(request))
+        .send_traced()
         .map_err(|err| err.to_string())?
         .error_for_status()
         .map_err(|err| err.to_string())?;
-    let bytes = response.bytes().map_err(|err| err.to_string())?;
-    Ok(json!({ "body_b64": BASE64_STD.encode(bytes) }))
+    if let Some(length) = response.content_length()
+        && length > request.max_bytes
+    {
+        return Err(format!(
+            "media too large: {} bytes exceeds limit of {}",
+            length, request.max_bytes
+        ));
+    }
+    let status = response.status().as_u16();
+    let final_url = response.url().to_string();
+    let declared_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    let part = PathBuf::from(part);
+
+    let (bytes, head) = match write_body(&mut response, &part, request.max_bytes) {
+        Ok(done) => done,
+        Err(err) => {
+            let _ = fs::remove_file(&part);
+            return Err(err);
+        }
+    };
+    fs::rename(&part, path).map_err(|err| err.to_string())?;
+
+    let content_type = declared_type
+        .filter(|value| !value.is_empty() && !is_generic_type(value))
+        .or_else(|| sniff_content_type(&head).map(str::to_string));
+    Ok(StreamedMedia {
+        status,
+        content_type,
+        final_url,
+        bytes,
+        normalized: false,
+    })
+}
+
+/// Copies the response body into `part`, returning the byte count and the first bytes of the
+/// body for content sniffing.
+fn write_body(
+    response: &mut reqwest::blocking::Response,
+    part: &Path,
+    max_bytes: u64,
+) -> Result<(u64, Vec<u8>), String> {
+    let mut file = File::create(part).map_err(|err| err.to_string())?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut head = Vec::new();
+    let mut written: u64 = 0;
+    loop {
+        let read = response.read(&mut buf).map_err(|err| err.to_string())?;
+        if read == 0 {
+            break;
+        }
+        written += read as u64;
+        if written > max_bytes {
+            return Err(format!(
+                "media too large: body exceeds limit of {} bytes",
+                max_bytes
+            ));
+        }
+        if head.len() < 512 {
+            head.extend_from_slice(&buf[..read.min(512 - head.len())]);
+        }
+        file.write_all(&buf[..read])
+            .map_err(|err| err.to_string())?;
+    }
+    file.sync_all().map_err(|err| err.to_string())?;
+    Ok((written, head))
+}
+
+struct FetchedMedia {
+    status: u16,
+    content_type: Option<String>,
+    final_url: String,
+    body: Vec<u8>,
+    cache: &'static str,
+    normalized: bool,
+}
+
+impl FetchedMedia {
+    fn from_cache(cached: media_cache::CachedMedia, status: u16, cache: &'static str) -> Self {
+        Self {
+            status,
+            content_type: cached.meta.content_type,
+            final_url: cached.meta.final_url.unwrap_or(cached.meta.url),
+            body: cached.body,
+            cache,
+            normalized: false,
+        }
+    }
+
+    fn normalize(&mut self, options: &NormalizeOptions) -> Result<(), String> {
+        let image = image_normalize::normalize(&self.body, options)?;
+        self.body = image.body;
+        self.content_type = Some("image/jpeg".to_string());
+        self.normalized = true;
+        Ok(())
+    }
+}
+
+/// Fetches `request.url`, revalidating against and filling the disk cache when one is
+/// configured. A cached copy is served on `304` and when the network or origin is unavailable.
+fn fetch(client: &Client, request: &MediaFetchRequest) -> Result<FetchedMedia, String> {
+    let use_cache = request.cache.unwrap_or(true) && media_cache::is_enabled();
+    let cached = if use_cache {
+        media_cache::lookup(&request.url)
+    } else {
+        None
+    };
+
+    let mut headers = request_headers(request)?;
+    if let Some(cached) = cached.as_ref() {
+        if let Some(etag) = cached.meta.etag.as_deref()
+            && let Ok(value) = HeaderValue::from_str(etag)
+        {
+            headers.insert(IF_NONE_MATCH, value);
+        }
+        if let Some(modified) = cached.meta.last_modified.as_deref()
+            && let Ok(value) = HeaderValue::from_str(modified)
+        {
+            headers.insert(IF_MODIFIED_SINCE, value);
+        }
+    }
+
+    let response = match client
+        .get(&request.url)
+        .headers(headers)
+        .timeout(request_timeout(request))
+        .send_traced()
+    {
+        Ok(response) => response,
+        Err(err) => {
+            return match cached {
+                Some(cached) => Ok(FetchedMedia::from_cache(cached, 200, "stale")),
+                None => Err(err.to_string()),
+            };
+        }
+    };
+    if let Some(cached) = cached {
+        if response.status() == StatusCode::NOT_MODIFIED {
+            media_cache::touch(&request.url);
+            return Ok(FetchedMedia::from_cache(cached, 304, "revalidated"));
+        }
+        if response.status().is_server_error() {
+            return Ok(FetchedMedia::from_cache(cached, 200, "stale"));
+        }
+    }
+    let response = response.error_for_status().map_err(|err| err.to_string())?;
+
+    let status = response.status().as_u16();
+    let final_url = response.url().to_string();
+    let header_text = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    let declared_type = header_text(CONTENT_TYPE);
+    let etag = header_text(ETAG);
+    let last_modified = header_text(LAST_MODIFIED);
+    if let Some(length) = response.content_length()
+        && length > request.max_bytes
+    {
+        return Err(format!(
+            "media too large: {} bytes exceeds limit of {}",
+            length, request.max_bytes
+        ));
+    }
+
+    let mut body = Vec::new();
+    response
+        .take(request.max_bytes.saturating_add(1))
+        .read_to_end(&mut body)
+        .map_err(|err| err.to_string())?;
+    if body.len() as u64 > request.max_bytes {
+        return Err(format!(
+            "media too large: body exceeds limit of {} bytes",
+            request.max_bytes
+        ));
+    }
+
+    let content_type = declared_type
+        .filter(|value| !value.is_empty() && !is_generic_type(value))
+        .or_else(|| sniff_content_type(&body).map(str::to_string));
+    if use_cache {
+        // A cache write failure should not fail an otherwise successful fetch.
+        let _ = media_cache::store(
+            media_cache::EntryMeta {
+                url: request.url.clone(),
+                final_url: Some(final_url.clone()),
+                etag,
+                last_modified,
+                content_type: content_type.clone(),
+                size: 0,
+                stored_at: 0,
+                last_access: 0,
+            },
+            &body,
+        );
+    }
+    Ok(FetchedMedia {
+        status,
+        content_type,
+        final_url,
+        body,
+        cache: if use_cache { "miss" } else { "bypass" },
+        normalized: false,
+    })
+}
+
+fn build_client(timeout_ms: u64) -> Result<Client, String> {
+    config::client_builder(timeout_ms)?
+        .build()
+        .map_err(|err| err.to_string())
+}
+
+fn request_timeout(request: &MediaFetchRequest) -> Duration {
+    Duration::from_millis(request.timeout_ms.max(1))
+}
+
+fn request_headers(request: &MediaFetchRequest) -> Result<HeaderMap, String> {
+    let identity = profiles::resolve(None, request.profile.as_deref())?;
+    let mut headers = identity.headers(request.user_agent.as_deref())?;
+    if let Some(referer) = request.referer.as_deref().filter(|s| !s.is_empty()) {
+        headers.insert(
+            REFERER,
+            HeaderValue::from_str(referer).map_err(|err| err.to_string())?,
+        );
+    }
+    for (key, value) in &request.headers {
+        let name = HeaderName::from_bytes(key.as_bytes()).map_err(|err| err.to_string())?;
+        let val = HeaderValue::from_str(value).map_err(|err| err.to_string())?;
+        headers.insert(name, val);
+    }
+    Ok(headers)
+}
+
+fn is_generic_type(value: &str) -> bool {
+    let essence = value.split(';').next().unwrap_or(value).trim();
+    essence.eq_ignore_ascii_case("application/octet-stream")
+        || essence.eq_ignore_ascii_case("binary/octet-stream")
+}
+
+/// Identifies common image formats from their leading magic bytes.
+fn sniff_content_type(body: &[u8]) -> Option<&'static str> {
+    let brand = body.get(4..12);
+    if body.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if body.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if body.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else if body.starts_with(b"RIFF") && body.get(8..12) == Some(b"WEBP") {
+        Some("image/webp")
+    } else if body.starts_with(b"BM") {
+        Some("image/bmp")
+    } else if body.starts_with(&[0x00, 0x00, 0x01, 0x00]) {
+        Some("image/x-icon")
+    } else if brand == Some(b"ftypavif") {
+        Some("image/avif")
+    } else if brand == Some(b"ftypheic") {
+        Some("image/heic")
+    } else if looks_like_svg(body) {
+        Some("image/svg+xml")
+    } else {
+        None
+    }
+}
+
+fn looks_like_svg(body: &[u8]) -> bool {
+    let head = &body[..body.len().min(512)];
+    let text = String::from_utf8_lossy(head);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    text.starts_with("<svg") || (text.starts_with("<?xml") && text.contains("<svg"))
+}
+
+pub(crate) fn fetch_response_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": {
+            "status": { "type": "integer" },
+            "content_type": { "type": ["string", "null"] },
+            "size": { "type": "integer", "minimum": 0 },
+            "final_url": { "type": "string" },
+            "cache": { "enum": ["miss", "bypass", "revalidated", "stale"] },
+            "normalized": { "type": "boolean" },
+            "body_b64": { "type": "string", "contentEncoding": "base64" }
+        },
+        "required": ["status", "size", "final_url", "cache", "normalized", "body_b64"]
+    })
+}
+
+pub(crate) fn fetch_many_response_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": {
+            "items": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "url": { "type": "string" },
+                        "ok": { "type": "boolean" },
+                        "error": { "type": "string" },
+                        "status": { "type": "integer" },
+                        "content_type": { "type": ["string", "null"] },
+                        "bytes": { "type": "integer", "minimum": 0 },
+                        "final_url": { "type": "string" },
+                        "cache": { "enum": ["miss", "bypass", "revalidated", "stale"] },
+                        "normalized": { "type": "boolean" },
+                        "path": { "type": "string" },
+                        "body_b64": { "type": "string", "contentEncoding": "base64" }
+                    },
+                    "required": ["url", "ok"]
+                }
+            },
+            "succeeded": { "type": "integer", "minimum": 0 },
+            "failed": { "type": "integer", "minimum": 0 }
+        },
+        "required": ["items", "succeeded", "failed"]
+    })
 }
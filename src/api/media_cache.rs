@@ -0,0 +1,404 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use schemars::{JsonSchema, Schema, json_schema};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::api::NoPayload;
+use crate::validate::{Validate, Validator};
+
+const DEFAULT_MAX_BYTES: u64 = 512 * 1024 * 1024;
+/// How stale an entry's persisted `last_access` may get before a hit writes it back.
+const ACCESS_PERSIST_MS: u64 = 10 * 60 * 1000;
+
+static CACHE: Lazy<Mutex<Option<MediaCache>>> = Lazy::new(|| Mutex::new(None));
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The cache's in-memory index: the size and recency of every entry, read from disk once when the
+/// cache is configured. File I/O happens outside the lock guarding it.
+struct MediaCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    index: HashMap<String, IndexEntry>,
+    /// `(last_access, key)` for every indexed entry, least recently used first.
+    by_access: BTreeSet<(u64, String)>,
+    bytes: u64,
+}
+
+struct IndexEntry {
+    size: u64,
+    last_access: u64,
+    /// The `last_access` last written to the entry's meta file.
+    persisted_access: u64,
+}
+
+/// Sidecar metadata stored next to each cached body.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct EntryMeta {
+    pub url: String,
+    #[serde(default)]
+    pub final_url: Option<String>,
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    #[serde(default)]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub stored_at: u64,
+    #[serde(default)]
+    pub last_access: u64,
+}
+
+pub(crate) struct CachedMedia {
+    pub meta: EntryMeta,
+    pub body: Vec<u8>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct ConfigureRequest {
+    dir: String,
+    #[serde(default)]
+    max_bytes: Option<u64>,
+}
+
+#[derive(Deserialize, JsonSchema, Default)]
+pub(crate) struct PurgeRequest {
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    older_than_ms: Option<u64>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn key_for(url: &str) -> String {
+    Sha256::digest(url.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn paths(dir: &Path, key: &str) -> (PathBuf, PathBuf) {
+    let shard = dir.join(&key[..2]);
+    (
+        shard.join(format!("{}.bin", key)),
+        shard.join(format!("{}.json", key)),
+    )
+}
+
+fn read_meta(path: &Path) -> Option<EntryMeta> {
+    let data = fs::read(path).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+fn write_meta(path: &Path, meta: &EntryMeta) -> Result<(), String> {
+    let data = serde_json::to_vec(meta).map_err(|err| err.to_string())?;
+    write_atomically(path, &data)
+}
+
+/// Writes through a uniquely named temporary file, so concurrent stores of one URL never
+/// interleave and readers never see a half written file.
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), String> {
+    let tmp = path.with_extension(format!(
+        "{}.tmp",
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&tmp, data).map_err(|err| err.to_string())?;
+    fs::rename(&tmp, path).map_err(|err| {
+        let _ = fs::remove_file(&tmp);
+        err.to_string()
+    })
+}
+
+fn remove_files((body_path, meta_path): (PathBuf, PathBuf)) {
+    let _ = fs::remove_file(body_path);
+    let _ = fs::remove_file(meta_path);
+}
+
+impl MediaCache {
+    /// Indexes every `(body, meta)` pair currently under `dir`.
+    fn load(dir: PathBuf, max_bytes: u64) -> Self {
+        let mut cache = MediaCache {
+            dir,
+            max_bytes,
+            index: HashMap::new(),
+            by_access: BTreeSet::new(),
+            bytes: 0,
+        };
+        let Ok(shards) = fs::read_dir(&cache.dir) else {
+            return cache;
+        };
+        for shard in shards.flatten() {
+            let Ok(files) = fs::read_dir(shard.path()) else {
+                continue;
+            };
+            for file in files.flatten() {
+                let meta_path = file.path();
+                if meta_path.extension().and_then(|e| e.to_str()) != Some("json") {
+                    continue;
+                }
+                if let Some(meta) = read_meta(&meta_path) {
+                    cache.insert(key_for(&meta.url), meta.size, meta.last_access);
+                }
+            }
+        }
+        cache
+    }
+
+    fn insert(&mut self, key: String, size: u64, last_access: u64) {
+        self.remove(&key);
+        self.bytes += size;
+        self.by_access.insert((last_access, key.clone()));
+        self.index.insert(
+            key,
+            IndexEntry {
+                size,
+                last_access,
+                persisted_access: last_access,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &str) -> Option<IndexEntry> {
+        let entry = self.index.remove(key)?;
+        self.by_access.remove(&(entry.last_access, key.to_string()));
+        self.bytes = self.bytes.saturating_sub(entry.size);
+        Some(entry)
+    }
+
+    /// Drops least recently used entries from the index until the cache fits into `max_bytes`,
+    /// returning the files to delete.
+    fn evict(&mut self) -> Vec<(PathBuf, PathBuf)> {
+        let mut evicted = Vec::new();
+        while self.bytes > self.max_bytes {
+            let Some((_, key)) = self.by_access.first().cloned() else {
+                break;
+            };
+            self.remove(&key);
+            evicted.push(paths(&self.dir, &key));
+        }
+        evicted
+    }
+}
+
+pub(crate) fn is_enabled() -> bool {
+    CACHE.lock().unwrap().is_some()
+}
+
+/// Returns the cached entry for `url`, if the cache is configured and holds a complete one.
+pub(crate) fn lookup(url: &str) -> Option<CachedMedia> {
+    let key = key_for(url);
+    let dir = {
+        let guard = CACHE.lock().unwrap();
+        let cache = guard.as_ref()?;
+        if !cache.index.contains_key(&key) {
+            return None;
+        }
+        cache.dir.clone()
+    };
+    let (body_path, meta_path) = paths(&dir, &key);
+    let cached = read_meta(&meta_path)
+        .filter(|meta| meta.url == url)
+        .and_then(|meta| {
+            let body = fs::read(&body_path).ok()?;
+            (body.len() as u64 == meta.size).then_some(CachedMedia { meta, body })
+        });
+    if cached.is_none() {
+        // The files went away or were damaged behind the index's back.
+        let mut guard = CACHE.lock().unwrap();
+        if let Some(cache) = guard.as_mut().filter(|cache| cache.dir == dir) {
+            cache.remove(&key);
+        }
+    }
+    cached
+}
+
+/// Marks the entry for `url` as just used so eviction keeps it around. The meta file is only
+/// rewritten once its recorded access time is [`ACCESS_PERSIST_MS`] old.
+pub(crate) fn touch(url: &str) {
+    let key = key_for(url);
+    let now = now_ms();
+    let meta_path = {
+        let mut guard = CACHE.lock().unwrap();
+        let Some(cache) = guard.as_mut() else {
+            return;
+        };
+        let Some(entry) = cache.index.get_mut(&key) else {
+            return;
+        };
+        cache.by_access.remove(&(entry.last_access, key.clone()));
+        entry.last_access = now;
+        cache.by_access.insert((now, key.clone()));
+        if now.saturating_sub(entry.persisted_access) < ACCESS_PERSIST_MS {
+            return;
+        }
+        entry.persisted_access = now;
+        paths(&cache.dir, &key).1
+    };
+    if let Some(mut meta) = read_meta(&meta_path) {
+        meta.last_access = now;
+        let _ = write_meta(&meta_path, &meta);
+    }
+}
+
+/// Writes `body` for `meta.url` and evicts older entries if the size budget is exceeded.
+pub(crate) fn store(mut meta: EntryMeta, body: &[u8]) -> Result<(), String> {
+    let dir = {
+        let guard = CACHE.lock().unwrap();
+        let Some(cache) = guard.as_ref() else {
+            return Ok(());
+        };
+        if body.len() as u64 > cache.max_bytes {
+            return Ok(());
+        }
+        cache.dir.clone()
+    };
+    let key = key_for(&meta.url);
+    let (body_path, meta_path) = paths(&dir, &key);
+    if let Some(parent) = body_path.parent() {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let now = now_ms();
+    meta.size = body.len() as u64;
+    meta.stored_at = now;
+    meta.last_access = now;
+
+    // Write the body first so a meta file never points at a half written body.
+    write_atomically(&body_path, body)?;
+    write_meta(&meta_path, &meta)?;
+
+    let evicted = {
+        let mut guard = CACHE.lock().unwrap();
+        // The cache may have been reconfigured to another directory meanwhile.
+        let Some(cache) = guard.as_mut().filter(|cache| cache.dir == dir) else {
+            return Ok(());
+        };
+        cache.insert(key, meta.size, now);
+        cache.evict()
+    };
+    evicted.into_iter().for_each(remove_files);
+    Ok(())
+}
+
+impl Validate for ConfigureRequest {
+    fn validate(&self, validator: &mut Validator) {
+        if let Some(max_bytes) = self.max_bytes {
+            validator.at_least("max_bytes", max_bytes, 1);
+        }
+    }
+}
+
+impl Validate for PurgeRequest {
+    fn validate(&self, validator: &mut Validator) {
+        if let Some(url) = self.url.as_deref() {
+            validator.non_empty("url", url);
+        }
+    }
+}
+
+pub fn handle_cache_configure(request: ConfigureRequest) -> Result<Value, String> {
+    if request.dir.is_empty() {
+        *CACHE.lock().unwrap() = None;
+        return Ok(json!({ "enabled": false }));
+    }
+    let dir = PathBuf::from(&request.dir);
+    fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+    let mut cache = MediaCache::load(dir, request.max_bytes.unwrap_or(DEFAULT_MAX_BYTES));
+    cache.evict().into_iter().for_each(remove_files);
+    *CACHE.lock().unwrap() = Some(cache);
+    cache_stats()
+}
+
+pub fn handle_cache_stats(_request: NoPayload) -> Result<Value, String> {
+    cache_stats()
+}
+
+pub fn handle_cache_purge(request: PurgeRequest) -> Result<Value, String> {
+    purge(request)
+}
+
+pub(crate) fn cache_stats() -> Result<Value, String> {
+    let guard = CACHE.lock().unwrap();
+    let Some(cache) = guard.as_ref() else {
+        return Ok(json!({ "enabled": false }));
+    };
+    Ok(json!({
+        "enabled": true,
+        "dir": cache.dir.to_string_lossy(),
+        "entries": cache.index.len(),
+        "bytes": cache.bytes,
+        "max_bytes": cache.max_bytes,
+    }))
+}
+
+/// Removes one URL, entries older than a cut-off, or (with no filter) everything.
+pub(crate) fn purge(request: PurgeRequest) -> Result<Value, String> {
+    let removed: Vec<(PathBuf, PathBuf)> = {
+        let mut guard = CACHE.lock().unwrap();
+        let Some(cache) = guard.as_mut() else {
+            return Err("media cache is not configured".to_string());
+        };
+        let keys: Vec<String> = match request.url.as_deref() {
+            Some(url) => vec![key_for(url)],
+            None => {
+                let cutoff = request
+                    .older_than_ms
+                    .map(|age| now_ms().saturating_sub(age));
+                cache
+                    .by_access
+                    .iter()
+                    .take_while(|(last_access, _)| {
+                        cutoff.is_none_or(|cutoff| *last_access < cutoff)
+                    })
+                    .map(|(_, key)| key.clone())
+                    .collect()
+            }
+        };
+        keys.into_iter()
+            .filter_map(|key| {
+                cache.remove(&key)?;
+                Some(paths(&cache.dir, &key))
+            })
+            .collect()
+    };
+    let count = removed.len();
+    removed.into_iter().for_each(remove_files);
+    Ok(json!({ "removed": count }))
+}
+
+pub(crate) fn cache_stats_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": {
+            "enabled": { "type": "boolean" },
+            "dir": { "type": "string" },
+            "entries": { "type": "integer", "minimum": 0 },
+            "bytes": { "type": "integer", "minimum": 0 },
+            "max_bytes": { "type": "integer", "minimum": 0 }
+        },
+        "required": ["enabled"]
+    })
+}
+
+pub(crate) fn purge_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": { "removed": { "type": "integer", "minimum": 0 } },
+        "required": ["removed"]
+    })
+}
@@ -1,34 +1,208 @@
-mod iid;
+mod content_disposition;
+pub(crate) mod describe;
 mod directory;
+mod iid;
+mod iid_pool;
+mod image_normalize;
 mod media;
+pub(crate) mod media_cache;
+pub(crate) mod profiles;
+mod release;
 mod reviews;
 mod search;
+pub(crate) mod session_store;
 mod signed_session;
+mod update;
 mod version;
 
+use schemars::{JsonSchema, Schema, json_schema};
+use serde::Deserialize;
 use serde_json::Value;
 
-use crate::api::iid::{handle_activate, handle_register};
-use crate::api::directory::handle_directory_detail;
-use crate::api::media::handle_media_fetch;
-use crate::api::reviews::{handle_comment_list, handle_comment_stats};
-use crate::api::search::handle_search_books;
-use crate::api::signed_session::{handle_batch_full, handle_batch_request, handle_register_key};
-use crate::api::version::handle_version_fetch_filename;
-
-pub fn handle_call(op: &str, payload: &[u8]) -> Result<Value, String> {
-    match op {
-        "iid_register" => handle_register(payload),
-        "iid_activate" => handle_activate(payload),
-        "book_directory_detail" => handle_directory_detail(payload),
-        "review_comment_stats" => handle_comment_stats(payload),
-        "review_comment_list" => handle_comment_list(payload),
-        "media_fetch" => handle_media_fetch(payload),
-        "signed_session_register_key" => handle_register_key(payload),
-        "signed_session_batch_full" => handle_batch_full(payload),
-        "signed_session_batch_request" => handle_batch_request(payload),
-        "version_fetch_filename" => handle_version_fetch_filename(payload),
-        "search_books" => handle_search_books(payload),
-        _ => Err(format!("unknown core operation: {}", op)),
-    }
+use crate::error::CoreError;
+use crate::ops::{CoreOperation, OperationMetadata, Registry};
+use crate::validate::{Validate, Validator};
+
+/// Payload type for ops that take no arguments; an empty payload or `{}` both match.
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct NoPayload {}
+
+impl Validate for NoPayload {
+    fn validate(&self, _validator: &mut Validator) {}
+}
+
+/// Response schema for ops that hand back the upstream JSON body unchanged.
+fn upstream_response() -> Schema {
+    json_schema!({ "description": "Upstream JSON response, passed through unchanged" })
+}
+
+/// Declares a unit struct per built-in op and registers it in [`register_builtin`].
+macro_rules! builtin_ops {
+    ($(
+        $ty:ident => $name:literal, $handler:path, $payload:ty, $response:expr,
+        $category:literal, $network:literal, $summary:literal;
+    )*) => {
+        $(
+            struct $ty;
+
+            impl CoreOperation for $ty {
+                type Payload = $payload;
+
+                fn name(&self) -> &str {
+                    $name
+                }
+
+                fn metadata(&self) -> OperationMetadata {
+                    OperationMetadata {
+                        summary: $summary.to_string(),
+                        category: $category.to_string(),
+                        network: $network,
+                    }
+                }
+
+                fn response_schema(&self) -> Schema {
+                    $response
+                }
+
+                fn validate(&self, payload: &Self::Payload, validator: &mut Validator) {
+                    payload.validate(validator);
+                }
+
+                fn handle(&self, payload: Self::Payload) -> Result<Value, String> {
+                    $handler(payload)
+                }
+            }
+        )*
+
+        pub(crate) fn register_builtin(registry: &mut Registry) {
+            $(registry.add($ty);)*
+        }
+    };
+}
+
+builtin_ops! {
+    IidRegister => "iid_register",
+        iid::handle_register, iid::RegisterRequest,
+        upstream_response(),
+        "iid", true, "Register a new install ID with the device register endpoint";
+    IidActivate => "iid_activate",
+        iid::handle_activate, iid::ActivateRequest,
+        upstream_response(),
+        "iid", true, "Activate a registered install ID";
+    IidPoolConfigure => "iid_pool_configure",
+        iid_pool::handle_pool_configure, iid_pool::ConfigureRequest,
+        iid_pool::pool_status_schema(),
+        "iid", false, "Configure the install ID pool strategy and health limits";
+    IidPoolAdd => "iid_pool_add",
+        iid_pool::handle_pool_add, iid_pool::InstallIdsRequest,
+        iid_pool::pool_change_schema(),
+        "iid", false, "Add install IDs to the pool";
+    IidPoolRemove => "iid_pool_remove",
+        iid_pool::handle_pool_remove, iid_pool::InstallIdsRequest,
+        iid_pool::pool_change_schema(),
+        "iid", false, "Remove install IDs from the pool";
+    IidPoolAcquire => "iid_pool_acquire",
+        iid_pool::handle_pool_acquire, NoPayload,
+        iid_pool::acquire_schema(),
+        "iid", false, "Pick the next healthy install ID";
+    IidPoolReport => "iid_pool_report",
+        iid_pool::handle_pool_report, iid_pool::ReportRequest,
+        iid_pool::pool_status_schema(),
+        "iid", false, "Report the HTTP status seen for an install ID";
+    IidPoolStatus => "iid_pool_status",
+        iid_pool::handle_pool_status, NoPayload,
+        iid_pool::pool_status_schema(),
+        "iid", false, "Show health and usage of every pooled install ID";
+    BookDirectoryDetail => "book_directory_detail",
+        directory::handle_directory_detail, directory::DirectoryDetailRequest,
+        upstream_response(),
+        "book", true, "Fetch the chapter directory of a book";
+    ReviewCommentStats => "review_comment_stats",
+        reviews::handle_comment_stats, reviews::CommentStatsRequest,
+        upstream_response(),
+        "review", true, "Fetch comment statistics for a chapter";
+    ReviewCommentList => "review_comment_list",
+        reviews::handle_comment_list, reviews::CommentListRequest,
+        upstream_response(),
+        "review", true, "Fetch the comment list for a chapter";
+    MediaFetch => "media_fetch",
+        media::handle_media_fetch, media::MediaFetchRequest,
+        media::fetch_response_schema(),
+        "media", true, "Download a single media file into memory";
+    MediaFetchMany => "media_fetch_many",
+        media::handle_media_fetch_many, media::MediaFetchManyRequest,
+        media::fetch_many_response_schema(),
+        "media", true, "Download many media files to disk concurrently";
+    MediaNormalizeImage => "media_normalize_image",
+        image_normalize::handle_normalize_image, image_normalize::NormalizeRequest,
+        image_normalize::normalize_response_schema(),
+        "media", false, "Re-encode an image as a baseline JPEG";
+    MediaCacheConfigure => "media_cache_configure",
+        media_cache::handle_cache_configure, media_cache::ConfigureRequest,
+        media_cache::cache_stats_schema(),
+        "media", false, "Enable, move or disable the media cache";
+    MediaCacheStats => "media_cache_stats",
+        media_cache::handle_cache_stats, NoPayload,
+        media_cache::cache_stats_schema(),
+        "media", false, "Report media cache size and entry count";
+    MediaCachePurge => "media_cache_purge",
+        media_cache::handle_cache_purge, media_cache::PurgeRequest,
+        media_cache::purge_schema(),
+        "media", false, "Remove entries from the media cache";
+    SignedSessionRegisterKey => "signed_session_register_key",
+        signed_session::handle_register_key, signed_session::RegisterKeyRequest,
+        upstream_response(),
+        "signed_session", true, "Register a key for signed chapter requests";
+    SignedSessionBatchFull => "signed_session_batch_full",
+        signed_session::handle_batch_full, signed_session::BatchFullRequest,
+        upstream_response(),
+        "signed_session", true, "Fetch a chapter batch with a prepared signed query";
+    SignedSessionBatchRequest => "signed_session_batch_request",
+        signed_session::handle_batch_request, signed_session::BatchRequestPayload,
+        signed_session::batch_request_response_schema(),
+        "signed_session", true, "Fetch raw chapter bodies one by one";
+    VersionFetchFilename => "version_fetch_filename",
+        version::handle_version_fetch_filename, version::VersionRequest,
+        version::version_response_schema(),
+        "update", true, "Resolve the file name served by a download URL";
+    UpdateCheck => "update_check",
+        update::handle_update_check, update::UpdateCheckRequest,
+        update::update_check_response_schema(),
+        "update", true, "Check whether a newer release is available";
+    DownloadRelease => "download_release",
+        release::handle_download_release, release::DownloadReleaseRequest,
+        release::download_release_response_schema(),
+        "update", true, "Download and verify a release asset";
+    SearchBooks => "search_books",
+        search::handle_search_books, search::SearchRequest,
+        upstream_response(),
+        "book", true, "Search the catalogue";
+    SessionStoreGet => "session_store_get",
+        session_store::handle_session_get, session_store::GetRequest,
+        session_store::session_get_schema(),
+        "session", false, "Show the loaded session state, cookie values masked unless revealed";
+    SessionStorePut => "session_store_put",
+        session_store::handle_session_put, session_store::PutRequest,
+        session_store::session_put_schema(),
+        "session", false, "Update signed keys and cookies in the session state";
+    ProfileList => "profile_list",
+        profiles::handle_profile_list, NoPayload,
+        profiles::profile_list_schema(),
+        "profile", false, "List device profiles and install ID bindings";
+    ProfileBind => "profile_bind",
+        profiles::handle_profile_bind, profiles::BindRequest,
+        profiles::binding_schema(),
+        "profile", false, "Bind an install ID to a device profile";
+    ProfileUnbind => "profile_unbind",
+        profiles::handle_profile_unbind, profiles::UnbindRequest,
+        profiles::binding_schema(),
+        "profile", false, "Remove the device profile binding of an install ID";
+    DescribeOp => "describe_op",
+        describe::handle_describe_op, describe::DescribeRequest,
+        describe::describe_response_schema(),
+        "core", false, "Describe registered operations and their JSON Schemas";
+}
+
+pub fn handle_call(op: &str, payload: &[u8]) -> Result<Value, CoreError> {
+    crate::ops::call_operation(op, payload)
 }
@@ -0,0 +1,205 @@
+//! Device profiles bound to install IDs.
+//!
+//! An install ID is bound to one profile the first time it is used (or when it is registered), and
+//! every later request made with it presents the same user agent, Accept-Language, platform
+//! headers and aid. Requests without an install ID use the configured default profile.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use reqwest::header::{ACCEPT_LANGUAGE, HeaderMap, HeaderName, HeaderValue, USER_AGENT};
+use schemars::{JsonSchema, Schema, json_schema};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use zeroize::Zeroize;
+
+use crate::api::NoPayload;
+use crate::config::{self, DeviceProfile};
+use crate::dry_run;
+use crate::validate::{Validate, Validator};
+
+/// Install ID to profile name.
+static BINDINGS: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The profile a single request is made with.
+pub(crate) struct Identity {
+    pub(crate) name: String,
+    profile: DeviceProfile,
+    aid: String,
+}
+
+impl Identity {
+    /// App ID for the request: an explicit payload value wins over the profile's, which wins over
+    /// the configured one.
+    pub(crate) fn aid(&self, explicit: Option<&str>) -> String {
+        explicit
+            .filter(|aid| !aid.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| self.aid.clone())
+    }
+
+    /// Identity headers for the request. `user_agent` overrides the profile's user agent only.
+    pub(crate) fn headers(&self, user_agent: Option<&str>) -> Result<HeaderMap, String> {
+        let mut headers = HeaderMap::new();
+        let user_agent = user_agent
+            .filter(|ua| !ua.is_empty())
+            .unwrap_or(&self.profile.user_agent);
+        headers.insert(
+            USER_AGENT,
+            HeaderValue::from_str(user_agent).map_err(|err| err.to_string())?,
+        );
+        if !self.profile.accept_language.is_empty() {
+            headers.insert(
+                ACCEPT_LANGUAGE,
+                HeaderValue::from_str(&self.profile.accept_language)
+                    .map_err(|err| err.to_string())?,
+            );
+        }
+        for (key, value) in &self.profile.headers {
+            let name = HeaderName::from_bytes(key.as_bytes()).map_err(|err| err.to_string())?;
+            let val = HeaderValue::from_str(value).map_err(|err| err.to_string())?;
+            headers.insert(name, val);
+        }
+        Ok(headers)
+    }
+}
+
+/// Resolves the profile for a request.
+///
+/// An explicit `profile` must match the install ID's binding, if there is one; an unbound install
+/// ID is bound to the explicit profile or, failing that, the default profile. A dry run resolves
+/// the same profile without binding it.
+pub(crate) fn resolve(install_id: Option<&str>, profile: Option<&str>) -> Result<Identity, String> {
+    let config = config::current();
+    let install_id = install_id.filter(|id| !id.is_empty());
+    let profile = profile.filter(|name| !name.is_empty());
+    let name = match install_id {
+        Some(install_id) => {
+            let mut bindings = BINDINGS.lock().unwrap();
+            match (bindings.get(install_id), profile) {
+                (Some(bound), Some(requested)) if bound != requested => {
+                    return Err(format!(
+                        "install ID {} is bound to profile {}, not {}",
+                        install_id, bound, requested
+                    ));
+                }
+                (Some(bound), _) => bound.clone(),
+                (None, requested) => {
+                    let name = requested.unwrap_or(&config.default_profile).to_string();
+                    config.profile(&name)?;
+                    if !dry_run::active() {
+                        bindings.insert(install_id.to_string(), name.clone());
+                    }
+                    name
+                }
+            }
+        }
+        None => profile.unwrap_or(&config.default_profile).to_string(),
+    };
+    let profile = config.profile(&name)?.clone();
+    let aid = profile.aid.clone().unwrap_or_else(|| config.aid.clone());
+    Ok(Identity { name, profile, aid })
+}
+
+/// Binds `install_id` to `profile`, replacing any previous binding.
+pub(crate) fn bind(install_id: &str, profile: &str) -> Result<(), String> {
+    config::current().profile(profile)?;
+    BINDINGS
+        .lock()
+        .unwrap()
+        .insert(install_id.to_string(), profile.to_string());
+    Ok(())
+}
+
+/// Current bindings, for persisting in the session store.
+pub(crate) fn bindings() -> HashMap<String, String> {
+    BINDINGS.lock().unwrap().clone()
+}
+
+/// Drops every binding, when the session state is cleared.
+pub(crate) fn clear() {
+    for (mut install_id, _) in BINDINGS.lock().unwrap().drain() {
+        install_id.zeroize();
+    }
+}
+
+/// Restores bindings loaded from the session store; existing bindings are kept.
+pub(crate) fn restore(bindings: &HashMap<String, String>) {
+    let mut current = BINDINGS.lock().unwrap();
+    for (install_id, profile) in bindings {
+        current
+            .entry(install_id.clone())
+            .or_insert_with(|| profile.clone());
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct BindRequest {
+    install_id: String,
+    profile: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct UnbindRequest {
+    install_id: String,
+}
+
+impl Validate for BindRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .numeric("install_id", &self.install_id)
+            .non_empty("profile", &self.profile);
+        if !self.profile.is_empty() && config::current().profile(&self.profile).is_err() {
+            validator.push("profile", "unknown device profile");
+        }
+    }
+}
+
+impl Validate for UnbindRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator.numeric("install_id", &self.install_id);
+    }
+}
+
+pub fn handle_profile_list(_request: NoPayload) -> Result<Value, String> {
+    let config = config::current();
+    Ok(json!({
+        "default_profile": config.default_profile,
+        "profiles": config.profiles,
+        "bindings": bindings(),
+    }))
+}
+
+pub fn handle_profile_bind(request: BindRequest) -> Result<Value, String> {
+    bind(&request.install_id, &request.profile)?;
+    Ok(json!({ "install_id": request.install_id, "profile": request.profile }))
+}
+
+pub fn handle_profile_unbind(request: UnbindRequest) -> Result<Value, String> {
+    let removed = BINDINGS.lock().unwrap().remove(&request.install_id);
+    Ok(json!({ "install_id": request.install_id, "profile": removed }))
+}
+
+pub(crate) fn profile_list_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": {
+            "default_profile": { "type": "string" },
+            "profiles": { "type": "object" },
+            "bindings": { "type": "object", "additionalProperties": { "type": "string" } }
+        },
+        "required": ["default_profile", "profiles", "bindings"]
+    })
+}
+
+pub(crate) fn binding_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": {
+            "install_id": { "type": "string" },
+            "profile": { "type": ["string", "null"] }
+        },
+        "required": ["install_id", "profile"]
+    })
+}
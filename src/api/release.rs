@@ -0,0 +1,550 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STD;
+use ed25519_dalek::{Signature, VerifyingKey};
+use reqwest::blocking::Client;
+use reqwest::header::{CONTENT_LENGTH, CONTENT_RANGE, RANGE, USER_AGENT};
+use reqwest::{StatusCode, Url};
+use schemars::{JsonSchema, Schema, json_schema};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::api::version::byte_range;
+use crate::config::{self, CoreConfig, SignatureKind};
+use crate::http::TracedSend;
+use crate::progress;
+use crate::validate::{Validate, Validator};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct DownloadReleaseRequest {
+    url: String,
+    dest_path: String,
+    #[serde(default)]
+    sha256: Option<String>,
+    #[serde(default)]
+    checksum_url: Option<String>,
+    /// Defaults to the asset URL with `.minisig` or `.sig` appended, per `signature_kind`.
+    #[serde(default)]
+    signature_url: Option<String>,
+    /// Defaults to `release_signing.signature_kind` from the configuration.
+    #[serde(default)]
+    signature_kind: Option<SignatureKind>,
+    /// Defaults to the pinned `release_signing.public_key` from the configuration.
+    #[serde(default)]
+    public_key: Option<String>,
+    #[serde(default)]
+    user_agent: Option<String>,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    #[serde(default)]
+    progress_id: Option<String>,
+}
+
+impl Validate for DownloadReleaseRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .url("url", &self.url)
+            .non_empty("dest_path", &self.dest_path)
+            .optional_url("checksum_url", self.checksum_url.as_deref())
+            .optional_url("signature_url", self.signature_url.as_deref());
+        match self.sha256.as_deref() {
+            Some(hash) if !is_sha256_hex(hash.trim()) => {
+                validator.push("sha256", "must be 64 hex characters");
+            }
+            Some(_) => {}
+            None if self.checksum_url.is_none() => {
+                validator.push("sha256", "either sha256 or checksum_url is required");
+            }
+            None => {}
+        }
+        let config = config::current();
+        let public_key = self
+            .public_key
+            .as_deref()
+            .unwrap_or(&config.release_signing.public_key);
+        if public_key.trim().is_empty() {
+            validator.push(
+                "public_key",
+                "required unless release_signing.public_key is configured",
+            );
+        }
+        if let Some(timeout_ms) = self.timeout_ms {
+            validator.at_least("timeout_ms", timeout_ms, 1);
+        }
+    }
+}
+
+pub fn handle_download_release(request: DownloadReleaseRequest) -> Result<Value, String> {
+    let config = config::current();
+    let builder = Client::builder()
+        .connect_timeout(Duration::from_millis(config.timeouts.release_connect_ms))
+        .user_agent(
+            request
+                .user_agent
+                .as_deref()
+                .unwrap_or(&config.user_agents.updater),
+        );
+    let mut builder = config::apply_proxy(builder, &config)?;
+    // The overall timeout applies to the whole transfer, so it is only set when asked for.
+    if let Some(ms) = request.timeout_ms {
+        builder = builder.timeout(Duration::from_millis(ms));
+    }
+    let client = builder.build().map_err(|err| err.to_string())?;
+    let signing = signing(&request, &config);
+
+    let dest = PathBuf::from(&request.dest_path);
+    let part = partial_path(&dest);
+    let (bytes, resumed_from) = download(&client, &request, &part)?;
+    if let Err(err) = verify(&client, &request, &signing, &part) {
+        let _ = fs::remove_file(&part);
+        return Err(err);
+    }
+    fs::rename(&part, &dest).map_err(|err| err.to_string())?;
+
+    Ok(json!({
+        "path": dest.to_string_lossy(),
+        "bytes": bytes,
+        "resumed_from": resumed_from,
+        "sha256_verified": true,
+        "signature_verified": true,
+    }))
+}
+
+/// The key, kind and location of the signature a download is checked against.
+struct Signing {
+    public_key: String,
+    kind: SignatureKind,
+    url: String,
+}
+
+fn signing(request: &DownloadReleaseRequest, config: &CoreConfig) -> Signing {
+    let pinned = &config.release_signing;
+    let kind = request.signature_kind.unwrap_or(pinned.signature_kind);
+    let url = request
+        .signature_url
+        .clone()
+        .unwrap_or_else(|| signature_url(&request.url, kind));
+    Signing {
+        public_key: request
+            .public_key
+            .clone()
+            .unwrap_or_else(|| pinned.public_key.clone()),
+        kind,
+        url,
+    }
+}
+
+/// `asset_url` with the signature file extension appended to its path.
+fn signature_url(asset_url: &str, kind: SignatureKind) -> String {
+    let suffix = match kind {
+        SignatureKind::Minisign => ".minisig",
+        SignatureKind::Ed25519 | SignatureKind::Ed25519ph => ".sig",
+    };
+    match Url::parse(asset_url) {
+        Ok(mut url) => {
+            let path = format!("{}{}", url.path(), suffix);
+            url.set_path(&path);
+            url.to_string()
+        }
+        Err(_) => format!("{}{}", asset_url, suffix),
+    }
+}
+
+fn partial_path(dest: &Path) -> PathBuf {
+    let mut name = dest.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// Streams the asset into `part`, resuming from its current length when the server honours
+/// `Range`. Returns the final size and the offset the transfer resumed from.
+fn download(
+    client: &Client,
+    request: &DownloadReleaseRequest,
+    part: &Path,
+) -> Result<(u64, u64), String> {
+    let existing = fs::metadata(part).map(|meta| meta.len()).unwrap_or(0);
+    let mut builder = client.get(&request.url);
+    if existing > 0 {
+        builder = builder.header(RANGE, byte_range(existing, None));
+    }
+    let response = builder.send_traced().map_err(|err| err.to_string())?;
+    if existing > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // The partial file already holds the whole asset; verification decides if it is usable.
+        return Ok((existing, existing));
+    }
+    let mut response = response.error_for_status().map_err(|err| err.to_string())?;
+    let resumed = existing > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+    if resumed && !content_range_starts_at(&response, existing) {
+        return Err("server returned an unexpected Content-Range".to_string());
+    }
+    let offset = if resumed { existing } else { 0 };
+    let total = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|text| text.parse::<u64>().ok())
+        .map(|len| len + offset);
+
+    if let Some(parent) = part.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(part)
+        .map_err(|err| err.to_string())?;
+
+    let mut downloaded = offset;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut last_report = Instant::now();
+    report(request, downloaded, total, "downloading");
+    loop {
+        let read = response.read(&mut buf).map_err(|err| err.to_string())?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buf[..read])
+            .map_err(|err| err.to_string())?;
+        downloaded += read as u64;
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            report(request, downloaded, total, "downloading");
+            last_report = Instant::now();
+        }
+    }
+    file.sync_all().map_err(|err| err.to_string())?;
+    report(request, downloaded, total, "downloaded");
+
+    if let Some(total) = total
+        && downloaded != total
+    {
+        return Err(format!(
+            "release download incomplete: {} of {} bytes",
+            downloaded, total
+        ));
+    }
+    Ok((downloaded, offset))
+}
+
+fn content_range_starts_at(response: &reqwest::blocking::Response, offset: u64) -> bool {
+    response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|text| text.strip_prefix("bytes "))
+        .and_then(|range| range.split('-').next())
+        .and_then(|start| start.trim().parse::<u64>().ok())
+        == Some(offset)
+}
+
+fn report(request: &DownloadReleaseRequest, downloaded: u64, total: Option<u64>, stage: &str) {
+    progress::emit(json!({
+        "op": "download_release",
+        "id": request.progress_id,
+        "stage": stage,
+        "downloaded": downloaded,
+        "total": total,
+    }));
+}
+
+/// Checks the SHA-256 digest and the signature of `part` in one pass over the file.
+fn verify(
+    client: &Client,
+    request: &DownloadReleaseRequest,
+    signing: &Signing,
+    part: &Path,
+) -> Result<(), String> {
+    report_stage(request, "verifying");
+    let expected = match request.sha256.as_deref() {
+        Some(hash) => hash.trim().to_ascii_lowercase(),
+        None => {
+            let checksum_url = request.checksum_url.as_deref().unwrap_or_default();
+            let listing = fetch_text(client, checksum_url)?;
+            find_checksum(&listing, &asset_name(&request.url))
+                .ok_or_else(|| "checksum for release asset not found".to_string())?
+        }
+    };
+    let signature = fetch_bytes(client, &signing.url)
+        .map_err(|err| format!("release signature unavailable: {}", err))?;
+    check_signature(signing, &signature, &expected, part)?;
+    report_stage(request, "verified");
+    Ok(())
+}
+
+/// Checks `part` against `signature` and the `expected` SHA-256 digest, reading it once.
+fn check_signature(
+    signing: &Signing,
+    signature: &[u8],
+    expected: &str,
+    part: &Path,
+) -> Result<(), String> {
+    match signing.kind {
+        SignatureKind::Minisign => {
+            let key = minisign_key(&signing.public_key)?;
+            let signature = minisign_signature(signature)?;
+            let mut stream = key
+                .verify_stream(&signature)
+                .map_err(|err| format!("minisign verification failed: {}", err))?;
+            let actual = sha256_file(part, |chunk| stream.update(chunk))?;
+            check_digest(expected, &actual)?;
+            stream
+                .finalize()
+                .map_err(|err| format!("minisign verification failed: {}", err))?;
+        }
+        SignatureKind::Ed25519 => {
+            let key = ed25519_key(&signing.public_key)?;
+            let signature = ed25519_signature(signature)?;
+            let mut stream = key
+                .verify_stream(&signature)
+                .map_err(|err| format!("ed25519 verification failed: {}", err))?;
+            let actual = sha256_file(part, |chunk| stream.update(chunk))?;
+            check_digest(expected, &actual)?;
+            stream
+                .finalize_and_verify()
+                .map_err(|err| format!("ed25519 verification failed: {}", err))?;
+        }
+        SignatureKind::Ed25519ph => {
+            let key = ed25519_key(&signing.public_key)?;
+            let signature = ed25519_signature(signature)?;
+            let mut prehash = Sha512::new();
+            let actual = sha256_file(part, |chunk| prehash.update(chunk))?;
+            check_digest(expected, &actual)?;
+            key.verify_prehashed(prehash, None, &signature)
+                .map_err(|err| format!("ed25519ph verification failed: {}", err))?;
+        }
+    }
+    Ok(())
+}
+
+fn check_digest(expected: &str, actual: &str) -> Result<(), String> {
+    if actual != expected {
+        return Err(format!(
+            "sha256 mismatch: expected {}, got {}",
+            expected, actual
+        ));
+    }
+    Ok(())
+}
+
+fn report_stage(request: &DownloadReleaseRequest, stage: &str) {
+    progress::emit(json!({
+        "op": "download_release",
+        "id": request.progress_id,
+        "stage": stage,
+    }));
+}
+
+fn fetch_bytes(client: &Client, url: &str) -> Result<Vec<u8>, String> {
+    client
+        .get(url)
+        .header(USER_AGENT, config::current().user_agents.updater.as_str())
+        .send_traced()
+        .map_err(|err| err.to_string())?
+        .error_for_status()
+        .map_err(|err| err.to_string())?
+        .bytes()
+        .map(|bytes| bytes.to_vec())
+        .map_err(|err| err.to_string())
+}
+
+fn fetch_text(client: &Client, url: &str) -> Result<String, String> {
+    let bytes = fetch_bytes(client, url)?;
+    String::from_utf8(bytes).map_err(|err| err.to_string())
+}
+
+fn asset_name(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().unwrap_or(path).to_string()
+}
+
+/// Finds the digest for `asset` in a `sha256sum` style listing. A listing holding a single bare
+/// digest is accepted as well.
+fn find_checksum(listing: &str, asset: &str) -> Option<String> {
+    let mut lone = None;
+    for line in listing.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(hash) = fields.next().filter(|h| is_sha256_hex(h)) else {
+            continue;
+        };
+        match fields.next() {
+            Some(name) if name.trim_start_matches('*') == asset => {
+                return Some(hash.to_ascii_lowercase());
+            }
+            Some(_) => {}
+            None => lone = Some(hash.to_ascii_lowercase()),
+        }
+    }
+    lone
+}
+
+fn is_sha256_hex(text: &str) -> bool {
+    text.len() == 64 && text.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Hashes the file at `path`, handing every chunk read to `also` as well.
+fn sha256_file(path: &Path, mut also: impl FnMut(&[u8])) -> Result<String, String> {
+    let mut file = File::open(path).map_err(|err| err.to_string())?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buf).map_err(|err| err.to_string())?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        also(&buf[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+fn minisign_key(public_key: &str) -> Result<minisign_verify::PublicKey, String> {
+    let public_key = public_key.trim();
+    if public_key.contains('\n') {
+        minisign_verify::PublicKey::decode(public_key)
+    } else {
+        minisign_verify::PublicKey::from_base64(public_key)
+    }
+    .map_err(|err| format!("invalid minisign public key: {}", err))
+}
+
+fn minisign_signature(signature: &[u8]) -> Result<minisign_verify::Signature, String> {
+    let text = std::str::from_utf8(signature).map_err(|err| err.to_string())?;
+    minisign_verify::Signature::decode(text)
+        .map_err(|err| format!("invalid minisign signature: {}", err))
+}
+
+fn ed25519_key(public_key: &str) -> Result<VerifyingKey, String> {
+    let key: [u8; 32] = decode_key_material(public_key.as_bytes())?
+        .try_into()
+        .map_err(|_| "ed25519 public key must be 32 bytes".to_string())?;
+    VerifyingKey::from_bytes(&key).map_err(|err| err.to_string())
+}
+
+fn ed25519_signature(signature: &[u8]) -> Result<Signature, String> {
+    let signature: [u8; 64] = match <[u8; 64]>::try_from(signature) {
+        Ok(raw) => raw,
+        Err(_) => decode_key_material(signature)?
+            .try_into()
+            .map_err(|_| "ed25519 signature must be 64 bytes".to_string())?,
+    };
+    Ok(Signature::from_bytes(&signature))
+}
+
+/// Decodes hex or base64 text, ignoring surrounding whitespace.
+fn decode_key_material(raw: &[u8]) -> Result<Vec<u8>, String> {
+    let text = std::str::from_utf8(raw)
+        .map_err(|err| err.to_string())?
+        .trim();
+    if text.len() % 2 == 0 && text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|err| err.to_string()))
+            .collect();
+    }
+    BASE64_STD.decode(text).map_err(|err| err.to_string())
+}
+
+pub(crate) fn download_release_response_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": {
+            "path": { "type": "string" },
+            "bytes": { "type": "integer", "minimum": 0 },
+            "resumed_from": { "type": "integer", "minimum": 0 },
+            "sha256_verified": { "type": "boolean" },
+            "signature_verified": { "type": "boolean" }
+        },
+        "required": ["path", "bytes", "resumed_from", "sha256_verified", "signature_verified"]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::*;
+
+    const ASSET: &[u8] = b"TomatoNovel release asset";
+
+    fn sha256_hex(data: &[u8]) -> String {
+        Sha256::digest(data)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn check(kind: SignatureKind, signature: &[u8], part: &Path) -> Result<(), String> {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let signing = Signing {
+            public_key: BASE64_STD.encode(key.verifying_key().as_bytes()),
+            kind,
+            url: String::new(),
+        };
+        check_signature(&signing, signature, &sha256_hex(ASSET), part)
+    }
+
+    fn asset_file(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("tn-core-release-{}-{}", std::process::id(), name));
+        fs::write(&path, ASSET).unwrap();
+        path
+    }
+
+    #[test]
+    fn ed25519_accepts_plain_signatures_only() {
+        let part = asset_file("plain");
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let plain = key.sign(ASSET).to_bytes();
+        let prehashed = key
+            .sign_prehashed(Sha512::new().chain_update(ASSET), None)
+            .unwrap()
+            .to_bytes();
+
+        assert_eq!(check(SignatureKind::Ed25519, &plain, &part), Ok(()));
+        let hex: String = plain.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(check(SignatureKind::Ed25519, hex.as_bytes(), &part), Ok(()));
+        assert!(check(SignatureKind::Ed25519, &prehashed, &part).is_err());
+
+        let other = SigningKey::from_bytes(&[8; 32]).sign(ASSET).to_bytes();
+        assert!(check(SignatureKind::Ed25519, &other, &part).is_err());
+        let _ = fs::remove_file(part);
+    }
+
+    #[test]
+    fn ed25519ph_accepts_prehashed_signatures_only() {
+        let part = asset_file("prehashed");
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let prehashed = key
+            .sign_prehashed(Sha512::new().chain_update(ASSET), None)
+            .unwrap()
+            .to_bytes();
+
+        assert_eq!(check(SignatureKind::Ed25519ph, &prehashed, &part), Ok(()));
+        assert!(check(SignatureKind::Ed25519ph, &key.sign(ASSET).to_bytes(), &part).is_err());
+        let _ = fs::remove_file(part);
+    }
+
+    #[test]
+    fn digest_mismatch_fails_before_the_signature() {
+        let part = asset_file("mismatch");
+        fs::write(&part, b"tampered").unwrap();
+        let signature = SigningKey::from_bytes(&[7; 32]).sign(ASSET).to_bytes();
+        let err = check(SignatureKind::Ed25519, &signature, &part).unwrap_err();
+        assert!(err.starts_with("sha256 mismatch"), "{}", err);
+        let _ = fs::remove_file(part);
+    }
+}
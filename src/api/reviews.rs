@@ -1,29 +1,41 @@
-use std::time::Duration;
-
 use reqwest::blocking::Client;
-use reqwest::header::{ACCEPT, CONTENT_TYPE, HeaderMap, HeaderValue, USER_AGENT};
+use reqwest::header::{ACCEPT, CONTENT_TYPE, HeaderMap, HeaderValue};
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_json::{Value, json};
 
-const AID_DEFAULT: &str = "1967";
+use crate::api::{iid_pool, profiles};
+use crate::config;
+use crate::http::TracedSend;
+use crate::validate::{Validate, Validator};
+
+const MAX_COMMENT_COUNT: usize = 100;
 
-#[derive(Deserialize)]
-struct CommentStatsRequest {
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct CommentStatsRequest {
+    #[serde(default = "default_stats_url")]
     base_url: String,
     chapter_id: String,
     item_version: String,
-    #[serde(default = "default_aid")]
-    aid: String,
-    install_id: String,
+    #[serde(default)]
+    aid: Option<String>,
+    #[serde(default)]
+    install_id: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
 }
 
-#[derive(Deserialize)]
-struct CommentListRequest {
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct CommentListRequest {
+    #[serde(default = "default_list_url")]
     base_url: String,
     chapter_id: String,
-    #[serde(default = "default_aid")]
-    aid: String,
-    install_id: String,
+    #[serde(default)]
+    aid: Option<String>,
+    #[serde(default)]
+    install_id: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
     business_param: Value,
     comment_source: i32,
     comment_type: i32,
@@ -32,28 +44,52 @@ struct CommentListRequest {
     sort: i32,
 }
 
-fn default_aid() -> String {
-    AID_DEFAULT.to_string()
+fn default_stats_url() -> String {
+    config::current().endpoints.comment_stats.clone()
+}
+
+fn default_list_url() -> String {
+    config::current().endpoints.comment_list.clone()
+}
+
+impl Validate for CommentStatsRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .url("base_url", &self.base_url)
+            .numeric("chapter_id", &self.chapter_id)
+            .non_empty("item_version", &self.item_version)
+            .optional_numeric("aid", self.aid.as_deref())
+            .optional_numeric("install_id", self.install_id.as_deref());
+    }
 }
 
-pub fn handle_comment_stats(payload: &[u8]) -> Result<Value, String> {
-    let request: CommentStatsRequest =
-        serde_json::from_slice(payload).map_err(|err| err.to_string())?;
-    if request.base_url.is_empty() {
-        return Err("comment stats url missing".to_string());
+impl Validate for CommentListRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .url("base_url", &self.base_url)
+            .numeric("chapter_id", &self.chapter_id)
+            .optional_numeric("aid", self.aid.as_deref())
+            .optional_numeric("install_id", self.install_id.as_deref())
+            .range("count", self.count, 1, MAX_COMMENT_COUNT);
     }
-    let client = build_client().map_err(|err| err.to_string())?;
+}
+
+pub fn handle_comment_stats(request: CommentStatsRequest) -> Result<Value, String> {
+    let install_id = iid_pool::resolve_install_id(request.install_id.as_deref())?;
+    let identity = profiles::resolve(Some(&install_id), request.profile.as_deref())?;
+    let aid = identity.aid(request.aid.as_deref());
+    let client = build_client()?;
     let url = format!("{}/{}/v1", request.base_url, request.chapter_id);
     let body = json!({ "item_version": request.item_version });
     let response = client
         .post(url)
-        .query(&[
-            ("aid", request.aid.as_str()),
-            ("iid", request.install_id.as_str()),
-        ])
+        .headers(identity.headers(None)?)
+        .query(&[("aid", aid.as_str()), ("iid", install_id.as_str())])
         .json(&body)
-        .send()
-        .map_err(|err| err.to_string())?
+        .send_traced()
+        .map_err(|err| err.to_string())?;
+    iid_pool::report_status(&install_id, response.status());
+    let response = response
         .error_for_status()
         .map_err(|err| err.to_string())?
         .json::<Value>()
@@ -61,20 +97,16 @@ pub fn handle_comment_stats(payload: &[u8]) -> Result<Value, String> {
     Ok(response)
 }
 
-pub fn handle_comment_list(payload: &[u8]) -> Result<Value, String> {
-    let request: CommentListRequest =
-        serde_json::from_slice(payload).map_err(|err| err.to_string())?;
-    if request.base_url.is_empty() {
-        return Err("comment list url missing".to_string());
-    }
-    let client = build_client().map_err(|err| err.to_string())?;
+pub fn handle_comment_list(request: CommentListRequest) -> Result<Value, String> {
+    let install_id = iid_pool::resolve_install_id(request.install_id.as_deref())?;
+    let identity = profiles::resolve(Some(&install_id), request.profile.as_deref())?;
+    let aid = identity.aid(request.aid.as_deref());
+    let client = build_client()?;
     let url = format!("{}/{}/v1", request.base_url, request.chapter_id);
     let response = client
         .post(url)
-        .query(&[
-            ("aid", request.aid.as_str()),
-            ("iid", request.install_id.as_str()),
-        ])
+        .headers(identity.headers(None)?)
+        .query(&[("aid", aid.as_str()), ("iid", install_id.as_str())])
         .json(&json!({
             "business_param": request.business_param,
             "comment_source": request.comment_source,
@@ -83,8 +115,10 @@ pub fn handle_comment_list(payload: &[u8]) -> Result<Value, String> {
             "group_type": request.group_type,
             "sort": request.sort,
         }))
-        .send()
-        .map_err(|err| err.to_string())?
+        .send_traced()
+        .map_err(|err| err.to_string())?;
+    iid_pool::report_status(&install_id, response.status());
+    let response = response
         .error_for_status()
         .map_err(|err| err.to_string())?
         .json::<Value>()
@@ -92,19 +126,13 @@ pub fn handle_comment_list(payload: &[u8]) -> Result<Value, String> {
     Ok(response)
 }
 
-fn build_client() -> Result<Client, reqwest::Error> {
+fn build_client() -> Result<Client, String> {
+    let config = config::current();
     let mut headers = HeaderMap::new();
-    headers.insert(
-        USER_AGENT,
-        HeaderValue::from_static(
-            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
-             (KHTML, like Gecko) Chrome/124.0 Safari/537.36",
-        ),
-    );
     headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
     headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-    Client::builder()
+    config::client_builder(config.timeouts.reviews_ms)?
         .default_headers(headers)
-        .timeout(Duration::from_secs(12))
         .build()
+        .map_err(|err| err.to_string())
 }
@@ -1,45 +1,62 @@
-use std::time::Duration;
-
 use reqwest::blocking::Client;
-use reqwest::header::{ACCEPT, CONTENT_TYPE, COOKIE, HeaderMap, HeaderValue, USER_AGENT};
+use reqwest::header::{ACCEPT, CONTENT_TYPE, COOKIE, HeaderMap, HeaderValue};
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_json::Value;
 
-const AID_DEFAULT: &str = "1967";
+use crate::api::{iid_pool, profiles};
+use crate::config;
+use crate::http::TracedSend;
+use crate::validate::{Validate, Validator};
 
-#[derive(Deserialize)]
-struct SearchRequest {
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct SearchRequest {
+    #[serde(default = "default_search_url")]
     url: String,
     query: String,
-    #[serde(default = "default_aid")]
-    aid: String,
-    install_id: String,
+    #[serde(default)]
+    aid: Option<String>,
+    #[serde(default)]
+    install_id: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
+}
+
+fn default_search_url() -> String {
+    config::current().endpoints.search.clone()
 }
 
-fn default_aid() -> String {
-    AID_DEFAULT.to_string()
+impl Validate for SearchRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .url("url", &self.url)
+            .optional_numeric("aid", self.aid.as_deref())
+            .optional_numeric("install_id", self.install_id.as_deref());
+    }
 }
 
-pub fn handle_search_books(payload: &[u8]) -> Result<Value, String> {
-    let req: SearchRequest = serde_json::from_slice(payload).map_err(|e| e.to_string())?;
+/// A blank `query` returns `null` without searching.
+pub fn handle_search_books(req: SearchRequest) -> Result<Value, String> {
     if req.query.trim().is_empty() {
         return Ok(Value::Null);
     }
-    if req.url.trim().is_empty() {
-        return Err("search url missing".to_string());
-    }
-
-    let client = build_client().map_err(|e| e.to_string())?;
+    let install_id = iid_pool::resolve_install_id(req.install_id.as_deref())?;
+    let identity = profiles::resolve(Some(&install_id), req.profile.as_deref())?;
+    let aid = identity.aid(req.aid.as_deref());
+    let client = build_client()?;
     let response = client
         .get(&req.url)
+        .headers(identity.headers(None)?)
         .query(&[
             ("offset", "0"),
-            ("aid", req.aid.as_str()),
+            ("aid", aid.as_str()),
             ("q", req.query.as_str()),
         ])
-        .header(COOKIE, format!("install_id={}", req.install_id))
-        .send()
-        .map_err(|e| e.to_string())?
+        .header(COOKIE, format!("install_id={}", install_id))
+        .send_traced()
+        .map_err(|e| e.to_string())?;
+    iid_pool::report_status(&install_id, response.status());
+    let response = response
         .error_for_status()
         .map_err(|e| e.to_string())?
         .json::<Value>()
@@ -48,20 +65,14 @@ pub fn handle_search_books(payload: &[u8]) -> Result<Value, String> {
     Ok(response)
 }
 
-fn build_client() -> Result<Client, reqwest::Error> {
+fn build_client() -> Result<Client, String> {
+    let config = config::current();
     let mut headers = HeaderMap::new();
-    headers.insert(
-        USER_AGENT,
-        HeaderValue::from_static(
-            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
-             (KHTML, like Gecko) Chrome/124.0 Safari/537.36",
-        ),
-    );
     headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
     headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
 
-    Client::builder()
+    config::client_builder(config.timeouts.search_ms)?
         .default_headers(headers)
-        .timeout(Duration::from_secs(12))
         .build()
+        .map_err(|err| err.to_string())
 }
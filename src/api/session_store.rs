@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, OsRng};
+use chacha20poly1305::{AeadCore, KeyInit, XChaCha20Poly1305, XNonce};
+use once_cell::sync::Lazy;
+use schemars::{JsonSchema, Schema, json_schema};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::api::{iid_pool, profiles};
+use crate::validate::{Validate, Validator};
+
+const MAGIC: &[u8; 4] = b"TNSS";
+const FORMAT_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+const REDACTED: &str = "[redacted]";
+
+static STORE: Lazy<Mutex<SessionState>> = Lazy::new(|| Mutex::new(SessionState::default()));
+
+/// Session material persisted between runs: pooled install IDs, registered signed-session keys
+/// (keyed by install ID), the device profile each install ID is bound to, and cookies (keyed by
+/// host or cookie name, as chosen by the caller).
+#[derive(Default, Serialize, Deserialize)]
+struct SessionState {
+    #[serde(default)]
+    install_ids: Vec<String>,
+    #[serde(default)]
+    signed_keys: HashMap<String, Value>,
+    #[serde(default)]
+    profiles: HashMap<String, String>,
+    #[serde(default)]
+    cookies: HashMap<String, String>,
+}
+
+impl SessionState {
+    fn wipe(&mut self) {
+        for install_id in self.install_ids.iter_mut() {
+            install_id.zeroize();
+        }
+        self.install_ids.clear();
+        for (_, mut key) in self.signed_keys.drain() {
+            wipe_value(&mut key);
+        }
+        for (mut install_id, _) in self.profiles.drain() {
+            install_id.zeroize();
+        }
+        for (mut name, mut cookie) in self.cookies.drain() {
+            name.zeroize();
+            cookie.zeroize();
+        }
+    }
+}
+
+impl Drop for SessionState {
+    fn drop(&mut self) {
+        self.wipe();
+    }
+}
+
+fn wipe_value(value: &mut Value) {
+    match value {
+        Value::String(text) => text.zeroize(),
+        Value::Array(items) => items.iter_mut().for_each(wipe_value),
+        Value::Object(map) => map.values_mut().for_each(wipe_value),
+        _ => {}
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct StoreFileRequest {
+    path: String,
+    passphrase: String,
+}
+
+impl Drop for StoreFileRequest {
+    fn drop(&mut self) {
+        self.passphrase.zeroize();
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct GetRequest {
+    /// Return cookie values in clear; they are masked otherwise.
+    #[serde(default)]
+    reveal: bool,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct PutRequest {
+    #[serde(default)]
+    signed_keys: HashMap<String, Value>,
+    #[serde(default)]
+    cookies: HashMap<String, String>,
+    #[serde(default)]
+    remove_cookies: Vec<String>,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, String> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|err| err.to_string())?;
+    Ok(key)
+}
+
+fn encrypt(passphrase: &str, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive_key(passphrase, &salt)?;
+    let cipher = XChaCha20Poly1305::new_from_slice(key.as_ref()).map_err(|err| err.to_string())?;
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| "session store encryption failed".to_string())?;
+
+    let mut out = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+    out.extend_from_slice(MAGIC);
+    out.push(FORMAT_VERSION);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+fn decrypt(passphrase: &str, data: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    if data.len() < HEADER_LEN || &data[..MAGIC.len()] != MAGIC {
+        return Err("not a session store file".to_string());
+    }
+    if data[MAGIC.len()] != FORMAT_VERSION {
+        return Err(format!(
+            "unsupported session store version: {}",
+            data[MAGIC.len()]
+        ));
+    }
+    let salt_start = MAGIC.len() + 1;
+    let nonce_start = salt_start + SALT_LEN;
+    let salt = &data[salt_start..nonce_start];
+    let nonce = XNonce::from_slice(&data[nonce_start..HEADER_LEN]);
+    let key = derive_key(passphrase, salt)?;
+    let cipher = XChaCha20Poly1305::new_from_slice(key.as_ref()).map_err(|err| err.to_string())?;
+    cipher
+        .decrypt(nonce, &data[HEADER_LEN..])
+        .map(Zeroizing::new)
+        .map_err(|_| {
+            "session store decryption failed (wrong passphrase or corrupt file)".to_string()
+        })
+}
+
+/// Decrypts the store at `path`, replaces the in-memory state, re-seeds the install ID pool and
+/// restores profile bindings.
+pub(crate) fn load(request: StoreFileRequest) -> Result<Value, String> {
+    if request.path.is_empty() {
+        return Err("session store path missing".to_string());
+    }
+    let data = fs::read(&request.path).map_err(|err| err.to_string())?;
+    let plaintext = decrypt(&request.passphrase, &data)?;
+    let state: SessionState = serde_json::from_slice(&plaintext).map_err(|err| err.to_string())?;
+    let added = iid_pool::add_install_ids(state.install_ids.iter().cloned());
+    profiles::restore(&state.profiles);
+
+    let mut store = STORE.lock().unwrap();
+    *store = state;
+    Ok(json!({
+        "install_ids": store.install_ids.len(),
+        "pooled": added,
+        "signed_keys": store.signed_keys.len(),
+        "profiles": store.profiles.len(),
+        "cookies": store.cookies.len(),
+    }))
+}
+
+/// Encrypts the current state (including the pool's install IDs and profile bindings) and writes
+/// it to `path`.
+pub(crate) fn save(request: StoreFileRequest) -> Result<Value, String> {
+    if request.path.is_empty() {
+        return Err("session store path missing".to_string());
+    }
+    let mut store = STORE.lock().unwrap();
+    for install_id in iid_pool::install_ids() {
+        if !store.install_ids.contains(&install_id) {
+            store.install_ids.push(install_id);
+        }
+    }
+    store.profiles.extend(profiles::bindings());
+    let plaintext = Zeroizing::new(serde_json::to_vec(&*store).map_err(|err| err.to_string())?);
+    let data = encrypt(&request.passphrase, &plaintext)?;
+    write_atomically(Path::new(&request.path), &data)?;
+    Ok(json!({
+        "install_ids": store.install_ids.len(),
+        "signed_keys": store.signed_keys.len(),
+        "profiles": store.profiles.len(),
+        "cookies": store.cookies.len(),
+    }))
+}
+
+/// Wipes the in-memory session state along with the install ID pool and profile bindings, so no
+/// identity outlives it. Pool settings and files on disk are left untouched.
+pub(crate) fn clear() {
+    STORE.lock().unwrap().wipe();
+    iid_pool::clear();
+    profiles::clear();
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, data).map_err(|err| err.to_string())?;
+    fs::rename(&tmp, path).map_err(|err| err.to_string())
+}
+
+/// Remembers the key material registered for `install_id` so it survives a save/load cycle.
+pub(crate) fn remember_signed_key(install_id: &str, key: &Value) {
+    let mut store = STORE.lock().unwrap();
+    if let Some(mut previous) = store
+        .signed_keys
+        .insert(install_id.to_string(), key.clone())
+    {
+        wipe_value(&mut previous);
+    }
+    if !store.install_ids.iter().any(|id| id == install_id) {
+        store.install_ids.push(install_id.to_string());
+    }
+}
+
+impl Validate for GetRequest {
+    fn validate(&self, _validator: &mut Validator) {}
+}
+
+impl Validate for PutRequest {
+    fn validate(&self, validator: &mut Validator) {
+        for install_id in self.signed_keys.keys() {
+            validator.numeric("signed_keys", install_id);
+        }
+        for name in self.cookies.keys().chain(&self.remove_cookies) {
+            validator.non_empty("cookies", name);
+        }
+    }
+}
+
+/// Key material never leaves the store: `signed_keys` only lists the install IDs holding some.
+/// Cookie values are decrypted session secrets, so they are masked unless the caller asks for
+/// them with `reveal`.
+pub fn handle_session_get(request: GetRequest) -> Result<Value, String> {
+    let store = STORE.lock().unwrap();
+    let mut signed_keys: Vec<&String> = store.signed_keys.keys().collect();
+    signed_keys.sort();
+    let cookies: HashMap<&String, &str> = store
+        .cookies
+        .iter()
+        .map(|(name, value)| (name, if request.reveal { value } else { REDACTED }))
+        .collect();
+    Ok(json!({
+        "install_ids": store.install_ids,
+        "signed_keys": signed_keys,
+        "profiles": profiles::bindings(),
+        "cookies": cookies,
+    }))
+}
+
+pub fn handle_session_put(request: PutRequest) -> Result<Value, String> {
+    for (install_id, key) in &request.signed_keys {
+        remember_signed_key(install_id, key);
+    }
+    let mut store = STORE.lock().unwrap();
+    for name in &request.remove_cookies {
+        if let Some(mut cookie) = store.cookies.remove(name) {
+            cookie.zeroize();
+        }
+    }
+    store.cookies.extend(request.cookies);
+    Ok(json!({
+        "install_ids": store.install_ids.len(),
+        "signed_keys": store.signed_keys.len(),
+        "cookies": store.cookies.len(),
+    }))
+}
+
+pub(crate) fn session_get_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": {
+            "install_ids": { "type": "array", "items": { "type": "string" } },
+            "signed_keys": { "type": "array", "items": { "type": "string" } },
+            "profiles": { "type": "object", "additionalProperties": { "type": "string" } },
+            "cookies": { "type": "object", "additionalProperties": { "type": "string" } }
+        },
+        "required": ["install_ids", "signed_keys", "profiles", "cookies"]
+    })
+}
+
+pub(crate) fn session_put_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": {
+            "install_ids": { "type": "integer", "minimum": 0 },
+            "signed_keys": { "type": "integer", "minimum": 0 },
+            "cookies": { "type": "integer", "minimum": 0 }
+        },
+        "required": ["install_ids", "signed_keys", "cookies"]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_decrypt_round_trip() {
+        let plaintext = br#"{"install_ids":["7300000000000000001"]}"#;
+        let sealed = encrypt("correct horse", plaintext).unwrap();
+        assert_eq!(&sealed[..MAGIC.len()], MAGIC);
+        assert!(!sealed.windows(plaintext.len()).any(|w| w == plaintext));
+        assert_eq!(&*decrypt("correct horse", &sealed).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn encryption_is_salted() {
+        let first = encrypt("passphrase", b"same").unwrap();
+        let second = encrypt("passphrase", b"same").unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let sealed = encrypt("correct horse", b"secret").unwrap();
+        let err = decrypt("battery staple", &sealed).unwrap_err();
+        assert!(err.contains("wrong passphrase"), "{}", err);
+    }
+
+    #[test]
+    fn tampered_or_foreign_files_are_rejected() {
+        let mut sealed = encrypt("passphrase", b"secret").unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(decrypt("passphrase", &sealed).is_err());
+
+        assert_eq!(
+            decrypt("passphrase", b"not a store").unwrap_err(),
+            "not a session store file"
+        );
+        let mut future = encrypt("passphrase", b"secret").unwrap();
+        future[MAGIC.len()] = FORMAT_VERSION + 1;
+        assert!(
+            decrypt("passphrase", &future)
+                .unwrap_err()
+                .contains("unsupported session store version")
+        );
+    }
+}
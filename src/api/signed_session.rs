@@ -1,82 +1,131 @@
 use std::collections::HashMap;
-use std::time::Duration;
 
 use reqwest::blocking::{Client, ClientBuilder};
 use reqwest::header::{
-    ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, CONNECTION, COOKIE, HeaderMap, HeaderName,
-    HeaderValue,
+    ACCEPT, ACCEPT_ENCODING, CONNECTION, COOKIE, HeaderMap, HeaderName, HeaderValue,
 };
 #[cfg(any(debug_assertions, feature = "charles_proxy"))]
 use reqwest::{Certificate, Proxy};
+use schemars::{JsonSchema, Schema, json_schema};
 use serde::Deserialize;
 use serde_json::Value;
 
-const DEFAULT_USER_AGENT: &str = "python-requests/2.31.0";
+use crate::api::{iid_pool, profiles, session_store};
+use crate::config;
+use crate::http::TracedSend;
+use crate::validate::{Validate, Validator};
 
-#[derive(Deserialize)]
-struct RegisterKeyRequest {
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct RegisterKeyRequest {
+    #[serde(default = "default_register_key_url")]
     url: String,
-    install_id: String,
-    aid: String,
+    #[serde(default)]
+    install_id: Option<String>,
+    #[serde(default)]
+    aid: Option<String>,
     body: Value,
     #[serde(default)]
     user_agent: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
 }
 
-#[derive(Deserialize)]
-struct BatchFullRequest {
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct BatchFullRequest {
+    #[serde(default = "default_batch_full_url")]
     base_url: String,
     query: String,
     headers: HashMap<String, String>,
+    #[serde(default)]
+    profile: Option<String>,
 }
 
-#[derive(Deserialize)]
-struct BatchRequestPayload {
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct BatchRequestPayload {
+    #[serde(default = "default_batch_request_url")]
     base_url: String,
     chapter_ids: Vec<String>,
+    #[serde(default)]
+    profile: Option<String>,
+}
+
+fn default_register_key_url() -> String {
+    config::current().endpoints.register_key.clone()
 }
 
-pub fn handle_register_key(payload: &[u8]) -> Result<Value, String> {
-    let request: RegisterKeyRequest =
-        serde_json::from_slice(payload).map_err(|err| err.to_string())?;
-    if request.url.is_empty() {
-        return Err("register key url missing".to_string());
+fn default_batch_full_url() -> String {
+    config::current().endpoints.batch_full.clone()
+}
+
+fn default_batch_request_url() -> String {
+    config::current().endpoints.batch_request.clone()
+}
+
+impl Validate for RegisterKeyRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .url("url", &self.url)
+            .optional_numeric("aid", self.aid.as_deref())
+            .optional_numeric("install_id", self.install_id.as_deref());
     }
-    let client = build_client(request.user_agent.as_deref()).map_err(|err| err.to_string())?;
-    let mut headers = HeaderMap::new();
+}
+
+impl Validate for BatchFullRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .url("base_url", &self.base_url)
+            .non_empty("query", &self.query);
+    }
+}
+
+impl Validate for BatchRequestPayload {
+    fn validate(&self, validator: &mut Validator) {
+        validator.url("base_url", &self.base_url);
+        for (index, chapter_id) in self.chapter_ids.iter().enumerate() {
+            validator.numeric(&format!("chapter_ids[{}]", index), chapter_id);
+        }
+    }
+}
+
+pub fn handle_register_key(request: RegisterKeyRequest) -> Result<Value, String> {
+    let install_id = iid_pool::resolve_install_id(request.install_id.as_deref())?;
+    let identity = profiles::resolve(Some(&install_id), request.profile.as_deref())?;
+    let aid = identity.aid(request.aid.as_deref());
+    let client = build_client()?;
+    let mut headers = identity.headers(request.user_agent.as_deref())?;
     headers.insert(
         COOKIE,
-        HeaderValue::from_str(&format!("install_id={}", request.install_id))
+        HeaderValue::from_str(&format!("install_id={}", install_id))
             .map_err(|err| err.to_string())?,
     );
 
     let response = client
         .post(&request.url)
         .headers(headers)
-        .query(&[("aid", request.aid.as_str())])
+        .query(&[("aid", aid.as_str())])
         .json(&request.body)
-        .send()
-        .map_err(|err| err.to_string())?
+        .send_traced()
+        .map_err(|err| err.to_string())?;
+    iid_pool::report_status(&install_id, response.status());
+    let response = response
         .error_for_status()
         .map_err(|err| err.to_string())?
         .json::<Value>()
         .map_err(|err| err.to_string())?;
+    session_store::remember_signed_key(&install_id, &request.body);
     Ok(response)
 }
 
-pub fn handle_batch_full(payload: &[u8]) -> Result<Value, String> {
-    let request: BatchFullRequest =
-        serde_json::from_slice(payload).map_err(|err| err.to_string())?;
-    if request.base_url.is_empty() {
-        return Err("batch full url missing".to_string());
-    }
-    let client = build_client(None).map_err(|err| err.to_string())?;
-    let headers = header_map_from_pairs(request.headers)?;
+pub fn handle_batch_full(request: BatchFullRequest) -> Result<Value, String> {
+    let identity = profiles::resolve(None, request.profile.as_deref())?;
+    let client = build_client()?;
+    let mut headers = identity.headers(None)?;
+    headers.extend(header_map_from_pairs(request.headers)?);
     let url = format!("{}{}", request.base_url, request.query);
     let response = client
         .get(url)
         .headers(headers)
-        .send()
+        .send_traced()
         .map_err(|err| err.to_string())?
         .error_for_status()
         .map_err(|err| err.to_string())?
@@ -85,22 +134,20 @@ pub fn handle_batch_full(payload: &[u8]) -> Result<Value, String> {
     Ok(response)
 }
 
-pub fn handle_batch_request(payload: &[u8]) -> Result<Value, String> {
-    let request: BatchRequestPayload =
-        serde_json::from_slice(payload).map_err(|err| err.to_string())?;
+pub fn handle_batch_request(request: BatchRequestPayload) -> Result<Value, String> {
     if request.chapter_ids.is_empty() {
         return Ok(Value::Array(Vec::new()));
     }
-    if request.base_url.is_empty() {
-        return Err("batch request url missing".to_string());
-    }
-    let client = build_client(None).map_err(|err| err.to_string())?;
+    let identity = profiles::resolve(None, request.profile.as_deref())?;
+    let headers = identity.headers(None)?;
+    let client = build_client()?;
     let mut results = Vec::with_capacity(request.chapter_ids.len());
     for chapter_id in request.chapter_ids {
         let url = format!("{}{}", request.base_url, chapter_id);
         let text = client
             .get(&url)
-            .send()
+            .headers(headers.clone())
+            .send_traced()
             .map_err(|err| err.to_string())?
             .error_for_status()
             .map_err(|err| err.to_string())?
@@ -111,21 +158,21 @@ pub fn handle_batch_request(payload: &[u8]) -> Result<Value, String> {
     Ok(Value::Array(results))
 }
 
-fn build_client(user_agent: Option<&str>) -> Result<Client, reqwest::Error> {
+fn build_client() -> Result<Client, String> {
     let mut headers = HeaderMap::new();
     headers.insert(
         ACCEPT,
         HeaderValue::from_static("application/json, text/plain, */*"),
     );
-    headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("zh-CN,zh;q=0.9"));
     headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
     headers.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
 
-    let builder = Client::builder()
-        .default_headers(headers)
-        .timeout(Duration::from_secs(15))
-        .user_agent(user_agent.unwrap_or(DEFAULT_USER_AGENT));
-    configure_charles_proxy(builder).build()
+    let config = config::current();
+    let builder =
+        config::client_builder(config.timeouts.signed_session_ms)?.default_headers(headers);
+    configure_charles_proxy(builder)
+        .build()
+        .map_err(|err| err.to_string())
 }
 
 fn header_map_from_pairs(pairs: HashMap<String, String>) -> Result<HeaderMap, String> {
@@ -170,3 +217,11 @@ fn configure_charles_proxy(mut builder: ClientBuilder) -> ClientBuilder {
 fn configure_charles_proxy(builder: ClientBuilder) -> ClientBuilder {
     builder
 }
+
+pub(crate) fn batch_request_response_schema() -> Schema {
+    json_schema!({
+        "description": "Raw response bodies in the order of `chapter_ids`",
+        "type": "array",
+        "items": { "type": "string" }
+    })
+}
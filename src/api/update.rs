@@ -0,0 +1,415 @@
+use reqwest::header::{ACCEPT, USER_AGENT};
+use schemars::{JsonSchema, Schema, json_schema};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::api::version::fetch_remote_filename;
+use crate::config;
+use crate::http::TracedSend;
+use crate::validate::{Validate, Validator};
+
+const KNOWN_EXTENSIONS: &[&str] = &[
+    "exe", "msi", "zip", "7z", "gz", "xz", "bz2", "zst", "tar", "tgz", "apk", "dmg", "pkg", "deb",
+    "rpm", "appimage", "bin", "jar", "whl",
+];
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Channel {
+    #[default]
+    Stable,
+    Prerelease,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct UpdateCheckRequest {
+    current_version: String,
+    #[serde(default)]
+    channel: Channel,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    releases_url: Option<String>,
+    #[serde(default)]
+    asset_pattern: Option<String>,
+    #[serde(default)]
+    user_agent: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GithubRelease {
+    #[serde(default)]
+    tag_name: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    published_at: Option<String>,
+    #[serde(default)]
+    assets: Vec<GithubAsset>,
+}
+
+#[derive(Deserialize)]
+struct GithubAsset {
+    name: String,
+    browser_download_url: String,
+    #[serde(default)]
+    size: Option<u64>,
+}
+
+struct Candidate {
+    version: Version,
+    filename: Option<String>,
+    asset_url: Option<String>,
+    size: Option<u64>,
+    notes: Option<String>,
+    published_at: Option<String>,
+}
+
+impl Validate for UpdateCheckRequest {
+    fn validate(&self, validator: &mut Validator) {
+        if parse_version(&self.current_version).is_none() {
+            validator.push("current_version", "must be a version such as 1.2.3");
+        }
+        validator
+            .optional_url("url", self.url.as_deref())
+            .optional_url("releases_url", self.releases_url.as_deref());
+        let has_url = self.url.as_deref().is_some_and(|u| !u.is_empty());
+        let has_releases = self.releases_url.as_deref().is_some_and(|u| !u.is_empty());
+        if !has_url && !has_releases {
+            validator.push("url", "either url or releases_url is required");
+        }
+    }
+}
+
+pub fn handle_update_check(request: UpdateCheckRequest) -> Result<Value, String> {
+    let current = parse_version(&request.current_version)
+        .ok_or_else(|| format!("invalid current version: {}", request.current_version))?;
+
+    let (source, candidate) = match (request.releases_url.as_deref(), request.url.as_deref()) {
+        (Some(releases_url), _) if !releases_url.is_empty() => {
+            ("releases", latest_release(releases_url, &request)?)
+        }
+        (_, Some(url)) if !url.is_empty() => ("filename", latest_from_filename(url)?),
+        _ => return Err("update check needs url or releases_url".to_string()),
+    };
+
+    let Some(candidate) = candidate.filter(|c| accepts(request.channel, &c.version)) else {
+        return Ok(json!({
+            "current": current.to_string(),
+            "latest": Value::Null,
+            "update_available": false,
+            "channel": request.channel,
+            "source": source,
+        }));
+    };
+
+    Ok(json!({
+        "current": current.to_string(),
+        "latest": candidate.version.to_string(),
+        "update_available": candidate.version > current,
+        "prerelease": !candidate.version.pre.is_empty(),
+        "channel": request.channel,
+        "source": source,
+        "filename": candidate.filename,
+        "asset_url": candidate.asset_url,
+        "size": candidate.size,
+        "notes": candidate.notes,
+        "published_at": candidate.published_at,
+    }))
+}
+
+fn accepts(channel: Channel, version: &Version) -> bool {
+    channel == Channel::Prerelease || version.pre.is_empty()
+}
+
+fn latest_from_filename(url: &str) -> Result<Option<Candidate>, String> {
+    let Some(filename) = fetch_remote_filename(url)? else {
+        return Ok(None);
+    };
+    Ok(version_from_filename(&filename).map(|version| Candidate {
+        version,
+        filename: Some(filename),
+        asset_url: Some(url.to_string()),
+        size: None,
+        notes: None,
+        published_at: None,
+    }))
+}
+
+fn latest_release(
+    releases_url: &str,
+    request: &UpdateCheckRequest,
+) -> Result<Option<Candidate>, String> {
+    let config = config::current();
+    let client = config::client_builder(config.timeouts.update_ms)?
+        .build()
+        .map_err(|err| err.to_string())?;
+    let body = client
+        .get(releases_url)
+        .header(ACCEPT, "application/vnd.github+json")
+        .header(
+            USER_AGENT,
+            request
+                .user_agent
+                .as_deref()
+                .unwrap_or(&config.user_agents.updater),
+        )
+        .send_traced()
+        .map_err(|err| err.to_string())?
+        .error_for_status()
+        .map_err(|err| err.to_string())?
+        .json::<Value>()
+        .map_err(|err| err.to_string())?;
+
+    // Both `/releases` (an array) and `/releases/latest` (a single object) are accepted.
+    let releases: Vec<GithubRelease> = match body {
+        Value::Array(_) => serde_json::from_value(body).map_err(|err| err.to_string())?,
+        other => vec![serde_json::from_value(other).map_err(|err| err.to_string())?],
+    };
+
+    let best = releases
+        .into_iter()
+        .filter(|release| !release.draft)
+        .filter_map(|release| {
+            let version = parse_version(&release.tag_name)
+                .or_else(|| release.name.as_deref().and_then(version_from_filename))?;
+            let prerelease = release.prerelease || !version.pre.is_empty();
+            if prerelease && request.channel == Channel::Stable {
+                return None;
+            }
+            Some((version, release))
+        })
+        .max_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(best.map(|(version, release)| {
+        let asset = pick_asset(&release.assets, request.asset_pattern.as_deref());
+        Candidate {
+            version,
+            filename: asset.map(|a| a.name.clone()),
+            asset_url: asset.map(|a| a.browser_download_url.clone()),
+            size: asset.and_then(|a| a.size),
+            notes: release.body,
+            published_at: release.published_at,
+        }
+    }))
+}
+
+fn pick_asset<'a>(assets: &'a [GithubAsset], pattern: Option<&str>) -> Option<&'a GithubAsset> {
+    match pattern.filter(|p| !p.is_empty()) {
+        Some(pattern) => {
+            let pattern = pattern.to_ascii_lowercase();
+            assets
+                .iter()
+                .find(|asset| asset.name.to_ascii_lowercase().contains(&pattern))
+        }
+        None => assets.first(),
+    }
+}
+
+/// Parses a version string such as `v1.2`, `1.2.3` or `1.2.3-beta.1`, padding missing
+/// components with zero.
+fn parse_version(text: &str) -> Option<Version> {
+    let text = text.trim();
+    let text = text
+        .strip_prefix('v')
+        .or_else(|| text.strip_prefix('V'))
+        .unwrap_or(text);
+    if let Ok(version) = Version::parse(text) {
+        return Some(version);
+    }
+    let split = text.find(['-', '+']).unwrap_or(text.len());
+    let (core, rest) = text.split_at(split);
+    let parts: Vec<&str> = core.split('.').collect();
+    if parts.is_empty()
+        || parts.len() > 3
+        || parts
+            .iter()
+            .any(|p| p.is_empty() || !p.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return None;
+    }
+    let mut padded = parts.join(".");
+    for _ in parts.len()..3 {
+        padded.push_str(".0");
+    }
+    Version::parse(&format!("{}{}", padded, rest)).ok()
+}
+
+/// Finds the first semantic version embedded in a release file name, e.g.
+/// `TomatoNovel-v1.4.0-beta.2-windows.zip` yields `1.4.0-beta.2`.
+fn version_from_filename(filename: &str) -> Option<Version> {
+    let stem = strip_extensions(filename);
+    let bytes = stem.as_bytes();
+    let mut start = 0;
+    while start < bytes.len() {
+        let boundary = start == 0 || !bytes[start - 1].is_ascii_alphanumeric() || {
+            // Allow a `v` prefix directly attached to the version (`app-v1.2.3`).
+            bytes[start - 1].eq_ignore_ascii_case(&b'v')
+                && (start < 2 || !bytes[start - 2].is_ascii_alphanumeric())
+        };
+        if boundary
+            && bytes[start].is_ascii_digit()
+            && let Some(version) = version_at(&stem[start..])
+        {
+            return Some(version);
+        }
+        start += 1;
+    }
+    None
+}
+
+fn version_at(text: &str) -> Option<Version> {
+    let bytes = text.as_bytes();
+    let mut end = 0;
+    let mut dots = 0;
+    while end < bytes.len() {
+        match bytes[end] {
+            b'0'..=b'9' => end += 1,
+            b'.' if dots < 2 && bytes.get(end + 1).is_some_and(u8::is_ascii_digit) => {
+                dots += 1;
+                end += 1;
+            }
+            _ => break,
+        }
+    }
+    if dots == 0 {
+        return None;
+    }
+    let core = &text[..end];
+    let mut version = parse_version(core)?;
+
+    // Take a prerelease tag only when it looks like one, so `-windows` or `-x64` suffixes are
+    // not mistaken for prerelease identifiers.
+    if let Some(rest) = text[end..].strip_prefix('-') {
+        let tag_len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.'))
+            .unwrap_or(rest.len());
+        let tag = rest[..tag_len].trim_end_matches('.');
+        if is_prerelease_tag(tag)
+            && let Ok(pre) = semver::Prerelease::new(tag)
+        {
+            version.pre = pre;
+        }
+    }
+    Some(version)
+}
+
+fn is_prerelease_tag(tag: &str) -> bool {
+    let lower = tag.to_ascii_lowercase();
+    ["alpha", "beta", "rc", "pre", "preview", "dev", "nightly"]
+        .iter()
+        .any(|prefix| lower.starts_with(prefix))
+}
+
+fn strip_extensions(filename: &str) -> &str {
+    let mut stem = filename;
+    while let Some((head, ext)) = stem.rsplit_once('.') {
+        if KNOWN_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()) {
+            stem = head;
+        } else {
+            break;
+        }
+    }
+    stem
+}
+
+pub(crate) fn update_check_response_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": {
+            "current": { "type": "string" },
+            "latest": { "type": ["string", "null"] },
+            "update_available": { "type": "boolean" },
+            "prerelease": { "type": "boolean" },
+            "channel": { "enum": ["stable", "prerelease"] },
+            "source": { "enum": ["releases", "filename"] },
+            "filename": { "type": ["string", "null"] },
+            "asset_url": { "type": ["string", "null"] },
+            "size": { "type": ["integer", "null"] },
+            "notes": { "type": ["string", "null"] },
+            "published_at": { "type": ["string", "null"] }
+        },
+        "required": ["current", "latest", "update_available", "channel", "source"]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(text: &str) -> Version {
+        Version::parse(text).unwrap()
+    }
+
+    #[test]
+    fn tags_are_parsed_and_padded() {
+        assert_eq!(parse_version("v1.2"), Some(version("1.2.0")));
+        assert_eq!(parse_version(" V3 "), Some(version("3.0.0")));
+        assert_eq!(parse_version("1.2.3"), Some(version("1.2.3")));
+        assert_eq!(parse_version("1.4-rc.1"), Some(version("1.4.0-rc.1")));
+        assert_eq!(parse_version("1.2.3-beta.1"), Some(version("1.2.3-beta.1")));
+        assert_eq!(parse_version("2.0+build.7"), Some(version("2.0.0+build.7")));
+        for text in ["", "v", "release", "1.2.3.4", "1..2", "1.x"] {
+            assert_eq!(parse_version(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn versions_are_found_in_file_names() {
+        let cases = [
+            ("TomatoNovel-v1.4.0-beta.2-windows.zip", "1.4.0-beta.2"),
+            ("app-1.2-x64.tar.gz", "1.2.0"),
+            ("v1.2", "1.2.0"),
+            ("TomatoNovel_2.10.3.exe", "2.10.3"),
+            ("tomato-novel-1.0.0-RC1.AppImage", "1.0.0-RC1"),
+            ("app-v2.1.0-linux-arm64.deb", "2.1.0"),
+            ("build-1.2.3.4.zip", "1.2.3"),
+        ];
+        for (filename, expected) in cases {
+            assert_eq!(
+                version_from_filename(filename),
+                Some(version(expected)),
+                "{}",
+                filename
+            );
+        }
+        for filename in [
+            "TomatoNovel-windows.zip",
+            "setup2024.exe",
+            "win32-x64.tar.gz",
+            "",
+        ] {
+            assert_eq!(version_from_filename(filename), None, "{}", filename);
+        }
+    }
+
+    #[test]
+    fn only_prerelease_looking_suffixes_are_kept() {
+        assert_eq!(version_at("1.2-x64"), Some(version("1.2.0")));
+        assert_eq!(version_at("1.2.0-windows"), Some(version("1.2.0")));
+        assert_eq!(
+            version_at("1.2.0-alpha.3-x64"),
+            Some(version("1.2.0-alpha.3"))
+        );
+        assert_eq!(version_at("1.2.0-nightly."), Some(version("1.2.0-nightly")));
+        assert_eq!(version_at("12-x64"), None);
+        assert_eq!(version_at("1."), None);
+    }
+
+    #[test]
+    fn only_known_extensions_are_stripped() {
+        assert_eq!(strip_extensions("app-1.2-x64.tar.gz"), "app-1.2-x64");
+        assert_eq!(strip_extensions("Tomato.Setup.EXE"), "Tomato.Setup");
+        assert_eq!(strip_extensions("app-1.2.3"), "app-1.2.3");
+        assert_eq!(strip_extensions("v1.2"), "v1.2");
+        assert_eq!(strip_extensions("archive.tar.zst"), "archive");
+        assert_eq!(strip_extensions("zip"), "zip");
+    }
+}
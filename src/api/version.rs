@@ -1,50 +1,63 @@
-use std::time::Duration;
-
 use reqwest::Method;
 use reqwest::blocking::{Client, Response};
 use reqwest::header::{CONTENT_DISPOSITION, LOCATION, RANGE};
+use schemars::{JsonSchema, Schema, json_schema};
 use serde::Deserialize;
 use serde_json::{Value, json};
 
-#[derive(Deserialize)]
-struct VersionRequest {
+use crate::api::content_disposition::{filename_from_header, percent_decode, sanitize_filename};
+use crate::config;
+use crate::http::TracedSend;
+use crate::validate::{Validate, Validator};
+
+#[derive(Deserialize, JsonSchema)]
+pub(crate) struct VersionRequest {
     url: String,
 }
 
-pub fn handle_version_fetch_filename(payload: &[u8]) -> Result<Value, String> {
-    let request: VersionRequest = serde_json::from_slice(payload).map_err(|err| err.to_string())?;
-    if request.url.is_empty() {
-        return Err("version fetch url missing".to_string());
+impl Validate for VersionRequest {
+    fn validate(&self, validator: &mut Validator) {
+        validator.url("url", &self.url);
     }
+}
+
+pub fn handle_version_fetch_filename(request: VersionRequest) -> Result<Value, String> {
+    let filename = fetch_remote_filename(&request.url)?;
+    Ok(json!({ "filename": filename }))
+}
 
-    let client = Client::builder()
-        .timeout(Duration::from_secs(8))
+/// Resolves the file name the server would hand out for `url`, trying `HEAD` before a one byte
+/// ranged `GET`.
+pub(crate) fn fetch_remote_filename(url: &str) -> Result<Option<String>, String> {
+    let client = config::client_builder(config::current().timeouts.version_ms)?
         .redirect(reqwest::redirect::Policy::limited(5))
         .build()
         .map_err(|err| err.to_string())?;
 
-    if let Some(filename) = fetch_filename(&client, Method::HEAD, &request.url)
-        .or_else(|| fetch_filename(&client, Method::GET, &request.url))
-    {
-        return Ok(json!({ "filename": filename }));
-    }
-
-    Ok(json!({ "filename": serde_json::Value::Null }))
+    Ok(fetch_filename(&client, Method::HEAD, url)
+        .or_else(|| fetch_filename(&client, Method::GET, url)))
 }
 
 fn fetch_filename(client: &Client, method: Method, url: &str) -> Option<String> {
     let mut request = client.request(method.clone(), url);
     if method == Method::GET {
-        request = request.header(RANGE, "bytes=0-0");
+        request = request.header(RANGE, byte_range(0, Some(0)));
     }
-    let response = request.send().ok()?.error_for_status().ok()?;
+    let response = request.send_traced().ok()?.error_for_status().ok()?;
     extract_filename(&response)
 }
 
+/// Formats a `Range` header value covering `start..=end`, or everything from `start` onwards.
+pub(crate) fn byte_range(start: u64, end: Option<u64>) -> String {
+    match end {
+        Some(end) => format!("bytes={}-{}", start, end),
+        None => format!("bytes={}-", start),
+    }
+}
+
 fn extract_filename(response: &Response) -> Option<String> {
     if let Some(header) = response.headers().get(CONTENT_DISPOSITION)
-        && let Ok(text) = header.to_str()
-        && let Some(name) = parse_content_disposition(text)
+        && let Some(name) = filename_from_header(header.as_bytes())
     {
         return Some(name);
     }
@@ -59,24 +72,18 @@ fn extract_filename(response: &Response) -> Option<String> {
     extract_from_url(response.url().path())
 }
 
-fn parse_content_disposition(value: &str) -> Option<String> {
-    for part in value.split(';') {
-        let part = part.trim();
-        if let Some(stripped) = part.strip_prefix("filename=") {
-            let cleaned = stripped.trim_matches('"');
-            if !cleaned.is_empty() {
-                return Some(cleaned.to_string());
-            }
-        }
-    }
-    None
+fn extract_from_url(value: &str) -> Option<String> {
+    let path = value.split(['?', '#']).next().unwrap_or(value);
+    let segment = path.rsplit('/').find(|segment| !segment.is_empty())?;
+    let decoded = String::from_utf8(percent_decode(segment)).ok()?;
+    let name = sanitize_filename(&decoded)?;
+    if name.contains('.') { Some(name) } else { None }
 }
 
-fn extract_from_url(value: &str) -> Option<String> {
-    let name = value.rsplit('/').find(|segment| !segment.is_empty())?;
-    if name.contains('.') {
-        Some(name.to_string())
-    } else {
-        None
-    }
+pub(crate) fn version_response_schema() -> Schema {
+    json_schema!({
+        "type": "object",
+        "properties": { "filename": { "type": ["string", "null"] } },
+        "required": ["filename"]
+    })
 }
@@ -0,0 +1,87 @@
+//! `tn-core-daemon`: serves the core API over HTTP on TCP or a Unix domain socket.
+//!
+//! See `tomato_novel_network_core::daemon` for the routes. The bearer token can also be supplied
+//! through `TN_CORE_DAEMON_TOKEN` so it does not show up in process listings; without either, a
+//! token is generated and printed to stderr.
+
+use std::process::ExitCode;
+
+use clap::Parser;
+use tomato_novel_network_core::config::{self, CoreConfig};
+use tomato_novel_network_core::daemon::{self, DaemonConfig, Listen};
+
+const TOKEN_ENV: &str = "TN_CORE_DAEMON_TOKEN";
+
+#[derive(Parser)]
+#[command(
+    name = "tn-core-daemon",
+    version,
+    about = "Serve tomato-novel network core operations over HTTP"
+)]
+struct Cli {
+    /// TCP address to listen on.
+    #[arg(long, default_value = "127.0.0.1:7878", value_name = "ADDR")]
+    listen: String,
+    /// Listen on this Unix domain socket instead of TCP.
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH")]
+    unix: Option<std::path::PathBuf>,
+    /// Require `Authorization: Bearer <TOKEN>`; defaults to `TN_CORE_DAEMON_TOKEN`, then to a
+    /// generated token.
+    #[arg(long, value_name = "TOKEN")]
+    token: Option<String>,
+    /// Serve without a token. Any local process can then run ops, including ones writing files.
+    #[arg(long, conflicts_with = "token")]
+    no_auth: bool,
+    /// Number of worker threads.
+    #[arg(long, default_value_t = 8)]
+    workers: usize,
+    /// Load core configuration from this TOML file instead of `TN_CORE_CONFIG`.
+    #[arg(long, value_name = "FILE")]
+    config: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if let Some(path) = cli.config.as_deref() {
+        match CoreConfig::from_toml_file(path) {
+            Ok(loaded) => config::set(loaded),
+            Err(err) => {
+                eprintln!("tn-core-daemon: {}", err);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let listen = Listen::Tcp(cli.listen);
+    #[cfg(unix)]
+    let listen = cli.unix.map(Listen::Unix).unwrap_or(listen);
+    let token = if cli.no_auth {
+        eprintln!("tn-core-daemon: serving without authentication");
+        None
+    } else {
+        let configured = cli
+            .token
+            .or_else(|| std::env::var(TOKEN_ENV).ok())
+            .filter(|token| !token.is_empty());
+        Some(configured.unwrap_or_else(|| {
+            let token = daemon::generate_token();
+            eprintln!("tn-core-daemon: token {}", token);
+            token
+        }))
+    };
+
+    eprintln!("tn-core-daemon: listening on {:?}", listen);
+    match daemon::serve(DaemonConfig {
+        listen,
+        token,
+        allow_unauthenticated: cli.no_auth,
+        workers: cli.workers,
+    }) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("tn-core-daemon: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
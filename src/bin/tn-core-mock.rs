@@ -0,0 +1,44 @@
+//! `tn-core-mock`: serves the mock Fanqie endpoints from `tomato_novel_network_core::mock`.
+//!
+//! Prints an `[endpoints]` section to point a core configuration file at the server. Faults are
+//! injected at runtime through `POST /__mock/faults`.
+
+use std::process::ExitCode;
+
+use clap::Parser;
+use tomato_novel_network_core::mock::{self, MockServer};
+
+#[derive(Parser)]
+#[command(
+    name = "tn-core-mock",
+    version,
+    about = "Serve canned Fanqie responses for local testing"
+)]
+struct Cli {
+    /// TCP address to listen on.
+    #[arg(long, default_value = "127.0.0.1:7879", value_name = "ADDR")]
+    listen: String,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let server = match MockServer::bind(&cli.listen) {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("tn-core-mock: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    eprintln!("tn-core-mock: listening on {}", server.url());
+    let endpoints = toml::to_string(&server.endpoints()).unwrap_or_default();
+    println!("[endpoints]\n{}", endpoints);
+    println!("# book_id = \"{}\"", mock::BOOK_ID);
+    println!("# releases = \"{}\"", server.url_for(mock::RELEASES));
+    println!(
+        "# release public key = \"{}\"",
+        MockServer::release_public_key()
+    );
+    server.wait();
+    ExitCode::SUCCESS
+}
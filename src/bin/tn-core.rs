@@ -0,0 +1,654 @@
+//! `tn-core`: command-line front end for the core operations.
+//!
+//! Every op subcommand builds a JSON payload and runs it through the same operation registry as
+//! `tn_core_call`. A base payload can be given with `--json` (inline, or `-` to read stdin);
+//! flags are applied on top of it, so anything without a dedicated flag can still be set.
+//!
+//! `tn-core rpc` switches to JSON-RPC 2.0 over stdin/stdout instead (see
+//! `tomato_novel_network_core::rpc`).
+//!
+//! Exit codes: 0 success, 1 operation failure (or an HTTP error status from `http`), 2 usage
+//! error, 3 payload validation error, 4 unknown operation, 5 local I/O or network failure.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STD;
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use tomato_novel_network_core::blocking::Client;
+use tomato_novel_network_core::config::{self, CoreConfig};
+use tomato_novel_network_core::headers::{HeaderMap, HeaderName, HeaderValue};
+use tomato_novel_network_core::{CoreError, ErrorKind, Method, ops, rpc};
+
+const EXIT_OPERATION: u8 = 1;
+const EXIT_VALIDATION: u8 = 3;
+const EXIT_UNKNOWN_OPERATION: u8 = 4;
+const EXIT_LOCAL: u8 = 5;
+
+#[derive(Parser)]
+#[command(
+    name = "tn-core",
+    version,
+    about = "Run tomato-novel network core operations"
+)]
+struct Cli {
+    /// Base JSON payload, inline or `-` to read it from stdin. Flags override its fields.
+    #[arg(long, global = true, value_name = "JSON|-")]
+    json: Option<String>,
+    /// Print compact JSON instead of pretty-printed JSON.
+    #[arg(long, global = true)]
+    raw: bool,
+    /// Load core configuration from this TOML file instead of `TN_CORE_CONFIG`.
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run any registered operation by name with the `--json` payload.
+    Call { op: String },
+    /// List registered operations.
+    Ops,
+    /// Show the request and response schemas of an operation, or of all of them.
+    Describe { op: Option<String> },
+    /// Search the catalogue.
+    Search {
+        query: Option<String>,
+        #[command(flatten)]
+        identity: IdentityArgs,
+    },
+    /// Fetch the chapter directory of a book.
+    Directory {
+        book_id: Option<String>,
+        #[command(flatten)]
+        identity: IdentityArgs,
+    },
+    /// Chapter comments.
+    #[command(subcommand)]
+    Comments(CommentsCommand),
+    /// Media downloads.
+    #[command(subcommand)]
+    Media(MediaCommand),
+    /// Resolve the file name served by a download URL.
+    Version { url: Option<String> },
+    /// Install ID registration, activation and pool management.
+    #[command(subcommand)]
+    Iid(IidCommand),
+    /// Signed chapter session requests.
+    #[command(subcommand)]
+    SignedSession(SignedSessionCommand),
+    /// Send a raw HTTP request through the core HTTP client.
+    Http(HttpArgs),
+    /// Serve newline-delimited JSON-RPC 2.0 on stdin/stdout until stdin closes.
+    Rpc,
+}
+
+#[derive(Args)]
+struct IdentityArgs {
+    #[arg(long)]
+    install_id: Option<String>,
+    /// Device profile to present; see `tn-core call profile_list`.
+    #[arg(long)]
+    profile: Option<String>,
+    #[arg(long)]
+    aid: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum CommentsCommand {
+    /// Fetch comment statistics for a chapter.
+    Stats {
+        chapter_id: Option<String>,
+        #[arg(long)]
+        item_version: Option<String>,
+        #[command(flatten)]
+        identity: IdentityArgs,
+    },
+    /// Fetch the comment list for a chapter.
+    List {
+        chapter_id: Option<String>,
+        #[arg(long)]
+        count: Option<u64>,
+        #[arg(long)]
+        sort: Option<i32>,
+        #[arg(long)]
+        comment_source: Option<i32>,
+        #[arg(long)]
+        comment_type: Option<i32>,
+        #[arg(long)]
+        group_type: Option<i32>,
+        /// `business_param` as JSON.
+        #[arg(long, value_name = "JSON")]
+        business_param: Option<String>,
+        #[command(flatten)]
+        identity: IdentityArgs,
+    },
+}
+
+#[derive(Subcommand)]
+enum MediaCommand {
+    /// Download a single media file.
+    Fetch {
+        url: Option<String>,
+        /// Write the body to this file instead of printing it base64-encoded.
+        #[arg(long, short, value_name = "FILE")]
+        out: Option<String>,
+        #[arg(long)]
+        referer: Option<String>,
+        #[arg(long)]
+        profile: Option<String>,
+        #[arg(long)]
+        timeout_ms: Option<u64>,
+        /// Bypass the media cache.
+        #[arg(long)]
+        no_cache: bool,
+    },
+    /// Download many media files to disk; items come from `--json`.
+    FetchMany {
+        #[arg(long)]
+        concurrency: Option<usize>,
+        #[arg(long)]
+        profile: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum IidCommand {
+    /// Register a new install ID.
+    Register {
+        #[arg(long, value_name = "BASE64")]
+        body_b64: Option<String>,
+        /// Read the register body from this file.
+        #[arg(long, value_name = "FILE", conflicts_with = "body_b64")]
+        body_file: Option<String>,
+        #[arg(long)]
+        profile: Option<String>,
+    },
+    /// Activate a registered install ID.
+    Activate {
+        #[arg(long)]
+        tt_info: Option<String>,
+        #[arg(long)]
+        aid: Option<String>,
+        #[arg(long)]
+        profile: Option<String>,
+    },
+    /// Install ID pool management.
+    #[command(subcommand)]
+    Pool(PoolCommand),
+}
+
+#[derive(Subcommand)]
+enum PoolCommand {
+    /// Show health and usage of every pooled install ID.
+    Status,
+    /// Pick the next healthy install ID.
+    Acquire,
+    /// Add install IDs to the pool.
+    Add { install_ids: Vec<String> },
+    /// Remove install IDs from the pool.
+    Remove { install_ids: Vec<String> },
+    /// Report the HTTP status seen for an install ID.
+    Report { install_id: String, status: u16 },
+}
+
+#[derive(Subcommand)]
+enum SignedSessionCommand {
+    /// Register a key for signed chapter requests.
+    RegisterKey {
+        /// Registration body as JSON.
+        #[arg(long, value_name = "JSON")]
+        body: Option<String>,
+        #[command(flatten)]
+        identity: IdentityArgs,
+    },
+    /// Fetch a chapter batch with a prepared signed query.
+    BatchFull {
+        #[arg(long)]
+        query: Option<String>,
+        /// Extra request header, `name: value`; repeatable.
+        #[arg(long = "header", short = 'H', value_name = "NAME: VALUE")]
+        headers: Vec<String>,
+        #[arg(long)]
+        profile: Option<String>,
+    },
+    /// Fetch raw chapter bodies one by one.
+    BatchRequest {
+        chapter_ids: Vec<String>,
+        #[arg(long)]
+        profile: Option<String>,
+    },
+}
+
+#[derive(Args)]
+struct HttpArgs {
+    method: String,
+    url: String,
+    /// Request header, `name: value`; repeatable.
+    #[arg(long = "header", short = 'H', value_name = "NAME: VALUE")]
+    headers: Vec<String>,
+    /// Request body sent as-is.
+    #[arg(long, short)]
+    data: Option<String>,
+    #[arg(long)]
+    timeout_ms: Option<u64>,
+}
+
+enum Failure {
+    Core(CoreError),
+    Local(String),
+}
+
+impl From<CoreError> for Failure {
+    fn from(err: CoreError) -> Self {
+        Self::Core(err)
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Self::Local(message)
+    }
+}
+
+/// What a subcommand produced: a value to print, if any, and whether it counts as a failure.
+struct Outcome {
+    value: Option<Value>,
+    failed: bool,
+}
+
+impl Outcome {
+    /// For subcommands that already wrote their own output.
+    fn quiet() -> Self {
+        Self {
+            value: None,
+            failed: false,
+        }
+    }
+}
+
+impl From<Value> for Outcome {
+    fn from(value: Value) -> Self {
+        Self {
+            value: Some(value),
+            failed: false,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let raw = cli.raw;
+    match run(cli) {
+        Ok(outcome) => {
+            if let Some(value) = &outcome.value {
+                print_json(value, raw);
+            }
+            if outcome.failed {
+                ExitCode::from(EXIT_OPERATION)
+            } else {
+                ExitCode::SUCCESS
+            }
+        }
+        Err(Failure::Core(err)) => {
+            let mut body = json!({ "error": err.message, "error_kind": err.kind });
+            if !err.fields.is_empty() {
+                body["errors"] = json!(err.fields);
+            }
+            eprintln_json(&body, raw);
+            ExitCode::from(match err.kind {
+                ErrorKind::Validation => EXIT_VALIDATION,
+                ErrorKind::UnknownOperation => EXIT_UNKNOWN_OPERATION,
+                ErrorKind::Operation => EXIT_OPERATION,
+            })
+        }
+        Err(Failure::Local(message)) => {
+            eprintln_json(&json!({ "error": message }), raw);
+            ExitCode::from(EXIT_LOCAL)
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<Outcome, Failure> {
+    if let Some(path) = cli.config.as_deref() {
+        config::set(CoreConfig::from_toml_file(path)?);
+    }
+    let mut payload = base_payload(cli.json.as_deref())?;
+    let p = &mut payload;
+    let outcome = match cli.command {
+        Command::Call { op } => call(&op, p)?,
+        Command::Ops => json!(ops::operation_names()),
+        Command::Describe { op } => {
+            set(p, "op", op);
+            call("describe_op", p)?
+        }
+        Command::Search { query, identity } => {
+            set(p, "query", query);
+            set_identity(p, identity);
+            call("search_books", p)?
+        }
+        Command::Directory { book_id, identity } => {
+            set(p, "book_id", book_id);
+            set_identity(p, identity);
+            call("book_directory_detail", p)?
+        }
+        Command::Comments(command) => comments(command, p)?,
+        Command::Media(command) => media(command, p)?,
+        Command::Version { url } => {
+            set(p, "url", url);
+            call("version_fetch_filename", p)?
+        }
+        Command::Iid(command) => iid(command, p)?,
+        Command::SignedSession(command) => signed_session(command, p)?,
+        Command::Http(args) => return http(args),
+        Command::Rpc => {
+            rpc::serve_stdio().map_err(|err| err.to_string())?;
+            return Ok(Outcome::quiet());
+        }
+    };
+    Ok(outcome.into())
+}
+
+fn comments(command: CommentsCommand, p: &mut Map<String, Value>) -> Result<Value, Failure> {
+    match command {
+        CommentsCommand::Stats {
+            chapter_id,
+            item_version,
+            identity,
+        } => {
+            set(p, "chapter_id", chapter_id);
+            set(p, "item_version", item_version);
+            set_identity(p, identity);
+            call("review_comment_stats", p)
+        }
+        CommentsCommand::List {
+            chapter_id,
+            count,
+            sort,
+            comment_source,
+            comment_type,
+            group_type,
+            business_param,
+            identity,
+        } => {
+            set(p, "chapter_id", chapter_id);
+            set(p, "count", count);
+            set(p, "sort", sort);
+            set(p, "comment_source", comment_source);
+            set(p, "comment_type", comment_type);
+            set(p, "group_type", group_type);
+            set(
+                p,
+                "business_param",
+                parse_json_arg("business_param", business_param)?,
+            );
+            set_identity(p, identity);
+            call("review_comment_list", p)
+        }
+    }
+}
+
+fn media(command: MediaCommand, p: &mut Map<String, Value>) -> Result<Value, Failure> {
+    match command {
+        MediaCommand::Fetch {
+            url,
+            out,
+            referer,
+            profile,
+            timeout_ms,
+            no_cache,
+        } => {
+            set(p, "url", url);
+            set(p, "referer", referer);
+            set(p, "profile", profile);
+            set(p, "timeout_ms", timeout_ms);
+            if no_cache {
+                set(p, "cache", Some(false));
+            }
+            let mut value = call("media_fetch", p)?;
+            if let (Some(path), Some(object)) = (out, value.as_object_mut()) {
+                let body = object
+                    .remove("body_b64")
+                    .and_then(|body| body.as_str().map(str::to_string))
+                    .unwrap_or_default();
+                let bytes = BASE64_STD.decode(body).map_err(|err| err.to_string())?;
+                fs::write(&path, bytes).map_err(|err| format!("{}: {}", path, err))?;
+                object.insert("dest_path".to_string(), Value::String(path));
+            }
+            Ok(value)
+        }
+        MediaCommand::FetchMany {
+            concurrency,
+            profile,
+        } => {
+            set(p, "concurrency", concurrency);
+            set(p, "profile", profile);
+            call("media_fetch_many", p)
+        }
+    }
+}
+
+fn iid(command: IidCommand, p: &mut Map<String, Value>) -> Result<Value, Failure> {
+    match command {
+        IidCommand::Register {
+            body_b64,
+            body_file,
+            profile,
+        } => {
+            let body_b64 = match body_file {
+                Some(path) => {
+                    let bytes = fs::read(&path).map_err(|err| format!("{}: {}", path, err))?;
+                    Some(BASE64_STD.encode(bytes))
+                }
+                None => body_b64,
+            };
+            set(p, "body_b64", body_b64);
+            set(p, "profile", profile);
+            call("iid_register", p)
+        }
+        IidCommand::Activate {
+            tt_info,
+            aid,
+            profile,
+        } => {
+            set(p, "tt_info", tt_info);
+            set(p, "aid", aid);
+            set(p, "profile", profile);
+            call("iid_activate", p)
+        }
+        IidCommand::Pool(PoolCommand::Status) => call("iid_pool_status", p),
+        IidCommand::Pool(PoolCommand::Acquire) => call("iid_pool_acquire", p),
+        IidCommand::Pool(PoolCommand::Add { install_ids }) => {
+            set(p, "install_ids", non_empty(install_ids));
+            call("iid_pool_add", p)
+        }
+        IidCommand::Pool(PoolCommand::Remove { install_ids }) => {
+            set(p, "install_ids", non_empty(install_ids));
+            call("iid_pool_remove", p)
+        }
+        IidCommand::Pool(PoolCommand::Report { install_id, status }) => {
+            set(p, "install_id", Some(install_id));
+            set(p, "status", Some(status));
+            call("iid_pool_report", p)
+        }
+    }
+}
+
+fn signed_session(
+    command: SignedSessionCommand,
+    p: &mut Map<String, Value>,
+) -> Result<Value, Failure> {
+    match command {
+        SignedSessionCommand::RegisterKey { body, identity } => {
+            set(p, "body", parse_json_arg("body", body)?);
+            set_identity(p, identity);
+            call("signed_session_register_key", p)
+        }
+        SignedSessionCommand::BatchFull {
+            query,
+            headers,
+            profile,
+        } => {
+            set(p, "query", query);
+            // The op requires `headers`; the CLI treats a missing map as empty.
+            let entry = p
+                .entry("headers")
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(existing) = entry {
+                for (name, value) in parse_headers(&headers)? {
+                    existing.insert(name, Value::String(value));
+                }
+            }
+            set(p, "profile", profile);
+            call("signed_session_batch_full", p)
+        }
+        SignedSessionCommand::BatchRequest {
+            chapter_ids,
+            profile,
+        } => {
+            set(p, "chapter_ids", non_empty(chapter_ids));
+            set(p, "profile", profile);
+            call("signed_session_batch_request", p)
+        }
+    }
+}
+
+/// Sends a raw request; an HTTP error status is printed like any response but exits with 1.
+fn http(args: HttpArgs) -> Result<Outcome, Failure> {
+    let method = args
+        .method
+        .to_ascii_uppercase()
+        .parse::<Method>()
+        .map_err(|err| err.to_string())?;
+    let mut builder = Client::builder();
+    if let Some(ms) = args.timeout_ms {
+        builder = builder.timeout(Duration::from_millis(ms));
+    }
+    let client = builder.build().map_err(|err| err.to_string())?;
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in parse_headers(&args.headers)? {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|err| err.to_string())?;
+        let value = HeaderValue::from_str(&value).map_err(|err| err.to_string())?;
+        headers.append(name, value);
+    }
+    let mut request = client.request(method, &args.url).headers(headers);
+    if let Some(data) = args.data {
+        request = request.body(data);
+    }
+    let response = request.send().map_err(|err| err.to_string())?;
+
+    let status = response.status();
+    let url = response.url().to_string();
+    let headers: HashMap<String, String> = response
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let bytes = response.bytes().map_err(|err| err.to_string())?;
+    let body = if let Ok(value) = serde_json::from_slice::<Value>(&bytes) {
+        json!({ "body": value })
+    } else if let Ok(text) = std::str::from_utf8(&bytes) {
+        json!({ "body": text })
+    } else {
+        json!({ "body_b64": BASE64_STD.encode(&bytes) })
+    };
+    let mut value = json!({ "status": status.as_u16(), "url": url, "headers": headers });
+    if let (Some(object), Value::Object(body)) = (value.as_object_mut(), body) {
+        object.extend(body);
+    }
+    Ok(Outcome {
+        value: Some(value),
+        failed: status.is_client_error() || status.is_server_error(),
+    })
+}
+
+fn call(op: &str, payload: &Map<String, Value>) -> Result<Value, Failure> {
+    let bytes = serde_json::to_vec(payload).map_err(|err| err.to_string())?;
+    Ok(ops::call_operation(op, &bytes)?)
+}
+
+fn base_payload(json: Option<&str>) -> Result<Map<String, Value>, Failure> {
+    let text = match json {
+        None => return Ok(Map::new()),
+        Some("-") => {
+            let mut text = String::new();
+            io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|err| format!("stdin: {}", err))?;
+            text
+        }
+        Some(text) => text.to_string(),
+    };
+    if text.trim().is_empty() {
+        return Ok(Map::new());
+    }
+    match serde_json::from_str(&text).map_err(|err| format!("--json: {}", err))? {
+        Value::Object(map) => Ok(map),
+        _ => Err(Failure::Local(
+            "--json: payload must be a JSON object".to_string(),
+        )),
+    }
+}
+
+fn set<T: Serialize>(payload: &mut Map<String, Value>, key: &str, value: Option<T>) {
+    if let Some(value) = value.and_then(|value| serde_json::to_value(value).ok()) {
+        payload.insert(key.to_string(), value);
+    }
+}
+
+fn set_identity(payload: &mut Map<String, Value>, identity: IdentityArgs) {
+    set(payload, "install_id", identity.install_id);
+    set(payload, "profile", identity.profile);
+    set(payload, "aid", identity.aid);
+}
+
+fn non_empty<T>(values: Vec<T>) -> Option<Vec<T>> {
+    (!values.is_empty()).then_some(values)
+}
+
+fn parse_json_arg(name: &str, value: Option<String>) -> Result<Option<Value>, Failure> {
+    value
+        .map(|text| serde_json::from_str(&text).map_err(|err| format!("--{}: {}", name, err)))
+        .transpose()
+        .map_err(Failure::Local)
+}
+
+fn parse_headers(headers: &[String]) -> Result<Vec<(String, String)>, Failure> {
+    headers
+        .iter()
+        .map(|header| match header.split_once(':') {
+            Some((name, value)) => Ok((name.trim().to_string(), value.trim().to_string())),
+            None => Err(Failure::Local(format!(
+                "header must look like `name: value`: {}",
+                header
+            ))),
+        })
+        .collect()
+}
+
+fn render(value: &Value, raw: bool) -> String {
+    let rendered = if raw {
+        serde_json::to_string(value)
+    } else {
+        serde_json::to_string_pretty(value)
+    };
+    rendered.unwrap_or_else(|err| json!({ "error": err.to_string() }).to_string())
+}
+
+// Write errors (e.g. a closed pipe) are ignored; the exit code still reports the outcome.
+fn print_json(value: &Value, raw: bool) {
+    let _ = writeln!(io::stdout().lock(), "{}", render(value, raw));
+}
+
+fn eprintln_json(value: &Value, raw: bool) {
+    let _ = writeln!(io::stderr().lock(), "{}", render(value, raw));
+}
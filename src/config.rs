@@ -0,0 +1,659 @@
+//! Central configuration for endpoints, user agents, timeouts and proxy settings.
+//!
+//! The effective configuration is built once from the defaults below, an optional TOML file
+//! named by `TN_CORE_CONFIG`, and `TN_CORE_*` environment overrides. It can then be changed at
+//! runtime with [`update`] (or `tn_core_configure` over FFI). Op payload fields always take
+//! precedence over anything configured here.
+//!
+//! Device profiles bundle the identity a request presents (user agent, Accept-Language,
+//! platform headers and aid). Install IDs are bound to one profile each; see
+//! `api::profiles`.
+//!
+//! Environment overrides mirror the TOML layout: `TN_CORE_AID` sets `aid`, and
+//! `TN_CORE_TIMEOUTS_SEARCH_MS` sets `timeouts.search_ms`. Setting `TN_CORE_RECORDING_PATH`
+//! turns on HAR recording of all upstream traffic, and `TN_CORE_REPLAY_SOURCE` answers it from
+//! such a recording instead.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use reqwest::blocking::ClientBuilder;
+use reqwest::{Certificate, Proxy};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+const ENV_FILE: &str = "TN_CORE_CONFIG";
+const ENV_PREFIX: &str = "TN_CORE_";
+
+const DEFAULT_AID: &str = "1967";
+const DEFAULT_PROFILE: &str = "desktop_web";
+const ACCEPT_LANGUAGE_ZH: &str = "zh-CN,zh;q=0.9";
+const DESKTOP_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0 Safari/537.36";
+const ANDROID_USER_AGENT: &str = "Mozilla/5.0 (Linux; Android 13; Pixel 7 Build/TQ3A.230901.001; wv) AppleWebKit/537.36 (KHTML, like Gecko) Version/4.0 Chrome/124.0.6367.82 Mobile Safari/537.36";
+const SCRIPT_USER_AGENT: &str = "python-requests/2.31.0";
+const UPDATER_USER_AGENT: &str = "tomato-novel-network-core";
+
+static CONFIG: Lazy<RwLock<Arc<CoreConfig>>> =
+    Lazy::new(|| RwLock::new(Arc::new(CoreConfig::from_environment())));
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CoreConfig {
+    /// App ID sent as `aid` by every op that takes one.
+    pub aid: String,
+    pub endpoints: Endpoints,
+    /// Profile used for install IDs that are not bound yet and for calls without one.
+    pub default_profile: String,
+    pub profiles: HashMap<String, DeviceProfile>,
+    pub user_agents: UserAgents,
+    pub timeouts: Timeouts,
+    pub proxy: Option<ProxyConfig>,
+    pub recording: Recording,
+    pub replay: Replay,
+    pub release_signing: ReleaseSigning,
+}
+
+/// Default URLs for ops whose payload leaves the URL out. Empty means "must be given per call".
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Endpoints {
+    pub device_register: String,
+    pub device_activate: String,
+    pub directory: String,
+    /// Prefix of the public book page, used as referer and for warm-up requests.
+    pub book_page: String,
+    pub search: String,
+    pub comment_stats: String,
+    pub comment_list: String,
+    pub register_key: String,
+    pub batch_full: String,
+    pub batch_request: String,
+}
+
+/// The identity a request presents to the upstream service.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DeviceProfile {
+    pub user_agent: String,
+    pub accept_language: String,
+    /// Extra platform headers, e.g. `sec-ch-ua-platform` for desktop browsers.
+    pub headers: HashMap<String, String>,
+    /// App ID for this profile; falls back to the top-level `aid`.
+    pub aid: Option<String>,
+}
+
+/// Identities that are not tied to a device, such as the updater.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UserAgents {
+    /// Identity used for update checks and release downloads.
+    pub updater: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Timeouts {
+    pub iid_ms: u64,
+    pub directory_ms: u64,
+    pub search_ms: u64,
+    pub reviews_ms: u64,
+    pub signed_session_ms: u64,
+    pub media_ms: u64,
+    pub version_ms: u64,
+    pub update_ms: u64,
+    /// Connect timeout for release downloads; the transfer itself is unbounded by default.
+    pub release_connect_ms: u64,
+}
+
+/// Traffic recording: every upstream request and response is appended to a HAR 1.2 file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Recording {
+    /// HAR file to append to; recording is off while this is empty.
+    pub path: String,
+    /// Mask `Cookie` and `Set-Cookie` headers.
+    pub redact_cookies: bool,
+    /// Mask install IDs, device IDs and similar values in URLs, headers and bodies.
+    pub redact_install_ids: bool,
+    /// Mask `Authorization` and token headers.
+    pub redact_auth: bool,
+    /// Truncate recorded bodies to this many bytes; 0 keeps them whole, up to 16 MiB.
+    pub max_body_bytes: u64,
+}
+
+/// Offline replay: upstream requests are answered from recorded traffic instead of the network.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Replay {
+    /// HAR file, or directory of `.har` and `.json` fixture files; replay is off while empty.
+    pub source: String,
+    pub mode: ReplayMode,
+    /// Require the request body to match as well as method, URL and query.
+    pub match_body: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayMode {
+    /// Requests without a recorded match fail.
+    #[default]
+    Strict,
+    /// Requests without a recorded match go to the network.
+    Passthrough,
+}
+
+/// Pinned key release downloads are verified against when the payload does not name one.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ReleaseSigning {
+    /// Minisign public key (base64 or `.pub` file contents), or Ed25519 key as hex or base64.
+    pub public_key: String,
+    pub signature_kind: SignatureKind,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureKind {
+    /// Prehashed minisign signature, served as `<asset>.minisig` by default.
+    #[default]
+    Minisign,
+    /// Plain Ed25519 (RFC 8032) signature over the file, as made by `openssl pkeyutl`, raw or as
+    /// hex or base64, served as `<asset>.sig` by default.
+    Ed25519,
+    /// Ed25519ph (RFC 8032, SHA-512 prehash) signature, in the same encodings and location as
+    /// [`SignatureKind::Ed25519`].
+    Ed25519ph,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    pub url: String,
+    pub ca_cert_path: Option<String>,
+    pub accept_invalid_certs: bool,
+    pub http1_only: bool,
+}
+
+impl Default for CoreConfig {
+    fn default() -> Self {
+        Self {
+            aid: DEFAULT_AID.to_string(),
+            endpoints: Endpoints::default(),
+            default_profile: DEFAULT_PROFILE.to_string(),
+            profiles: default_profiles(),
+            user_agents: UserAgents::default(),
+            timeouts: Timeouts::default(),
+            proxy: None,
+            recording: Recording::default(),
+            replay: Replay::default(),
+            release_signing: ReleaseSigning::default(),
+        }
+    }
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            device_register: String::new(),
+            device_activate: String::new(),
+            directory: "https://fanqienovel.com/api/reader/directory/detail".to_string(),
+            book_page: "https://fanqienovel.com/page/".to_string(),
+            search: String::new(),
+            comment_stats: String::new(),
+            comment_list: String::new(),
+            register_key: String::new(),
+            batch_full: String::new(),
+            batch_request: String::new(),
+        }
+    }
+}
+
+impl Default for UserAgents {
+    fn default() -> Self {
+        Self {
+            updater: UPDATER_USER_AGENT.to_string(),
+        }
+    }
+}
+
+fn default_profiles() -> HashMap<String, DeviceProfile> {
+    let profile = |user_agent: &str, headers: &[(&str, &str)]| DeviceProfile {
+        user_agent: user_agent.to_string(),
+        accept_language: ACCEPT_LANGUAGE_ZH.to_string(),
+        headers: headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        aid: None,
+    };
+    HashMap::from([
+        (
+            "desktop_web".to_string(),
+            profile(
+                DESKTOP_USER_AGENT,
+                &[
+                    ("sec-ch-ua-platform", "\"Windows\""),
+                    ("sec-ch-ua-mobile", "?0"),
+                ],
+            ),
+        ),
+        (
+            "android_app".to_string(),
+            profile(
+                ANDROID_USER_AGENT,
+                &[
+                    ("sec-ch-ua-platform", "\"Android\""),
+                    ("sec-ch-ua-mobile", "?1"),
+                    ("x-requested-with", "com.dragon.read"),
+                ],
+            ),
+        ),
+        ("script".to_string(), profile(SCRIPT_USER_AGENT, &[])),
+    ])
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            redact_cookies: true,
+            redact_install_ids: true,
+            redact_auth: true,
+            max_body_bytes: 0,
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            iid_ms: 10_000,
+            directory_ms: 15_000,
+            search_ms: 12_000,
+            reviews_ms: 12_000,
+            signed_session_ms: 15_000,
+            media_ms: 10_000,
+            version_ms: 8_000,
+            update_ms: 10_000,
+            release_connect_ms: 15_000,
+        }
+    }
+}
+
+impl CoreConfig {
+    /// Looks up a profile by name; unknown names are an error so typos do not silently fall
+    /// back to another identity.
+    pub fn profile(&self, name: &str) -> Result<&DeviceProfile, String> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| format!("unknown device profile: {}", name))
+    }
+
+    /// Parses a TOML document and merges it over the defaults, so a file that only adds a
+    /// profile keeps the built-in ones.
+    pub fn from_toml_str(text: &str) -> Result<Self, String> {
+        let table: toml::Table = toml::from_str(text).map_err(|err| err.to_string())?;
+        let patch = serde_json::to_value(table).map_err(|err| err.to_string())?;
+        Self::default().merged(&patch)
+    }
+
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path.as_ref())
+            .map_err(|err| format!("{}: {}", path.as_ref().display(), err))?;
+        Self::from_toml_str(&text)
+    }
+
+    /// Defaults, then the file named by `TN_CORE_CONFIG`, then `TN_CORE_*` overrides. A broken
+    /// file or override is logged and skipped rather than failing every call.
+    pub fn from_environment() -> Self {
+        let base = match std::env::var(ENV_FILE).ok().filter(|path| !path.is_empty()) {
+            Some(path) => Self::from_toml_file(&path).unwrap_or_else(|err| {
+                tracing::warn!(error = %err, "config: ignoring {}", ENV_FILE);
+                Self::default()
+            }),
+            None => Self::default(),
+        };
+        let vars: HashMap<String, String> = std::env::vars_os()
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+            .filter(|(key, _)| key.starts_with(ENV_PREFIX))
+            .collect();
+        base.with_env_overrides(&vars).unwrap_or_else(|err| {
+            tracing::warn!(error = %err, "config: ignoring {}* overrides", ENV_PREFIX);
+            base
+        })
+    }
+
+    /// Returns a copy with the `TN_CORE_*` overrides in `vars` applied. A value that does not
+    /// parse as the setting's type is an error naming the variable.
+    fn with_env_overrides(&self, vars: &HashMap<String, String>) -> Result<Self, String> {
+        let mut value = serde_json::to_value(self).map_err(|err| err.to_string())?;
+        apply_env_overrides(&mut value, ENV_PREFIX, vars)?;
+        serde_json::from_value(value).map_err(|err| err.to_string())
+    }
+
+    /// Returns a copy with `patch` deep-merged over this configuration.
+    pub fn merged(&self, patch: &Value) -> Result<Self, String> {
+        let mut value = serde_json::to_value(self).map_err(|err| err.to_string())?;
+        merge(&mut value, patch);
+        serde_json::from_value(value).map_err(|err| err.to_string())
+    }
+}
+
+/// The effective configuration.
+pub fn current() -> Arc<CoreConfig> {
+    CONFIG.read().unwrap().clone()
+}
+
+/// Replaces the effective configuration.
+pub fn set(config: CoreConfig) {
+    *CONFIG.write().unwrap() = Arc::new(config);
+}
+
+/// Deep-merges a partial configuration (same shape as the TOML file) into the current one and
+/// returns the result. `null` resets optional settings such as `proxy`.
+pub fn update(patch: &Value) -> Result<Arc<CoreConfig>, String> {
+    let mut guard = CONFIG.write().unwrap();
+    let next = Arc::new(guard.merged(patch)?);
+    *guard = next.clone();
+    Ok(next)
+}
+
+/// Re-reads the TOML file and environment, discarding runtime changes.
+pub fn reload() -> Arc<CoreConfig> {
+    let next = Arc::new(CoreConfig::from_environment());
+    *CONFIG.write().unwrap() = next.clone();
+    next
+}
+
+/// Starts a blocking client builder with the given timeout and the configured proxy applied.
+pub(crate) fn client_builder(timeout_ms: u64) -> Result<ClientBuilder, String> {
+    let builder =
+        reqwest::blocking::Client::builder().timeout(Duration::from_millis(timeout_ms.max(1)));
+    apply_proxy(builder, &current())
+}
+
+/// Routes `builder` through the configured proxy. A proxy that cannot be used is an error, so
+/// requests never silently bypass it.
+pub(crate) fn apply_proxy(
+    mut builder: ClientBuilder,
+    config: &CoreConfig,
+) -> Result<ClientBuilder, String> {
+    let Some(proxy) = config.proxy.as_ref().filter(|p| !p.url.is_empty()) else {
+        return Ok(builder);
+    };
+    let all = Proxy::all(&proxy.url).map_err(|err| format!("invalid proxy url: {}", err))?;
+    builder = builder.proxy(all);
+    if let Some(path) = proxy
+        .ca_cert_path
+        .as_deref()
+        .filter(|path| !path.is_empty())
+    {
+        let cert = std::fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|pem| Certificate::from_pem(&pem).map_err(|err| err.to_string()))
+            .map_err(|err| format!("proxy CA certificate {}: {}", path, err))?;
+        builder = builder.add_root_certificate(cert);
+    }
+    if proxy.accept_invalid_certs {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    if proxy.http1_only {
+        builder = builder.http1_only();
+    }
+    Ok(builder)
+}
+
+fn merge(target: &mut Value, patch: &Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch {
+                match target.get_mut(key) {
+                    Some(existing) if existing.is_object() && value.is_object() => {
+                        merge(existing, value)
+                    }
+                    _ => {
+                        target.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (target, patch) => *target = patch.clone(),
+    }
+}
+
+/// Walks the serialized config and replaces each scalar whose `PREFIX_SECTION_KEY` variable is
+/// set, parsing the text according to the type already there.
+fn apply_env_overrides(
+    value: &mut Value,
+    prefix: &str,
+    vars: &HashMap<String, String>,
+) -> Result<(), String> {
+    let Value::Object(map) = value else {
+        return Ok(());
+    };
+    if prefix == ENV_PREFIX {
+        expand_proxy(map, vars);
+    }
+    for (key, field) in map.iter_mut() {
+        let name = format!("{}{}", prefix, key.to_ascii_uppercase());
+        if field.is_object() {
+            apply_env_overrides(field, &format!("{}_", name), vars)?;
+        } else if let Some(text) = vars.get(&name) {
+            *field = parse_env_value(field, text).map_err(|err| format!("{}: {}", name, err))?;
+        }
+    }
+    Ok(())
+}
+
+/// `proxy` is absent by default; `TN_CORE_PROXY` (or any `TN_CORE_PROXY_*`) creates it, with the
+/// bare variable holding the proxy URL.
+fn expand_proxy(map: &mut Map<String, Value>, vars: &HashMap<String, String>) {
+    let proxy_prefix = format!("{}PROXY", ENV_PREFIX);
+    let bare = vars.get(&proxy_prefix).cloned();
+    let wanted = bare.is_some() || vars.keys().any(|key| key.starts_with(&proxy_prefix));
+    if !wanted {
+        return;
+    }
+    if map.get("proxy").is_none_or(Value::is_null)
+        && let Ok(default) = serde_json::to_value(ProxyConfig::default())
+    {
+        map.insert("proxy".to_string(), default);
+    }
+    if let (Some(url), Some(Value::Object(proxy))) = (bare, map.get_mut("proxy")) {
+        proxy.insert("url".to_string(), Value::String(url));
+    }
+}
+
+fn parse_env_value(current: &Value, text: &str) -> Result<Value, String> {
+    match current {
+        Value::Number(_) => text
+            .trim()
+            .parse::<u64>()
+            .map(Value::from)
+            .map_err(|_| format!("expected a whole number, got {:?}", text)),
+        Value::Bool(_) => match text.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(Value::Bool(true)),
+            "0" | "false" | "no" | "off" => Ok(Value::Bool(false)),
+            _ => Err(format!("expected true or false, got {:?}", text)),
+        },
+        _ => Ok(Value::String(text.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_proxy(url: &str, ca_cert_path: Option<&str>) -> CoreConfig {
+        CoreConfig {
+            proxy: Some(ProxyConfig {
+                url: url.to_string(),
+                ca_cert_path: ca_cert_path.map(str::to_string),
+                ..ProxyConfig::default()
+            }),
+            ..CoreConfig::default()
+        }
+    }
+
+    #[test]
+    fn unusable_proxy_settings_are_errors() {
+        let builder = reqwest::blocking::Client::builder;
+        assert!(apply_proxy(builder(), &CoreConfig::default()).is_ok());
+        assert!(apply_proxy(builder(), &with_proxy("http://127.0.0.1:8888", None)).is_ok());
+
+        let err = apply_proxy(builder(), &with_proxy("http://[::1", None)).unwrap_err();
+        assert!(err.starts_with("invalid proxy url"), "{}", err);
+
+        let missing = std::env::temp_dir().join("tn-core-missing-ca.pem");
+        let missing = missing.to_str().unwrap();
+        let err = apply_proxy(
+            builder(),
+            &with_proxy("http://127.0.0.1:8888", Some(missing)),
+        )
+        .unwrap_err();
+        assert!(err.contains(missing), "{}", err);
+    }
+
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn toml_is_merged_over_the_defaults() {
+        let config = CoreConfig::from_toml_str(
+            r#"
+            aid = "2329"
+
+            [timeouts]
+            search_ms = 3000
+
+            [profiles.tablet]
+            user_agent = "Tablet/1.0"
+            aid = "1128"
+            "#,
+        )
+        .unwrap();
+        let defaults = CoreConfig::default();
+        assert_eq!(config.aid, "2329");
+        assert_eq!(config.timeouts.search_ms, 3000);
+        assert_eq!(config.timeouts.update_ms, defaults.timeouts.update_ms);
+        assert_eq!(config.endpoints.directory, defaults.endpoints.directory);
+        assert!(config.recording.redact_cookies);
+        assert_eq!(config.profile("tablet").unwrap().user_agent, "Tablet/1.0");
+        assert_eq!(
+            config.profile("tablet").unwrap().aid.as_deref(),
+            Some("1128")
+        );
+        assert_eq!(
+            config.profile("desktop_web").unwrap().user_agent,
+            DESKTOP_USER_AGENT
+        );
+
+        let err = CoreConfig::from_toml_str("[timeouts]\nsearch_ms = \"soon\"").unwrap_err();
+        assert!(err.contains("invalid type"), "{}", err);
+        assert!(CoreConfig::from_toml_str("aid = ").is_err());
+    }
+
+    #[test]
+    fn env_overrides_follow_the_toml_layout() {
+        let config = CoreConfig::default()
+            .with_env_overrides(&env(&[
+                ("TN_CORE_AID", "2329"),
+                ("TN_CORE_TIMEOUTS_UPDATE_MS", " 2500 "),
+                ("TN_CORE_RECORDING_REDACT_COOKIES", "off"),
+                ("TN_CORE_REPLAY_MODE", "passthrough"),
+                ("TN_CORE_PROXY", "http://127.0.0.1:8888"),
+                ("TN_CORE_PROXY_HTTP1_ONLY", "Yes"),
+                ("TN_CORE_UNKNOWN", "ignored"),
+            ]))
+            .unwrap();
+        assert_eq!(config.aid, "2329");
+        assert_eq!(config.timeouts.update_ms, 2500);
+        assert!(!config.recording.redact_cookies);
+        assert_eq!(config.replay.mode, ReplayMode::Passthrough);
+        let proxy = config.proxy.unwrap();
+        assert_eq!(proxy.url, "http://127.0.0.1:8888");
+        assert!(proxy.http1_only);
+
+        let config = CoreConfig::default()
+            .with_env_overrides(&env(&[("TN_CORE_PROXY_ACCEPT_INVALID_CERTS", "1")]))
+            .unwrap();
+        let proxy = config.proxy.unwrap();
+        assert!(proxy.url.is_empty() && proxy.accept_invalid_certs);
+        assert!(
+            CoreConfig::default()
+                .with_env_overrides(&HashMap::new())
+                .unwrap()
+                .proxy
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn unparsable_env_overrides_name_the_variable() {
+        let cases = [
+            (
+                "TN_CORE_TIMEOUTS_UPDATE_MS",
+                "abc",
+                "expected a whole number",
+            ),
+            (
+                "TN_CORE_TIMEOUTS_UPDATE_MS",
+                "-5",
+                "expected a whole number",
+            ),
+            (
+                "TN_CORE_RECORDING_REDACT_AUTH",
+                "maybe",
+                "expected true or false",
+            ),
+        ];
+        for (name, value, expected) in cases {
+            let err = CoreConfig::default()
+                .with_env_overrides(&env(&[(name, value)]))
+                .unwrap_err();
+            assert!(err.starts_with(name), "{}", err);
+            assert!(err.contains(expected), "{}={}: {}", name, value, err);
+        }
+
+        // Enum values are only checked when the whole configuration is rebuilt.
+        let err = CoreConfig::default()
+            .with_env_overrides(&env(&[("TN_CORE_REPLAY_MODE", "sometimes")]))
+            .unwrap_err();
+        assert!(err.contains("unknown variant"), "{}", err);
+    }
+
+    #[test]
+    fn configure_patches_merge_deeply() {
+        let base = CoreConfig::default()
+            .merged(&serde_json::json!({ "proxy": { "url": "http://127.0.0.1:8888" } }))
+            .unwrap();
+        let patched = base
+            .merged(&serde_json::json!({
+                "timeouts": { "media_ms": 500 },
+                "profiles": { "script": { "user_agent": "curl/8.0" } },
+            }))
+            .unwrap();
+        assert_eq!(patched.timeouts.media_ms, 500);
+        assert_eq!(patched.timeouts.search_ms, base.timeouts.search_ms);
+        let script = patched.profile("script").unwrap();
+        assert_eq!(script.user_agent, "curl/8.0");
+        assert_eq!(script.accept_language, ACCEPT_LANGUAGE_ZH);
+        assert_eq!(patched.proxy.as_ref().unwrap().url, "http://127.0.0.1:8888");
+
+        let reset = patched
+            .merged(&serde_json::json!({ "proxy": null }))
+            .unwrap();
+        assert!(reset.proxy.is_none());
+        let err = base
+            .merged(&serde_json::json!({ "timeouts": { "media_ms": "fast" } }))
+            .unwrap_err();
+        assert!(err.contains("invalid type"), "{}", err);
+    }
+}
@@ -0,0 +1,297 @@
+//! HTTP sidecar exposing the core API to hosts that cannot load the `dylib`.
+//!
+//! Every response body is the same JSON envelope the FFI returns (`ok`, `data`, `error`,
+//! `error_kind`, `errors`). Routes:
+//!
+//! - `POST /ops/{op}`: runs an operation with the request body as payload, like `tn_core_call`.
+//! - `GET /ops` lists operation names; `GET /ops/{op}` describes one, like `tn_core_describe_op`.
+//! - `POST /clients`: `tn_core_create_client`.
+//! - `POST /clients/{handle}/requests`: `tn_core_execute_request`.
+//! - `DELETE /clients/{handle}`: `tn_core_destroy_client`.
+//! - `GET /metrics`: metrics in the Prometheus text format (not the JSON envelope).
+//! - `GET /health`: liveness probe; never requires a token.
+//!
+//! Every route except `/health` requires `Authorization: Bearer <token>` unless the daemon was
+//! started with [`DaemonConfig::allow_unauthenticated`]; [`generate_token`] makes one. Requests
+//! whose `Host` or `Origin` names anything but a loopback address or the listen address are
+//! refused, so web pages cannot reach the daemon through the browser, and `POST` bodies must be
+//! sent as `Content-Type: application/json`.
+
+use std::io::Read;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::rand_core::RngCore;
+use serde_json::Value;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::api::describe;
+use crate::error::{CoreError, ErrorKind};
+use crate::ffi::{self, HandlePayload};
+use crate::{metrics, ops};
+
+const MAX_BODY_BYTES: u64 = 32 * 1024 * 1024;
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Where the daemon accepts connections.
+#[derive(Clone, Debug)]
+pub enum Listen {
+    /// A TCP address such as `127.0.0.1:7878`.
+    Tcp(String),
+    /// A Unix domain socket path; a stale socket file is replaced.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub struct DaemonConfig {
+    pub listen: Listen,
+    /// Bearer token required on every route except `/health`.
+    pub token: Option<String>,
+    /// Serves every route without a token when `token` is `None`; any local process can then
+    /// run ops, including those writing files. [`serve`] refuses to start without either.
+    pub allow_unauthenticated: bool,
+    /// Number of worker threads handling requests.
+    pub workers: usize,
+}
+
+/// A random token suitable for [`DaemonConfig::token`].
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    BASE64_URL.encode(bytes)
+}
+
+/// Binds the listener and serves requests until the process exits.
+pub fn serve(config: DaemonConfig) -> Result<(), String> {
+    if config.token.is_none() && !config.allow_unauthenticated {
+        return Err("no token configured; set one or allow unauthenticated access".to_string());
+    }
+    let listen_host = match &config.listen {
+        Listen::Tcp(addr) => Some(host_name(addr).to_ascii_lowercase()),
+        #[cfg(unix)]
+        Listen::Unix(_) => None,
+    };
+    let server = match &config.listen {
+        Listen::Tcp(addr) => Server::http(addr.as_str()),
+        #[cfg(unix)]
+        Listen::Unix(path) => {
+            if path.exists() {
+                std::fs::remove_file(path).map_err(|err| err.to_string())?;
+            }
+            Server::http_unix(path)
+        }
+    }
+    .map_err(|err| err.to_string())?;
+
+    let server = Arc::new(server);
+    let token = Arc::new(config.token);
+    let listen_host = Arc::new(listen_host);
+    let workers: Vec<_> = (0..config.workers.max(1))
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let token = Arc::clone(&token);
+            let listen_host = Arc::clone(&listen_host);
+            thread::spawn(move || {
+                while let Ok(request) = server.recv() {
+                    handle(request, token.as_deref(), listen_host.as_deref());
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+fn handle(mut request: Request, token: Option<&str>, listen_host: Option<&str>) {
+    let path = request.url().split('?').next().unwrap_or("").to_string();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let method = request.method().clone();
+
+    let mut content_type = "application/json";
+    let (status, body) = if !local_origin(&request, listen_host) {
+        (
+            403,
+            ffi::error_payload("Host and Origin must name a local address"),
+        )
+    } else if segments == ["health"] {
+        (200, ffi::success(Value::Null))
+    } else if !authorized(&request, token) {
+        (401, ffi::error_payload("missing or invalid bearer token"))
+    } else if method == Method::Post && !json_content(&request) {
+        (
+            415,
+            ffi::error_payload("Content-Type must be application/json"),
+        )
+    } else if segments == ["metrics"] && method == Method::Get {
+        content_type = PROMETHEUS_CONTENT_TYPE;
+        (200, metrics::prometheus_text().into_bytes())
+    } else {
+        match read_body(&mut request) {
+            Ok(payload) => route(&method, &segments, &payload),
+            Err((status, message)) => (status, ffi::error_payload(&message)),
+        }
+    };
+
+    let mut response = Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header("Content-Type", content_type));
+    if status == 401 {
+        response = response.with_header(header("WWW-Authenticate", "Bearer"));
+    }
+    let _ = request.respond(response);
+}
+
+fn route(method: &Method, segments: &[&str], payload: &[u8]) -> (u16, Vec<u8>) {
+    match (method, segments) {
+        (Method::Get, ["ops"]) => (200, ffi::success(ops::operation_names())),
+        (Method::Get, ["ops", op]) => match describe::describe(op) {
+            Ok(value) => (200, ffi::success(value)),
+            Err(err) => (404, ffi::error_payload(&err)),
+        },
+        (Method::Post, ["ops", op]) => match crate::api::handle_call(op, payload) {
+            Ok(value) => (200, ffi::success(value)),
+            Err(err) => (core_error_status(&err), ffi::core_error_payload(&err)),
+        },
+        (Method::Post, ["clients"]) => {
+            match serde_json::from_slice(payload)
+                .map_err(|err| err.to_string())
+                .and_then(ffi::register_client)
+            {
+                Ok(handle) => (200, ffi::success(HandlePayload { handle })),
+                Err(err) => (400, ffi::error_payload(&err)),
+            }
+        }
+        (Method::Post, ["clients", handle, "requests"]) => {
+            let Some(handle) = known_handle(handle) else {
+                return (404, ffi::error_payload("invalid client handle"));
+            };
+            match serde_json::from_slice(payload) {
+                Ok(spec) => match ffi::send_request(handle, spec) {
+                    Ok(response) => (200, ffi::success(response)),
+                    Err(err) => (502, ffi::error_payload(&err)),
+                },
+                Err(err) => (400, ffi::error_payload(&err.to_string())),
+            }
+        }
+        (Method::Delete, ["clients", handle]) => {
+            match handle.parse().ok().filter(|h| ffi::destroy_client(*h)) {
+                Some(handle) => (200, ffi::success(HandlePayload { handle })),
+                None => (404, ffi::error_payload("invalid client handle")),
+            }
+        }
+        (_, ["ops", ..] | ["clients", ..] | ["metrics"]) => {
+            (405, ffi::error_payload("method not allowed"))
+        }
+        _ => (404, ffi::error_payload("not found")),
+    }
+}
+
+fn core_error_status(err: &CoreError) -> u16 {
+    match err.kind {
+        ErrorKind::Validation => 400,
+        ErrorKind::UnknownOperation => 404,
+        ErrorKind::Operation => 500,
+    }
+}
+
+fn known_handle(text: &str) -> Option<u64> {
+    text.parse().ok().filter(|handle| ffi::has_client(*handle))
+}
+
+fn authorized(request: &Request, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    header_value(request, "Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|presented| constant_time_eq(presented.trim().as_bytes(), token.as_bytes()))
+}
+
+fn header_value<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+/// Whether `Host` and `Origin`, when present, name a loopback address or the listen address.
+/// Browsers always send the page's own host after DNS rebinding, and `Origin` on cross-origin
+/// requests.
+fn local_origin(request: &Request, listen_host: Option<&str>) -> bool {
+    let allowed = |host: &str| {
+        let host = host.to_ascii_lowercase();
+        is_loopback(&host) || listen_host.is_some_and(|listen| listen == host)
+    };
+    let host_ok = header_value(request, "Host").is_none_or(|value| allowed(host_name(value)));
+    let origin_ok = header_value(request, "Origin").is_none_or(|value| {
+        value
+            .split_once("://")
+            .is_some_and(|(_, rest)| allowed(host_name(rest)))
+    });
+    host_ok && origin_ok
+}
+
+/// The host part of `host[:port]` or `[v6]:port`, without brackets.
+fn host_name(authority: &str) -> &str {
+    let authority = authority.trim();
+    if let Some(rest) = authority.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or_default();
+    }
+    match authority.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => authority,
+    }
+}
+
+fn is_loopback(host: &str) -> bool {
+    host == "localhost"
+        || host.ends_with(".localhost")
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+fn json_content(request: &Request) -> bool {
+    header_value(request, "Content-Type").is_some_and(|value| {
+        value
+            .split(';')
+            .next()
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+    })
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn read_body(request: &mut Request) -> Result<Vec<u8>, (u16, String)> {
+    if request
+        .body_length()
+        .is_some_and(|len| len as u64 > MAX_BODY_BYTES)
+    {
+        return Err((413, "request body too large".to_string()));
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_BYTES + 1)
+        .read_to_end(&mut body)
+        .map_err(|err| (400, err.to_string()))?;
+    if body.len() as u64 > MAX_BODY_BYTES {
+        return Err((413, "request body too large".to_string()));
+    }
+    Ok(body)
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("static header is valid")
+}
@@ -0,0 +1,184 @@
+//! Dry runs: ops called with `dry_run: true` in their payload return the requests they would
+//! send instead of sending them.
+//!
+//! Each request is recorded at the send choke point as the client would put it on the wire: the
+//! client's default headers fill in those the request does not set, along with the `Host`,
+//! `Accept` and `Content-Length` headers reqwest adds itself. The op is handed a failed request in
+//! return, so requests it makes on failure paths (retries, fallbacks) are captured too. Nothing
+//! leaves the process, and ops skip their own side effects, such as rotating the install ID pool.
+//!
+//! Ops that do not talk to the network are not run at all.
+
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STD;
+use reqwest::blocking::{Request, Response};
+use reqwest::header::{ACCEPT, CONTENT_LENGTH, HOST, HeaderMap, HeaderValue, TRANSFER_ENCODING};
+use reqwest::{ResponseBuilderExt, StatusCode, Url};
+use serde_json::{Map, Value, json};
+
+use crate::http::NetworkError;
+
+/// Requests captured so far by the current dry run.
+pub(crate) type Capture = Arc<Mutex<Vec<Value>>>;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Capture>> = const { RefCell::new(None) };
+}
+
+/// Marks the current thread as dry-running until dropped.
+pub(crate) struct DryRunScope {
+    previous: Option<Capture>,
+}
+
+impl Drop for DryRunScope {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+pub(crate) fn enter(capture: Option<Capture>) -> DryRunScope {
+    let previous = CURRENT.with(|current| current.replace(capture));
+    DryRunScope { previous }
+}
+
+/// The current dry run, to carry into worker threads.
+pub(crate) fn current() -> Option<Capture> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+pub(crate) fn active() -> bool {
+    CURRENT.with(|current| current.borrow().is_some())
+}
+
+/// Runs `handle` as a dry run and returns `{dry_run: true, requests: [...]}`. An error the op
+/// hits before sending anything, such as an empty install ID pool, is returned as is.
+pub(crate) fn run(
+    network: bool,
+    handle: impl FnOnce() -> Result<Value, String>,
+) -> Result<Value, String> {
+    if !network {
+        return Ok(json!({ "dry_run": true, "requests": [] }));
+    }
+    let capture = Capture::default();
+    let result = {
+        let _scope = enter(Some(capture.clone()));
+        handle()
+    };
+    let requests = std::mem::take(&mut *capture.lock().unwrap());
+    if requests.is_empty() {
+        result?;
+    }
+    Ok(json!({ "dry_run": true, "requests": requests }))
+}
+
+/// Captures `request` and fails it, if a dry run is active.
+pub(crate) fn intercept(request: Request) -> Outcome {
+    let Some(capture) = current() else {
+        return Outcome::Network(request);
+    };
+    let url = request.url().clone();
+    capture.lock().unwrap().push(record(&request));
+    tracing::debug!(url = %url, "dry run: request captured");
+    Outcome::Captured(Err(abort_error(url)))
+}
+
+pub(crate) enum Outcome {
+    Network(Request),
+    Captured(Result<Response, NetworkError>),
+}
+
+/// Adds the sending client's default headers to `request` where it sets none of its own, as
+/// reqwest does when sending, so dry runs record them.
+pub(crate) fn add_client_headers(request: &mut Request, defaults: &HeaderMap) {
+    if defaults.is_empty() || !active() {
+        return;
+    }
+    let headers = request.headers_mut();
+    for name in defaults.keys() {
+        if !headers.contains_key(name) {
+            for value in defaults.get_all(name) {
+                headers.append(name, value.clone());
+            }
+        }
+    }
+}
+
+/// The error handed to the op in place of a response.
+fn abort_error(url: Url) -> NetworkError {
+    let response = http::Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .url(url)
+        .body(Vec::new())
+        .map(Response::from)
+        .expect("static parts form a valid response");
+    response
+        .error_for_status()
+        .expect_err("503 is an error status")
+}
+
+/// Turns `request` into `{method, url, headers, body}`. A streamed body cannot be read without
+/// consuming it, so it is only flagged with `body_streamed`.
+fn record(request: &Request) -> Value {
+    let url = request.url();
+    let mut headers = request.headers().clone();
+    headers.insert(
+        HOST,
+        HeaderValue::from_str(&authority(url)).expect("URL hosts are valid"),
+    );
+    headers
+        .entry(ACCEPT)
+        .or_insert(HeaderValue::from_static("*/*"));
+    let body = request.body().map(|body| body.as_bytes());
+    match body {
+        Some(Some(bytes)) => {
+            headers
+                .entry(CONTENT_LENGTH)
+                .or_insert(HeaderValue::from(bytes.len()));
+        }
+        Some(None) if !headers.contains_key(CONTENT_LENGTH) => {
+            headers
+                .entry(TRANSFER_ENCODING)
+                .or_insert(HeaderValue::from_static("chunked"));
+        }
+        _ => {}
+    }
+
+    let mut recorded = Map::new();
+    for name in headers.keys() {
+        let values: Vec<String> = headers
+            .get_all(name)
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+            .collect();
+        recorded.insert(name.to_string(), Value::String(values.join(", ")));
+    }
+
+    let mut captured = json!({
+        "method": request.method().as_str(),
+        "url": url.as_str(),
+        "headers": recorded,
+    });
+    match body {
+        None | Some(Some([])) => captured["body"] = Value::Null,
+        Some(Some(bytes)) => match std::str::from_utf8(bytes) {
+            Ok(text) => captured["body"] = json!(text),
+            Err(_) => captured["body_b64"] = json!(BASE64_STD.encode(bytes)),
+        },
+        Some(None) => {
+            captured["body"] = Value::Null;
+            captured["body_streamed"] = json!(true);
+        }
+    }
+    captured
+}
+
+fn authority(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// Broad class of a failed core call, surfaced as `error_kind` in the FFI envelope.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The payload was malformed or failed field validation.
+    Validation,
+    /// No operation is registered under the requested name.
+    UnknownOperation,
+    /// The operation ran and failed.
+    Operation,
+}
+
+impl ErrorKind {
+    /// The name used for this kind in JSON output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Validation => "validation",
+            Self::UnknownOperation => "unknown_operation",
+            Self::Operation => "operation",
+        }
+    }
+}
+
+/// A single problem found while validating a payload.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    /// Path of the offending field, e.g. `url` or `items[2].url`; `$` for the payload itself.
+    pub field: String,
+    pub problem: String,
+}
+
+/// Error returned by core operations.
+#[derive(Clone, Debug)]
+pub struct CoreError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Every field problem found, for [`ErrorKind::Validation`] errors.
+    pub fields: Vec<FieldError>,
+}
+
+impl CoreError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn validation(fields: Vec<FieldError>) -> Self {
+        let summary: Vec<String> = fields
+            .iter()
+            .map(|field| format!("{}: {}", field.field, field.problem))
+            .collect();
+        Self {
+            kind: ErrorKind::Validation,
+            message: format!("invalid payload: {}", summary.join("; ")),
+            fields,
+        }
+    }
+}
+
+impl fmt::Display for CoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CoreError {}
+
+impl From<String> for CoreError {
+    fn from(message: String) -> Self {
+        Self::new(ErrorKind::Operation, message)
+    }
+}
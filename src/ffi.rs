@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::sync::Mutex;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD as BASE64_STD;
@@ -12,7 +12,13 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::api;
+use crate::api::{describe, media_cache, session_store};
+use crate::config;
+use crate::error::{CoreError, ErrorKind, FieldError};
 use crate::http::HttpClient;
+use crate::logging;
+use crate::metrics;
+use crate::replay;
 
 static REGISTRY: Lazy<ClientRegistry> = Lazy::new(ClientRegistry::new);
 
@@ -39,8 +45,8 @@ impl ClientRegistry {
         self.clients.lock().unwrap().get(&handle).cloned()
     }
 
-    fn remove(&self, handle: u64) {
-        self.clients.lock().unwrap().remove(&handle);
+    fn remove(&self, handle: u64) -> bool {
+        self.clients.lock().unwrap().remove(&handle).is_some()
     }
 }
 
@@ -51,7 +57,7 @@ pub struct FfiBuffer {
 }
 
 #[derive(Deserialize)]
-struct ClientConfig {
+pub(crate) struct ClientConfig {
     #[serde(default)]
     default_headers: HashMap<String, String>,
     #[serde(default)]
@@ -66,10 +72,13 @@ struct ClientConfig {
     danger_accept_invalid_certs: Option<bool>,
     #[serde(default)]
     http1_only: Option<bool>,
+    #[cfg(feature = "fault_injection")]
+    #[serde(default)]
+    faults: Option<crate::http::faults::FaultConfig>,
 }
 
 #[derive(Deserialize)]
-struct RequestSpec {
+pub(crate) struct RequestSpec {
     method: String,
     url: String,
     #[serde(default)]
@@ -85,7 +94,7 @@ struct RequestSpec {
 }
 
 #[derive(Serialize)]
-struct ResponsePayload {
+pub(crate) struct ResponsePayload {
     status: u16,
     url: String,
     headers: HashMap<String, String>,
@@ -93,8 +102,8 @@ struct ResponsePayload {
 }
 
 #[derive(Serialize)]
-struct HandlePayload {
-    handle: u64,
+pub(crate) struct HandlePayload {
+    pub(crate) handle: u64,
 }
 
 #[derive(Serialize)]
@@ -103,22 +112,41 @@ struct Envelope<T> {
     #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    error_kind: Option<ErrorKind>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     data: Option<T>,
 }
 
-fn success<T: Serialize>(data: T) -> Vec<u8> {
+pub(crate) fn success<T: Serialize>(data: T) -> Vec<u8> {
     serde_json::to_vec(&Envelope {
         ok: true,
         error: None,
+        error_kind: None,
+        errors: Vec::new(),
         data: Some(data),
     })
     .unwrap_or_else(|err| error_payload(&err.to_string()))
 }
 
-fn error_payload(message: &str) -> Vec<u8> {
+pub(crate) fn error_payload(message: &str) -> Vec<u8> {
     serde_json::to_vec(&Envelope::<Value> {
         ok: false,
         error: Some(message.to_string()),
+        error_kind: None,
+        errors: Vec::new(),
+        data: None,
+    })
+    .unwrap_or_else(|_| Vec::new())
+}
+
+pub(crate) fn core_error_payload(err: &CoreError) -> Vec<u8> {
+    serde_json::to_vec(&Envelope::<Value> {
+        ok: false,
+        error: Some(err.message.clone()),
+        error_kind: Some(err.kind),
+        errors: err.fields.clone(),
         data: None,
     })
     .unwrap_or_else(|_| Vec::new())
@@ -169,7 +197,29 @@ pub unsafe extern "C" fn tn_core_execute_request(
 /// again after destruction.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn tn_core_destroy_client(handle: u64) {
-    REGISTRY.remove(handle);
+    destroy_client(handle);
+}
+
+/// Host function receiving log events: the level (1 error, 2 warn, 3 info, 4 debug, 5 trace) and
+/// `len` bytes of UTF-8 JSON describing the event. The bytes are only valid during the call.
+pub type LogCallback = unsafe extern "C" fn(level: u32, ptr: *const u8, len: usize);
+
+/// Forwards structured log events at or below `level` (1 error .. 5 trace) to `callback`. A null
+/// callback or level 0 turns logging off. Install IDs, cookies and keys are redacted.
+///
+/// # Safety
+/// `callback`, when non-null, must be safe to call from any thread for as long as it stays
+/// installed; it may be invoked concurrently.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tn_core_set_log_callback(level: u32, callback: Option<LogCallback>) {
+    let sink = callback.map(|callback| -> logging::LogSink {
+        Box::new(move |level, event| {
+            if let Ok(bytes) = serde_json::to_vec(event) {
+                unsafe { callback(level, bytes.as_ptr(), bytes.len()) };
+            }
+        })
+    });
+    logging::set_log_sink(level, sink);
 }
 
 /// Releases an FFI buffer that was allocated by this crate and returned to the caller.
@@ -188,6 +238,9 @@ pub unsafe extern "C" fn tn_core_free_buffer(buffer: FfiBuffer) {
 
 /// Invokes a generic API operation and returns the serialized response.
 ///
+/// Failed calls carry an `error_kind`; payload validation failures also list every offending
+/// field under `errors` as `{field, problem}` objects.
+///
 /// # Safety
 /// The caller must ensure both pointer/length pairs reference readable memory for the lifetime of
 /// the call and contain valid UTF-8 for the operation name and binary payload for the request.
@@ -199,13 +252,191 @@ pub unsafe extern "C" fn tn_core_call(
     payload_len: usize,
 ) -> FfiBuffer {
     match core_dispatch(op_ptr, op_len, payload_ptr, payload_len) {
+        Ok(value) => into_buffer(success(value)),
+        Err(err) => into_buffer(core_error_payload(&err)),
+    }
+}
+
+/// Loads the encrypted session store from disk, replacing the in-memory session state.
+///
+/// # Safety
+/// The caller must ensure `ptr` points to `len` readable bytes containing UTF-8 JSON with the
+/// `path` and `passphrase` of the store. The memory must remain accessible for the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tn_core_session_load(ptr: *const u8, len: usize) -> FfiBuffer {
+    match read_json(ptr, len).and_then(session_store::load) {
+        Ok(value) => into_buffer(success(value)),
+        Err(err) => into_buffer(error_payload(&err)),
+    }
+}
+
+/// Encrypts the current session state and writes it to disk.
+///
+/// # Safety
+/// The caller must ensure `ptr` points to `len` readable bytes containing UTF-8 JSON with the
+/// `path` and `passphrase` of the store. The memory must remain accessible for the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tn_core_session_save(ptr: *const u8, len: usize) -> FfiBuffer {
+    match read_json(ptr, len).and_then(session_store::save) {
         Ok(value) => into_buffer(success(value)),
         Err(err) => into_buffer(error_payload(&err)),
     }
 }
 
+/// Zeroes and discards the in-memory session state, the install ID pool and profile bindings.
+///
+/// # Safety
+/// This function has no pointer arguments and is always safe to call; it is `unsafe` only for
+/// consistency with the rest of the exported API.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tn_core_session_clear() {
+    session_store::clear();
+}
+
+/// Returns entry count, byte usage and limits of the on-disk media cache.
+///
+/// # Safety
+/// This function has no pointer arguments and is always safe to call; it is `unsafe` only for
+/// consistency with the rest of the exported API.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tn_core_media_cache_stats() -> FfiBuffer {
+    match media_cache::cache_stats() {
+        Ok(value) => into_buffer(success(value)),
+        Err(err) => into_buffer(error_payload(&err)),
+    }
+}
+
+/// Returns a snapshot of the in-process metrics: per-op request, error, latency, retry and byte
+/// counters under `ops`, and per-client-handle counters under `clients`.
+///
+/// # Safety
+/// This function has no pointer arguments and is always safe to call; it is `unsafe` only for
+/// consistency with the rest of the exported API.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tn_core_metrics() -> FfiBuffer {
+    into_buffer(success(metrics::snapshot()))
+}
+
+/// Purges media cache entries. An empty payload (`len == 0`) removes everything; otherwise the
+/// JSON may name a single `url` or an `older_than_ms` cut-off.
+///
+/// # Safety
+/// When `len` is non-zero the caller must ensure `ptr` points to `len` readable bytes of UTF-8
+/// JSON that stay valid for the duration of the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tn_core_media_cache_purge(ptr: *const u8, len: usize) -> FfiBuffer {
+    let request = if len == 0 {
+        Ok(media_cache::PurgeRequest::default())
+    } else {
+        read_json(ptr, len)
+    };
+    match request.and_then(media_cache::purge) {
+        Ok(value) => into_buffer(success(value)),
+        Err(err) => into_buffer(error_payload(&err)),
+    }
+}
+
+/// Updates the core configuration and returns the effective result. The payload is a partial
+/// configuration, either as a JSON object or as TOML text in the same layout as the
+/// `TN_CORE_CONFIG` file; it is merged over the current settings. An empty payload (`len == 0`)
+/// only returns the current configuration.
+///
+/// # Safety
+/// When `len` is non-zero the caller must ensure `ptr` points to `len` readable bytes of UTF-8
+/// that stay valid for the duration of the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tn_core_configure(ptr: *const u8, len: usize) -> FfiBuffer {
+    let result = if len == 0 {
+        Ok(config::current())
+    } else {
+        read_utf8(ptr, len)
+            .and_then(|text| parse_config_patch(&text))
+            .and_then(|patch| config::update(&patch))
+    };
+    match result {
+        Ok(config) => into_buffer(success(&*config)),
+        Err(err) => into_buffer(error_payload(&err)),
+    }
+}
+
+/// Switches offline replay. The payload is the `replay` configuration section (`source`,
+/// `mode`, `match_body`); the source is loaded up front so a bad path or file fails here rather
+/// than on the next request. An empty payload (`len == 0`) turns replay off. Returns the
+/// effective settings and the number of `recordings` loaded.
+///
+/// # Safety
+/// When `len` is non-zero the caller must ensure `ptr` points to `len` readable bytes of UTF-8
+/// JSON that stay valid for the duration of the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tn_core_set_replay(ptr: *const u8, len: usize) -> FfiBuffer {
+    let settings = if len == 0 {
+        Ok(config::Replay::default())
+    } else {
+        read_json(ptr, len)
+    };
+    match settings.and_then(replay::configure) {
+        Ok(value) => into_buffer(success(value)),
+        Err(err) => into_buffer(error_payload(&err)),
+    }
+}
+
+/// Replaces the global fault injection rules (`seed`, `rules`), which apply to every upstream
+/// request. An empty payload (`len == 0`) clears them. Returns the effective rules. Only
+/// exported with the `fault_injection` feature.
+///
+/// # Safety
+/// When `len` is non-zero the caller must ensure `ptr` points to `len` readable bytes of UTF-8
+/// JSON that stay valid for the duration of the call.
+#[cfg(feature = "fault_injection")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tn_core_set_faults(ptr: *const u8, len: usize) -> FfiBuffer {
+    use crate::http::faults;
+
+    let rules = if len == 0 {
+        Ok(faults::FaultConfig::default())
+    } else {
+        read_json(ptr, len)
+    };
+    match rules.and_then(faults::set) {
+        Ok(()) => into_buffer(success(faults::current())),
+        Err(err) => into_buffer(error_payload(&err)),
+    }
+}
+
+/// Returns metadata and request/response JSON Schemas for one operation, or for every registered
+/// operation when `op_len` is zero.
+///
+/// # Safety
+/// When `op_len` is non-zero the caller must ensure `op_ptr` points to `op_len` readable bytes of
+/// UTF-8 that stay valid for the duration of the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tn_core_describe_op(op_ptr: *const u8, op_len: usize) -> FfiBuffer {
+    let described = if op_len == 0 {
+        describe::describe_all()
+    } else {
+        read_utf8(op_ptr, op_len).and_then(|op| describe::describe(&op))
+    };
+    match described {
+        Ok(value) => into_buffer(success(value)),
+        Err(err) => into_buffer(error_payload(&err)),
+    }
+}
+
+fn parse_config_patch(text: &str) -> Result<Value, String> {
+    if text.trim_start().starts_with('{') {
+        serde_json::from_str(text).map_err(|err| err.to_string())
+    } else {
+        let table: toml::Table = toml::from_str(text).map_err(|err| err.to_string())?;
+        serde_json::to_value(table).map_err(|err| err.to_string())
+    }
+}
+
 fn create_client(ptr: *const u8, len: usize) -> Result<u64, String> {
-    let config: ClientConfig = read_json(ptr, len)?;
+    read_json(ptr, len).and_then(register_client)
+}
+
+/// Builds a client from `config` and returns its handle.
+pub(crate) fn register_client(config: ClientConfig) -> Result<u64, String> {
     let mut builder = HttpClient::builder();
     if !config.default_headers.is_empty() {
         let mut header_map = reqwest::header::HeaderMap::new();
@@ -239,15 +470,58 @@ fn create_client(ptr: *const u8, len: usize) -> Result<u64, String> {
     if config.http1_only.unwrap_or(false) {
         builder = builder.http1_only();
     }
+    #[cfg(feature = "fault_injection")]
+    if let Some(faults) = config.faults {
+        faults.validate()?;
+        builder = builder.faults(faults);
+    }
     let client = builder.build().map_err(|err| err.to_string())?;
     Ok(REGISTRY.insert(client))
 }
 
 fn execute_request(handle: u64, ptr: *const u8, len: usize) -> Result<ResponsePayload, String> {
-    let spec: RequestSpec = read_json(ptr, len)?;
+    read_json(ptr, len).and_then(|spec| send_request(handle, spec))
+}
+
+pub(crate) fn has_client(handle: u64) -> bool {
+    REGISTRY.get(handle).is_some()
+}
+
+/// Drops the client registered as `handle`, returning whether it existed.
+pub(crate) fn destroy_client(handle: u64) -> bool {
+    let removed = REGISTRY.remove(handle);
+    metrics::remove_client(handle);
+    removed
+}
+
+/// Sends `spec` with the client registered as `handle`.
+pub(crate) fn send_request(handle: u64, spec: RequestSpec) -> Result<ResponsePayload, String> {
     let client = REGISTRY
         .get(handle)
         .ok_or_else(|| "invalid client handle".to_string())?;
+    let started = Instant::now();
+    let (mut bytes_sent, mut bytes_received) = (0, 0);
+    let result = perform_request(&client, spec, &mut bytes_sent, &mut bytes_received);
+    let status = result.as_ref().ok().map(|response| response.status);
+    // A handle destroyed mid-request keeps no stats.
+    if has_client(handle) {
+        metrics::record_client(
+            handle,
+            started.elapsed(),
+            status,
+            bytes_sent,
+            bytes_received,
+        );
+    }
+    result
+}
+
+fn perform_request(
+    client: &HttpClient,
+    spec: RequestSpec,
+    bytes_sent: &mut u64,
+    bytes_received: &mut u64,
+) -> Result<ResponsePayload, String> {
     let method = spec
         .method
         .parse::<reqwest::Method>()
@@ -273,9 +547,11 @@ fn execute_request(handle: u64, ptr: *const u8, len: usize) -> Result<ResponsePa
         builder = builder.timeout(Duration::from_millis(ms));
     }
     if let Some(json) = spec.json_body {
+        *bytes_sent = serde_json::to_vec(&json).map_or(0, |body| body.len() as u64);
         builder = builder.json(&json);
     } else if let Some(body_b64) = spec.body_b64 {
         let bytes = BASE64_STD.decode(body_b64).map_err(|err| err.to_string())?;
+        *bytes_sent = bytes.len() as u64;
         builder = builder.body(bytes);
     }
     let response = builder.send().map_err(|err| err.to_string())?;
@@ -288,6 +564,7 @@ fn execute_request(handle: u64, ptr: *const u8, len: usize) -> Result<ResponsePa
         }
     }
     let body = response.bytes().map_err(|err| err.to_string())?;
+    *bytes_received = body.len() as u64;
     let body_b64 = if body.is_empty() {
         None
     } else {
@@ -314,7 +591,7 @@ fn core_dispatch(
     op_len: usize,
     payload_ptr: *const u8,
     payload_len: usize,
-) -> Result<Value, String> {
+) -> Result<Value, CoreError> {
     let op = read_utf8(op_ptr, op_len)?;
     let payload = read_bytes(payload_ptr, payload_len)?;
     api::handle_call(&op, &payload)
@@ -0,0 +1,474 @@
+//! Recording of upstream traffic to a HAR 1.2 file, configured under `recording` in
+//! [`crate::config`].
+//!
+//! Entries are appended in place: the file is kept a complete HAR document after every request,
+//! so it can be opened in browser dev tools or Charles while a session is still running. Response
+//! bodies are recorded as the caller reads them, never buffered ahead of it, keeping at most
+//! `max_body_bytes` (and never more than [`MAX_CAPTURED_BODY`]); a response's entry is appended
+//! once its body has been read to the end or dropped.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STD;
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use once_cell::sync::Lazy;
+use reqwest::ResponseBuilderExt;
+use reqwest::blocking::{Request, Response};
+use reqwest::header::{CONTENT_TYPE, HeaderMap};
+use serde_json::{Value, json};
+
+use crate::config::{self, Recording};
+use crate::http::NetworkError;
+use crate::logging::{SENSITIVE_PARAMS, redact_text};
+use crate::metrics;
+
+const REDACTED: &str = "[redacted]";
+const MAX_CAPTURED_BODY: u64 = 16 * 1024 * 1024;
+const READ_CHUNK: usize = 16 * 1024;
+const AUTH_HEADERS: &[&str] = &["authorization", "proxy-authorization", "x-api-key"];
+const COOKIE_HEADERS: &[&str] = &["cookie", "set-cookie"];
+const TAIL: &[u8] = b"]}}";
+
+/// Serialises appends so concurrent requests cannot interleave entries.
+static WRITER: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// A request captured before sending, completed into a HAR entry once the response is in.
+pub(crate) struct PendingEntry {
+    settings: Recording,
+    started: SystemTime,
+    op: Option<String>,
+    request: Value,
+}
+
+/// Captures `request` when recording is on.
+pub(crate) fn start(request: &Request) -> Option<PendingEntry> {
+    let settings = config::current().recording.clone();
+    if settings.path.is_empty() {
+        return None;
+    }
+    let url = request.url();
+    let query: Vec<Value> = url
+        .query_pairs()
+        .map(|(name, value)| {
+            let value = if settings.redact_install_ids && is_sensitive_param(&name) {
+                REDACTED.into()
+            } else {
+                value
+            };
+            json!({ "name": name, "value": value })
+        })
+        .collect();
+    let mut entry = json!({
+        "method": request.method().as_str(),
+        "url": redact_if(settings.redact_install_ids, url.as_str()),
+        "httpVersion": format!("{:?}", request.version()),
+        "cookies": [],
+        "headers": headers(&settings, request.headers()),
+        "queryString": query,
+        "headersSize": -1,
+        "bodySize": 0,
+    });
+    if let Some(body) = request.body() {
+        match body.as_bytes() {
+            Some(bytes) => {
+                let mime_type = content_type(request.headers());
+                let mut post_data = content(&settings, bytes, &mime_type);
+                post_data["mimeType"] = json!(mime_type);
+                entry["postData"] = post_data;
+                entry["bodySize"] = json!(bytes.len());
+            }
+            None => entry["bodySize"] = json!(-1),
+        }
+    }
+    Some(PendingEntry {
+        settings,
+        started: SystemTime::now(),
+        op: metrics::current_op(),
+        request: entry,
+    })
+}
+
+impl PendingEntry {
+    /// Hands back an equivalent result and appends the entry for it. A response body is
+    /// recorded as the caller reads it, and the entry appended once it has been read to the end
+    /// or dropped.
+    pub(crate) fn finish(
+        self,
+        result: Result<Response, NetworkError>,
+        elapsed: Duration,
+    ) -> Result<Response, NetworkError> {
+        match result {
+            Ok(response) => Ok(self.tee(response, elapsed)),
+            Err(err) => {
+                let entry = json!({
+                    "status": 0,
+                    "statusText": "",
+                    "httpVersion": "",
+                    "cookies": [],
+                    "headers": [],
+                    "content": { "size": 0, "mimeType": "" },
+                    "redirectURL": "",
+                    "headersSize": -1,
+                    "bodySize": -1,
+                    "_error": redact_text(&err.to_string()),
+                });
+                self.append(entry, elapsed, Duration::ZERO);
+                Err(err)
+            }
+        }
+    }
+
+    fn append(self, response: Value, elapsed: Duration, receive: Duration) {
+        let wait = elapsed.as_secs_f64() * 1000.0;
+        let receive = receive.as_secs_f64() * 1000.0;
+        let mut entry = json!({
+            "startedDateTime": iso8601(self.started),
+            "time": wait + receive,
+            "request": self.request,
+            "response": response,
+            "cache": {},
+            "timings": { "send": 0, "wait": wait, "receive": receive },
+        });
+        if let Some(op) = self.op {
+            entry["_op"] = json!(op);
+        }
+        if let Err(err) = append(&self.settings.path, &entry) {
+            tracing::warn!(path = %self.settings.path, error = %err, "HAR recording failed");
+        }
+    }
+
+    /// Rebuilds `response` around a body that records what the caller reads from it.
+    fn tee(self, response: Response, elapsed: Duration) -> Response {
+        let status = response.status();
+        let version = response.version();
+        let url = response.url().clone();
+        let header_map = response.headers().clone();
+        let mime_type = content_type(&header_map);
+        let mut entry = json!({
+            "status": status.as_u16(),
+            "statusText": status.canonical_reason().unwrap_or(""),
+            "httpVersion": format!("{:?}", version),
+            "cookies": [],
+            "headers": headers(&self.settings, &header_map),
+            "content": { "size": -1, "mimeType": mime_type },
+            "redirectURL": "",
+            "headersSize": -1,
+            "bodySize": -1,
+        });
+        if let Some(location) = header_map
+            .get(reqwest::header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+        {
+            entry["redirectURL"] = json!(redact_if(self.settings.redact_install_ids, location));
+        }
+
+        let limit = match self.settings.max_body_bytes {
+            0 => MAX_CAPTURED_BODY,
+            limit => limit.min(MAX_CAPTURED_BODY),
+        };
+        let body = RecordingBody {
+            remaining: response.content_length(),
+            source: Mutex::new(response),
+            recording: Some(BodyRecording {
+                pending: self,
+                entry,
+                elapsed,
+                mime_type,
+                limit: usize::try_from(limit).unwrap_or(usize::MAX),
+                captured: Vec::new(),
+                total: 0,
+                error: None,
+                started: Instant::now(),
+            }),
+        };
+        http::Response::builder()
+            .status(status)
+            .version(version)
+            .url(url)
+            .body(reqwest::Body::wrap(body))
+            .map(|mut rebuilt| {
+                *rebuilt.headers_mut() = header_map;
+                Response::from(rebuilt)
+            })
+            .expect("parts taken from a valid response")
+    }
+}
+
+/// A response body read through from the upstream response, keeping the first `limit` bytes
+/// for the HAR entry. Reads block the calling thread, which is where blocking responses are
+/// polled.
+struct RecordingBody {
+    // Only ever locked through `get_mut`; it makes the body `Sync`.
+    source: Mutex<Response>,
+    remaining: Option<u64>,
+    recording: Option<BodyRecording>,
+}
+
+struct BodyRecording {
+    pending: PendingEntry,
+    entry: Value,
+    elapsed: Duration,
+    mime_type: String,
+    limit: usize,
+    captured: Vec<u8>,
+    total: u64,
+    error: Option<String>,
+    started: Instant,
+}
+
+impl BodyRecording {
+    fn push(&mut self, chunk: &[u8]) {
+        let room = self.limit.saturating_sub(self.captured.len());
+        self.captured
+            .extend_from_slice(&chunk[..room.min(chunk.len())]);
+        self.total += chunk.len() as u64;
+    }
+
+    fn append(mut self, complete: bool) {
+        let settings = &self.pending.settings;
+        let mut content = content(settings, &self.captured, &self.mime_type);
+        content["size"] = json!(self.total);
+        content["mimeType"] = json!(self.mime_type);
+        if !complete {
+            content["comment"] = json!("body not read to the end");
+        } else if self.total > self.captured.len() as u64 {
+            content["comment"] = json!(format!("truncated from {} bytes", self.total));
+        }
+        self.entry["content"] = content;
+        self.entry["bodySize"] = json!(self.total);
+        if let Some(error) = self.error {
+            self.entry["_error"] = json!(redact_text(&error));
+        }
+        let receive = self.started.elapsed();
+        self.pending.append(self.entry, self.elapsed, receive);
+    }
+}
+
+impl Body for RecordingBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        let this = self.get_mut();
+        let mut chunk = vec![0; READ_CHUNK];
+        let source = this.source.get_mut().unwrap_or_else(|err| err.into_inner());
+        match source.read(&mut chunk) {
+            Ok(0) => {
+                if let Some(recording) = this.recording.take() {
+                    recording.append(true);
+                }
+                Poll::Ready(None)
+            }
+            Ok(read) => {
+                chunk.truncate(read);
+                if let Some(recording) = &mut this.recording {
+                    recording.push(&chunk);
+                }
+                this.remaining = this
+                    .remaining
+                    .map(|remaining| remaining.saturating_sub(read as u64));
+                Poll::Ready(Some(Ok(Frame::data(Bytes::from(chunk)))))
+            }
+            Err(err) => {
+                if let Some(mut recording) = this.recording.take() {
+                    recording.error = Some(err.to_string());
+                    recording.append(false);
+                }
+                Poll::Ready(Some(Err(err)))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.remaining
+            .map_or_else(SizeHint::default, SizeHint::with_exact)
+    }
+}
+
+impl Drop for RecordingBody {
+    fn drop(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            recording.append(false);
+        }
+    }
+}
+
+fn headers(settings: &Recording, headers: &HeaderMap) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let name = name.as_str();
+            let value = String::from_utf8_lossy(value.as_bytes());
+            let hidden = (settings.redact_cookies && COOKIE_HEADERS.contains(&name))
+                || (settings.redact_auth
+                    && (AUTH_HEADERS.contains(&name) || name.contains("token")));
+            let value = if hidden {
+                REDACTED.to_string()
+            } else {
+                redact_if(settings.redact_install_ids, &value)
+            };
+            json!({ "name": name, "value": value })
+        })
+        .collect()
+}
+
+/// A HAR `content` (or `postData`) object: UTF-8 bodies as text, anything else as base64.
+fn content(settings: &Recording, body: &[u8], mime_type: &str) -> Value {
+    let limit = match settings.max_body_bytes {
+        0 => usize::MAX,
+        limit => usize::try_from(limit).unwrap_or(usize::MAX),
+    };
+    let mut content = match std::str::from_utf8(body) {
+        Ok(text) => {
+            let text = if settings.redact_install_ids {
+                redact_body(text, mime_type)
+            } else {
+                text.to_string()
+            };
+            let mut end = text.len().min(limit);
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            json!({ "text": &text[..end] })
+        }
+        Err(_) => json!({
+            "text": BASE64_STD.encode(&body[..body.len().min(limit)]),
+            "encoding": "base64",
+        }),
+    };
+    if body.len() > limit {
+        content["comment"] = json!(format!("truncated from {} bytes", body.len()));
+    }
+    content
+}
+
+fn redact_body(text: &str, mime_type: &str) -> String {
+    if mime_type.contains("json")
+        && let Ok(mut value) = serde_json::from_str::<Value>(text)
+    {
+        redact_json(&mut value);
+        return value.to_string();
+    }
+    redact_text(text)
+}
+
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                if is_sensitive_param(key) && !field.is_object() && !field.is_array() {
+                    *field = json!(REDACTED);
+                } else {
+                    redact_json(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        Value::String(text) => *text = redact_text(text),
+        _ => {}
+    }
+}
+
+fn is_sensitive_param(name: &str) -> bool {
+    SENSITIVE_PARAMS
+        .iter()
+        .any(|param| name.eq_ignore_ascii_case(param))
+}
+
+fn redact_if(enabled: bool, text: &str) -> String {
+    if enabled {
+        redact_text(text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn content_type(headers: &HeaderMap) -> String {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Appends `entry` to the HAR file at `path`, creating the file if needed.
+fn append(path: &str, entry: &Value) -> io::Result<()> {
+    let _guard = WRITER.lock().unwrap();
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let entry = serde_json::to_vec(entry)?;
+    if file.metadata()?.len() == 0 {
+        let header = json!({
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        write!(
+            file,
+            "{{\"log\":{{\"version\":\"1.2\",\"creator\":{},\"entries\":[",
+            header
+        )?;
+    } else {
+        seek_to_tail(&mut file)?;
+        file.write_all(b",")?;
+    }
+    file.write_all(&entry)?;
+    file.write_all(TAIL)?;
+    file.flush()
+}
+
+/// Positions `file` on the closing `]}}` of its entries array.
+fn seek_to_tail(file: &mut File) -> io::Result<()> {
+    let offset = -(TAIL.len() as i64);
+    file.seek(SeekFrom::End(offset))?;
+    let mut tail = [0u8; 3];
+    file.read_exact(&mut tail)?;
+    if tail != TAIL {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a HAR file written by this recorder",
+        ));
+    }
+    file.seek(SeekFrom::End(offset))?;
+    Ok(())
+}
+
+/// `2024-05-01T12:34:56.789Z`, as HAR timestamps require.
+fn iso8601(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60,
+        since_epoch.subsec_millis()
+    )
+}
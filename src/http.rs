@@ -1,22 +1,39 @@
 use std::convert::TryFrom;
 use std::fmt;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use http::Error as HttpError;
 use reqwest::blocking::Body as ReqwestBody;
 use reqwest::blocking::{Client as ReqwestClient, ClientBuilder as ReqwestClientBuilder};
 use reqwest::blocking::{RequestBuilder as ReqwestRequestBuilder, Response as ReqwestResponse};
-use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
 use reqwest::{IntoUrl, Method, Url};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 
+use crate::replay::{self, Outcome};
+use crate::{dry_run, har, metrics};
+
+#[cfg(feature = "fault_injection")]
+pub mod faults;
+pub mod middleware;
+
+#[cfg(feature = "fault_injection")]
+use faults::apply as send_with_faults;
+use middleware::{Chain, Middleware};
+
 pub type NetworkError = reqwest::Error;
 pub type Bytes = bytes::Bytes;
 
 #[derive(Clone)]
 pub struct HttpClient {
     inner: ReqwestClient,
+    /// The default headers `inner` was built with, which reqwest does not expose.
+    default_headers: Arc<HeaderMap>,
+    middleware: Chain,
+    #[cfg(feature = "fault_injection")]
+    faults: Option<Arc<faults::Seeded>>,
 }
 
 impl fmt::Debug for HttpClient {
@@ -31,36 +48,63 @@ impl HttpClient {
     }
 
     pub fn get(&self, url: impl IntoUrl) -> HttpRequestBuilder {
-        HttpRequestBuilder::from(self.inner.get(url))
+        self.wrap(self.inner.get(url))
     }
 
     pub fn post(&self, url: impl IntoUrl) -> HttpRequestBuilder {
-        HttpRequestBuilder::from(self.inner.post(url))
+        self.wrap(self.inner.post(url))
     }
 
     pub fn request(&self, method: Method, url: impl IntoUrl) -> HttpRequestBuilder {
-        HttpRequestBuilder::from(self.inner.request(method, url))
+        self.wrap(self.inner.request(method, url))
+    }
+
+    fn wrap(&self, inner: ReqwestRequestBuilder) -> HttpRequestBuilder {
+        HttpRequestBuilder {
+            inner,
+            default_headers: self.default_headers.clone(),
+            middleware: self.middleware.clone(),
+            #[cfg(feature = "fault_injection")]
+            faults: self.faults.clone(),
+        }
     }
 }
 
 impl From<ReqwestClient> for HttpClient {
     fn from(inner: ReqwestClient) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            default_headers: Arc::default(),
+            middleware: Chain::default(),
+            #[cfg(feature = "fault_injection")]
+            faults: None,
+        }
     }
 }
 
 pub struct HttpClientBuilder {
     inner: ReqwestClientBuilder,
+    default_headers: HeaderMap,
+    middleware: Vec<Arc<dyn Middleware>>,
+    #[cfg(feature = "fault_injection")]
+    faults: Option<Arc<faults::Seeded>>,
 }
 
 impl HttpClientBuilder {
     pub fn new() -> Self {
         Self {
             inner: ReqwestClient::builder(),
+            default_headers: HeaderMap::new(),
+            middleware: Vec::new(),
+            #[cfg(feature = "fault_injection")]
+            faults: None,
         }
     }
 
     pub fn default_headers(mut self, headers: HeaderMap) -> Self {
+        for (name, value) in headers.iter() {
+            self.default_headers.insert(name, value.clone());
+        }
         self.inner = self.inner.default_headers(headers);
         self
     }
@@ -70,7 +114,14 @@ impl HttpClientBuilder {
         HeaderValue: TryFrom<T>,
         <HeaderValue as TryFrom<T>>::Error: Into<HttpError>,
     {
-        self.inner = self.inner.user_agent(value);
+        match HeaderValue::try_from(value) {
+            Ok(header) => {
+                self.default_headers.insert(USER_AGENT, header.clone());
+                self.inner = self.inner.user_agent(header);
+            }
+            // Hands the error to reqwest, which reports it from `build`.
+            Err(err) => self.inner = self.inner.user_agent(InvalidHeader(err.into())),
+        }
         self
     }
 
@@ -99,8 +150,40 @@ impl HttpClientBuilder {
         self
     }
 
+    /// Appends `layer` to the client's [`middleware`] chain.
+    pub fn middleware(mut self, layer: impl Middleware + 'static) -> Self {
+        self.middleware.push(Arc::new(layer));
+        self
+    }
+
+    /// Injects faults into this client's requests, on top of the global rules. Check the rules
+    /// with [`faults::FaultConfig::validate`] first; invalid rates never or always fire.
+    #[cfg(feature = "fault_injection")]
+    pub fn faults(mut self, config: faults::FaultConfig) -> Self {
+        self.faults = Some(Arc::new(faults::Seeded::new(config)));
+        self
+    }
+
     pub fn build(self) -> Result<HttpClient, NetworkError> {
-        self.inner.build().map(HttpClient::from)
+        let inner = self.inner.build()?;
+        Ok(HttpClient {
+            inner,
+            default_headers: Arc::new(self.default_headers),
+            middleware: self.middleware.into(),
+            #[cfg(feature = "fault_injection")]
+            faults: self.faults,
+        })
+    }
+}
+
+/// A header value that failed to convert, carrying the error along.
+struct InvalidHeader(HttpError);
+
+impl TryFrom<InvalidHeader> for HeaderValue {
+    type Error = HttpError;
+
+    fn try_from(invalid: InvalidHeader) -> Result<Self, HttpError> {
+        Err(invalid.0)
     }
 }
 
@@ -112,25 +195,34 @@ impl Default for HttpClientBuilder {
 
 pub struct HttpRequestBuilder {
     inner: ReqwestRequestBuilder,
+    default_headers: Arc<HeaderMap>,
+    middleware: Chain,
+    #[cfg(feature = "fault_injection")]
+    faults: Option<Arc<faults::Seeded>>,
 }
 
 impl From<ReqwestRequestBuilder> for HttpRequestBuilder {
     fn from(inner: ReqwestRequestBuilder) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            default_headers: Arc::default(),
+            middleware: Chain::default(),
+            #[cfg(feature = "fault_injection")]
+            faults: None,
+        }
     }
 }
 
 impl HttpRequestBuilder {
-    pub fn header<K, V>(self, key: K, value: V) -> Self
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
     where
         reqwest::header::HeaderName: TryFrom<K>,
         <reqwest::header::HeaderName as TryFrom<K>>::Error: Into<HttpError>,
         HeaderValue: TryFrom<V>,
         <HeaderValue as TryFrom<V>>::Error: Into<HttpError>,
     {
-        Self {
-            inner: self.inner.header(key, value),
-        }
+        self.inner = self.inner.header(key, value);
+        self
     }
 
     pub fn headers(mut self, headers: HeaderMap) -> Self {
@@ -159,7 +251,15 @@ impl HttpRequestBuilder {
     }
 
     pub fn send(self) -> Result<HttpResponse, NetworkError> {
-        self.inner.send().map(HttpResponse::from)
+        #[cfg(feature = "fault_injection")]
+        let _scope = faults::enter_client(self.faults);
+        let (client, request) = self.inner.build_split();
+        middleware::run(&self.middleware, request?, &|mut request| {
+            dry_run::add_client_headers(&mut request, &self.default_headers);
+            ReqwestRequestBuilder::from_parts(client.clone(), request)
+                .send_traced()
+                .map(HttpResponse::from)
+        })
     }
 }
 
@@ -202,3 +302,84 @@ impl HttpResponse {
         self.inner.url()
     }
 }
+
+#[cfg(not(feature = "fault_injection"))]
+fn send_with_faults(
+    request: reqwest::blocking::Request,
+    send: impl FnOnce(reqwest::blocking::Request) -> Result<ReqwestResponse, NetworkError>,
+) -> Result<ReqwestResponse, NetworkError> {
+    send(request)
+}
+
+/// Sends a request inside an `http` span recording method, host, status and latency, counts it
+/// against the current op's metrics and appends it to the HAR recording when one is configured.
+/// With replay configured, the request is answered from recorded traffic instead; with the
+/// `fault_injection` feature, matching fault rules apply first. During a dry run the request is
+/// captured and failed without leaving the machine.
+pub(crate) trait TracedSend {
+    fn send_traced(self) -> Result<ReqwestResponse, NetworkError>;
+}
+
+impl TracedSend for ReqwestRequestBuilder {
+    fn send_traced(self) -> Result<ReqwestResponse, NetworkError> {
+        let (client, request) = self.build_split();
+        let request = request?;
+        let span = tracing::debug_span!(
+            "http",
+            method = %request.method(),
+            host = request.url().host_str().unwrap_or_default(),
+        );
+        let _entered = span.enter();
+        let request = match dry_run::intercept(request) {
+            dry_run::Outcome::Captured(result) => return result,
+            dry_run::Outcome::Network(request) => request,
+        };
+        let bytes_sent = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map_or(0, |body| body.len() as u64);
+        let started = Instant::now();
+        let result = send_with_faults(request, |request| match replay::intercept(request) {
+            Outcome::Replayed(result) => result,
+            Outcome::Network(request) => {
+                let recording = har::start(&request);
+                let result = client.execute(request);
+                match recording {
+                    Some(recording) => recording.finish(result, started.elapsed()),
+                    None => result,
+                }
+            }
+        });
+        let result = result.map(metrics::count_received);
+        metrics::record_upstream(
+            result
+                .as_ref()
+                .ok()
+                .map(|response| response.status().as_u16()),
+            bytes_sent,
+        );
+        let latency_ms = started.elapsed().as_millis() as u64;
+        match &result {
+            Ok(response)
+                if response.status().is_client_error() || response.status().is_server_error() =>
+            {
+                tracing::warn!(
+                    status = response.status().as_u16(),
+                    latency_ms,
+                    "upstream error status"
+                );
+            }
+            Ok(response) => {
+                tracing::debug!(
+                    status = response.status().as_u16(),
+                    latency_ms,
+                    "upstream response"
+                );
+            }
+            Err(err) => {
+                tracing::warn!(latency_ms, error = %err, "upstream request failed");
+            }
+        }
+        result
+    }
+}
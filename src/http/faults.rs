@@ -0,0 +1,325 @@
+//! Fault injection for upstream requests, compiled in with the `fault_injection` feature.
+//!
+//! Rules add latency and jitter, drop connections, answer with synthetic status codes and
+//! truncate response bodies, each at a given probability. A rule can be scoped to a host
+//! (subdomains included) and to an op. Global rules set with [`set`] (or `tn_core_set_faults`)
+//! apply to every request; rules given to [`HttpClientBuilder::faults`] (or the `faults` field of
+//! a client handle's configuration) apply to that client's requests on top of them.
+//!
+//! A dropped connection is a real one: the request is sent to a local listener that closes it
+//! without answering, so the caller gets the same error as from a peer hanging up. Synthetic
+//! responses never reach the network, the HAR recording or replay, and carry an
+//! `x-tn-core-fault` header naming the fault.
+//!
+//! [`HttpClientBuilder::faults`]: crate::blocking::ClientBuilder::faults
+
+use std::cell::RefCell;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use reqwest::ResponseBuilderExt;
+use reqwest::StatusCode;
+use reqwest::blocking::{Client, Request, Response};
+use reqwest::header::{CONTENT_TYPE, RETRY_AFTER};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::http::NetworkError;
+use crate::metrics;
+
+const FAULT_HEADER: &str = "x-tn-core-fault";
+
+static GLOBAL: Lazy<RwLock<Arc<Seeded>>> =
+    Lazy::new(|| RwLock::new(Arc::new(Seeded::new(FaultConfig::default()))));
+/// Seeds rules that carry no seed of their own.
+static SEEDER: Lazy<AtomicU64> = Lazy::new(|| AtomicU64::new(clock_seed()));
+
+/// Accepts connections and closes them straight away, to stand in for a peer that hangs up.
+static DROPPER: Lazy<SocketAddr> = Lazy::new(|| {
+    let bound = TcpListener::bind("127.0.0.1:0").and_then(|listener| {
+        let addr = listener.local_addr()?;
+        thread::spawn(move || listener.incoming().for_each(drop));
+        Ok(addr)
+    });
+    // The discard port refuses connections on most hosts, which is the next best thing.
+    bound.unwrap_or_else(|_| SocketAddr::from(([127, 0, 0, 1], 9)))
+});
+
+thread_local! {
+    static CLIENT_FAULTS: RefCell<Option<Arc<Seeded>>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FaultConfig {
+    /// Seeds the random source these rules draw from, for reproducible runs; seeded from the
+    /// clock otherwise.
+    pub seed: Option<u64>,
+    pub rules: Vec<FaultRule>,
+}
+
+/// One fault rule. Every matching rule adds its latency; the first matching rule whose drop,
+/// status or truncation fires decides the outcome, in that order.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FaultRule {
+    /// Host the rule applies to, subdomains included; every host when absent.
+    pub host: Option<String>,
+    /// Op the rule applies to; every request, including client handle ones, when absent.
+    pub op: Option<String>,
+    pub latency_ms: u64,
+    /// Up to this much extra latency, drawn uniformly per request.
+    pub jitter_ms: u64,
+    /// Probability of dropping the connection.
+    pub drop_rate: f64,
+    /// Synthetic status returned at `status_rate`.
+    pub status: Option<u16>,
+    pub status_rate: f64,
+    /// Probability of truncating the response body.
+    pub truncate_rate: f64,
+    /// Bytes kept when truncating; half the body when absent.
+    pub truncate_to: Option<u64>,
+}
+
+impl FaultConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (index, rule) in self.rules.iter().enumerate() {
+            let rates = [
+                ("drop_rate", rule.drop_rate),
+                ("status_rate", rule.status_rate),
+                ("truncate_rate", rule.truncate_rate),
+            ];
+            for (name, rate) in rates {
+                if !(0.0..=1.0).contains(&rate) {
+                    return Err(format!(
+                        "rules[{}].{}: must be between 0 and 1",
+                        index, name
+                    ));
+                }
+            }
+            match rule.status {
+                Some(status) if !(100..=599).contains(&status) => {
+                    return Err(format!("rules[{}].status: not an HTTP status", index));
+                }
+                None if rule.status_rate > 0.0 => {
+                    return Err(format!(
+                        "rules[{}].status: required with status_rate",
+                        index
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn matching<'a>(
+        &'a self,
+        host: &'a str,
+        op: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a FaultRule> {
+        self.rules.iter().filter(move |rule| {
+            let host_matches = rule.host.as_deref().is_none_or(|scope| {
+                host.eq_ignore_ascii_case(scope)
+                    || host
+                        .to_ascii_lowercase()
+                        .ends_with(&format!(".{}", scope.to_ascii_lowercase()))
+            });
+            let op_matches = rule.op.as_deref().is_none_or(|scope| op == Some(scope));
+            host_matches && op_matches
+        })
+    }
+}
+
+/// Rules together with the random source they draw from, so that the draws of one client's
+/// rules never shift those of another client or of the global rules.
+pub(crate) struct Seeded {
+    config: FaultConfig,
+    rng: AtomicU64,
+}
+
+impl Seeded {
+    pub(crate) fn new(config: FaultConfig) -> Self {
+        let seed = config.seed.unwrap_or_else(|| next_random(&SEEDER));
+        Self {
+            config,
+            rng: AtomicU64::new(seed),
+        }
+    }
+
+    fn roll(&self, rate: f64) -> bool {
+        // The top 53 bits give a uniform float in [0, 1).
+        let unit = (next_random(&self.rng) >> 11) as f64 / (1u64 << 53) as f64;
+        rate > 0.0 && unit < rate
+    }
+}
+
+/// Replaces the global rules, returning an error for invalid ones.
+pub fn set(config: FaultConfig) -> Result<(), String> {
+    config.validate()?;
+    *GLOBAL.write().unwrap() = Arc::new(Seeded::new(config));
+    Ok(())
+}
+
+/// The global rules.
+pub fn current() -> FaultConfig {
+    GLOBAL.read().unwrap().config.clone()
+}
+
+/// Applies a client's rules to requests sent on the current thread until dropped.
+pub(crate) struct ClientScope {
+    previous: Option<Arc<Seeded>>,
+}
+
+impl Drop for ClientScope {
+    fn drop(&mut self) {
+        CLIENT_FAULTS.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+pub(crate) fn enter_client(faults: Option<Arc<Seeded>>) -> ClientScope {
+    let previous = CLIENT_FAULTS.with(|current| current.replace(faults));
+    ClientScope { previous }
+}
+
+enum Action {
+    Drop,
+    Status(u16),
+    Truncate(Option<u64>),
+}
+
+/// Sends `request` with `send`, subject to the faults matching it.
+pub(crate) fn apply(
+    request: Request,
+    send: impl FnOnce(Request) -> Result<Response, NetworkError>,
+) -> Result<Response, NetworkError> {
+    let global = GLOBAL.read().unwrap().clone();
+    let client = CLIENT_FAULTS.with(|current| current.borrow().clone());
+    if global.config.rules.is_empty() && client.as_ref().is_none_or(|c| c.config.rules.is_empty()) {
+        return send(request);
+    }
+
+    let host = request.url().host_str().unwrap_or_default().to_string();
+    let op = metrics::current_op();
+    let mut delay = 0;
+    let mut action = None;
+    for seeded in std::iter::once(&*global).chain(client.as_deref()) {
+        for rule in seeded.config.matching(&host, op.as_deref()) {
+            delay += rule.latency_ms;
+            if rule.jitter_ms > 0 {
+                delay += next_random(&seeded.rng) % (rule.jitter_ms + 1);
+            }
+            if action.is_none() {
+                action = if seeded.roll(rule.drop_rate) {
+                    Some(Action::Drop)
+                } else if let Some(status) = rule.status.filter(|_| seeded.roll(rule.status_rate)) {
+                    Some(Action::Status(status))
+                } else if seeded.roll(rule.truncate_rate) {
+                    Some(Action::Truncate(rule.truncate_to))
+                } else {
+                    None
+                };
+            }
+        }
+    }
+
+    if delay > 0 {
+        tracing::debug!(delay_ms = delay, "fault injection: delaying request");
+        thread::sleep(Duration::from_millis(delay));
+    }
+    match action {
+        None => send(request),
+        Some(Action::Drop) => {
+            tracing::debug!("fault injection: dropping connection");
+            drop_connection(request)
+        }
+        Some(Action::Status(status)) => {
+            tracing::debug!(status, "fault injection: synthetic status");
+            Ok(synthetic_status(&request, status))
+        }
+        Some(Action::Truncate(keep)) => {
+            let response = send(request)?;
+            tracing::debug!("fault injection: truncating body");
+            truncate(response, keep)
+        }
+    }
+}
+
+fn drop_connection(mut request: Request) -> Result<Response, NetworkError> {
+    let original = request.url().clone();
+    let mut url = original.clone();
+    let _ = url.set_scheme("http");
+    let _ = url.set_ip_host(DROPPER.ip());
+    let _ = url.set_port(Some(DROPPER.port()));
+    *request.url_mut() = url;
+    let client = Client::builder()
+        .no_proxy()
+        .timeout(Duration::from_secs(5))
+        .build()?;
+    client
+        .execute(request)
+        .map_err(|err| err.with_url(original))
+}
+
+fn synthetic_status(request: &Request, status: u16) -> Response {
+    // Rules given to a client builder are not validated, so fall back on an invalid status.
+    let status = StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY);
+    let mut builder = http::Response::builder()
+        .status(status)
+        .url(request.url().clone())
+        .header(CONTENT_TYPE, "application/json")
+        .header(FAULT_HEADER, "status");
+    if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+        builder = builder.header(RETRY_AFTER, "1");
+    }
+    let body = json!({ "code": status.as_u16(), "message": "injected fault" }).to_string();
+    builder
+        .body(body)
+        .map(Response::from)
+        .expect("static parts form a valid response")
+}
+
+fn truncate(response: Response, keep: Option<u64>) -> Result<Response, NetworkError> {
+    let status = response.status();
+    let version = response.version();
+    let url = response.url().clone();
+    let mut headers = response.headers().clone();
+    let body = response.bytes()?;
+    let keep = keep.map_or(body.len() / 2, |keep| {
+        usize::try_from(keep).unwrap_or(usize::MAX).min(body.len())
+    });
+    if let Ok(value) = "truncate".parse() {
+        headers.insert(FAULT_HEADER, value);
+    }
+    let rebuilt = http::Response::builder()
+        .status(status)
+        .version(version)
+        .url(url)
+        .body(body.slice(..keep))
+        .map(|mut rebuilt| {
+            *rebuilt.headers_mut() = headers;
+            Response::from(rebuilt)
+        })
+        .expect("parts taken from a valid response");
+    Ok(rebuilt)
+}
+
+/// SplitMix64 over `state`; good enough to pick faults, not for anything else.
+fn next_random(state: &AtomicU64) -> u64 {
+    let mut z = state
+        .fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+        .wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn clock_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64)
+}
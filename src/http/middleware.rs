@@ -0,0 +1,117 @@
+//! Middleware for [`Client`](crate::blocking::Client) requests.
+//!
+//! Layers added with [`ClientBuilder::middleware`] form an ordered chain: `on_request` runs
+//! from the first layer to the last, `on_response` and `on_error` from the last back to the
+//! first, so the first layer added is the outermost one. Each attempt still goes through the
+//! usual send path, so retries show up in traces, metrics, HAR recordings and dry runs as
+//! requests of their own.
+//!
+//! [`ClientBuilder::middleware`]: crate::blocking::ClientBuilder::middleware
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::Request;
+
+use crate::http::{HttpResponse, NetworkError};
+
+/// One layer of a client's middleware chain. Every hook has a pass-through default.
+pub trait Middleware: Send + Sync {
+    /// Called before the request is sent, and may edit it. Returning a response answers the
+    /// request without sending it: later layers are skipped and earlier ones see the response in
+    /// `on_response`.
+    fn on_request(&self, request: &mut Request) -> Option<HttpResponse> {
+        let _ = request;
+        None
+    }
+
+    /// Called with the response, which may be replaced or turned into an error with
+    /// [`HttpResponse::error_for_status`].
+    fn on_response(&self, response: HttpResponse) -> Result<HttpResponse, NetworkError> {
+        Ok(response)
+    }
+
+    /// Called when the request failed, and may recover with a response.
+    fn on_error(&self, error: NetworkError) -> Result<HttpResponse, NetworkError> {
+        Err(error)
+    }
+
+    /// Called after each attempt, counted from 1, before `on_response` or `on_error`. Returning
+    /// a delay sends the request again after it, through the later layers only. Requests whose
+    /// body is a stream are sent once.
+    fn retry(
+        &self,
+        attempt: u32,
+        outcome: &Result<HttpResponse, NetworkError>,
+    ) -> Option<Duration> {
+        let _ = (attempt, outcome);
+        None
+    }
+}
+
+impl<T: Middleware + ?Sized> Middleware for Arc<T> {
+    fn on_request(&self, request: &mut Request) -> Option<HttpResponse> {
+        (**self).on_request(request)
+    }
+
+    fn on_response(&self, response: HttpResponse) -> Result<HttpResponse, NetworkError> {
+        (**self).on_response(response)
+    }
+
+    fn on_error(&self, error: NetworkError) -> Result<HttpResponse, NetworkError> {
+        (**self).on_error(error)
+    }
+
+    fn retry(
+        &self,
+        attempt: u32,
+        outcome: &Result<HttpResponse, NetworkError>,
+    ) -> Option<Duration> {
+        (**self).retry(attempt, outcome)
+    }
+}
+
+pub(crate) type Chain = Arc<[Arc<dyn Middleware>]>;
+
+/// Sends `request` through `chain`, with `send` at the end of it.
+pub(crate) fn run(
+    chain: &[Arc<dyn Middleware>],
+    mut request: Request,
+    send: &dyn Fn(Request) -> Result<HttpResponse, NetworkError>,
+) -> Result<HttpResponse, NetworkError> {
+    let Some((layer, inner)) = chain.split_first() else {
+        return send(request);
+    };
+    if let Some(response) = layer.on_request(&mut request) {
+        return Ok(response);
+    }
+
+    // Sending consumes the request, so every layer copies it up front in case `retry` asks for
+    // another attempt, and copies that again for each one. A copy clones the URL and headers;
+    // buffered bodies share their bytes. A streamed body cannot be copied, so such requests are
+    // sent once.
+    let original = request.try_clone();
+    let mut result = run(inner, request, send);
+    let mut attempt = 1;
+    while let Some(original) = original.as_ref() {
+        let Some(delay) = layer.retry(attempt, &result) else {
+            break;
+        };
+        let Some(request) = original.try_clone() else {
+            break;
+        };
+        tracing::debug!(
+            attempt,
+            delay_ms = delay.as_millis() as u64,
+            "middleware: retrying request"
+        );
+        thread::sleep(delay);
+        attempt += 1;
+        result = run(inner, request, send);
+    }
+    match result {
+        Ok(response) => layer.on_response(response),
+        Err(err) => layer.on_error(err),
+    }
+}
@@ -1,17 +1,39 @@
 mod api;
+pub mod config;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+mod dry_run;
+mod error;
 pub mod ffi;
+mod har;
 mod http;
+pub mod logging;
+pub mod metrics;
+#[cfg(feature = "testing")]
+pub mod mock;
+pub mod ops;
+mod progress;
+mod replay;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+pub mod validate;
 
 pub mod blocking {
     pub use crate::http::{HttpClient as Client, HttpClientBuilder as ClientBuilder};
     pub use crate::http::{HttpRequestBuilder as RequestBuilder, HttpResponse as Response};
+    pub use reqwest::blocking::Request;
 }
 
 pub mod headers {
     pub use reqwest::header::*;
 }
 
+pub use error::{CoreError, ErrorKind, FieldError};
+#[cfg(feature = "fault_injection")]
+pub use http::faults;
+pub use http::middleware;
 pub use http::{Bytes, NetworkError};
+pub use progress::{ProgressSink, set_progress_sink};
 pub use reqwest::Certificate;
 pub use reqwest::IntoUrl;
 pub use reqwest::Method;
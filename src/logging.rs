@@ -0,0 +1,349 @@
+//! Structured log events for hosts.
+//!
+//! The core is instrumented with `tracing`: every op runs in an `op` span and every upstream
+//! request in an `http` span (method, host, status, latency). Installing a sink with
+//! [`set_log_sink`] (or `tn_core_set_log_callback` over FFI) registers a global subscriber that
+//! turns each event into a JSON object:
+//!
+//! ```json
+//! {"level": "warn", "target": "...", "message": "...", "fields": {...},
+//!  "spans": [{"name": "op", "fields": {"op": "search_books"}}, ...]}
+//! ```
+//!
+//! Install IDs, cookies, keys and similar values are redacted before they reach the sink, both as
+//! named fields and inside URLs and error messages. Rust embedders that install their own
+//! `tracing` subscriber first keep it; the sink is then not called.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+
+use once_cell::sync::Lazy;
+use serde_json::{Map, Value, json};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::{LookupSpan, Registry};
+
+pub const LEVEL_OFF: u32 = 0;
+pub const LEVEL_ERROR: u32 = 1;
+pub const LEVEL_WARN: u32 = 2;
+pub const LEVEL_INFO: u32 = 3;
+pub const LEVEL_DEBUG: u32 = 4;
+pub const LEVEL_TRACE: u32 = 5;
+
+const REDACTED: &str = "[redacted]";
+
+/// Field names (matched case-insensitively as substrings) whose values are never logged.
+const SENSITIVE_FIELDS: &[&str] = &[
+    "install_id",
+    "iid",
+    "cookie",
+    "key",
+    "token",
+    "passphrase",
+    "password",
+    "secret",
+    "authorization",
+    "tt_info",
+];
+
+/// Query and cookie parameters whose values are masked inside logged text, including the
+/// session cookies the Fanqie web endpoints set.
+pub(crate) const SENSITIVE_PARAMS: &[&str] = &[
+    "install_id",
+    "iid",
+    "tt_info",
+    "token",
+    "key",
+    "device_id",
+    "sessionid",
+    "sessionid_ss",
+    "sid_tt",
+    "sid_guard",
+    "uid_tt",
+    "uid_tt_ss",
+    "odin_tt",
+    "ttwid",
+];
+
+/// Receives log events at or above the configured level: the level (`LEVEL_ERROR` to
+/// `LEVEL_TRACE`) and the event as JSON.
+pub type LogSink = Box<dyn Fn(u32, &Value) + Send + Sync>;
+
+/// Shared so an event can call the sink without holding the lock, which a sink that logs or
+/// replaces itself would otherwise deadlock on.
+type SharedSink = Arc<dyn Fn(u32, &Value) + Send + Sync>;
+
+static LEVEL: AtomicU32 = AtomicU32::new(LEVEL_OFF);
+static SINK: Lazy<RwLock<Option<SharedSink>>> = Lazy::new(|| RwLock::new(None));
+static INSTALLED: Lazy<()> = Lazy::new(|| {
+    let _ = tracing::subscriber::set_global_default(Registry::default().with(SinkLayer));
+});
+
+/// Installs (or removes, with `None` or `LEVEL_OFF`) the process wide log sink.
+pub fn set_log_sink(level: u32, sink: Option<LogSink>) {
+    Lazy::force(&INSTALLED);
+    let level = if sink.is_some() {
+        level.min(LEVEL_TRACE)
+    } else {
+        LEVEL_OFF
+    };
+    *SINK.write().unwrap() = sink.map(Arc::from);
+    LEVEL.store(level, Ordering::Relaxed);
+    tracing::callsite::rebuild_interest_cache();
+}
+
+fn level_number(level: &Level) -> u32 {
+    match *level {
+        Level::ERROR => LEVEL_ERROR,
+        Level::WARN => LEVEL_WARN,
+        Level::INFO => LEVEL_INFO,
+        Level::DEBUG => LEVEL_DEBUG,
+        Level::TRACE => LEVEL_TRACE,
+    }
+}
+
+/// Masks the values of sensitive query or cookie parameters (`iid=123` becomes
+/// `iid=[redacted]`) in free text such as URLs and error messages.
+pub(crate) fn redact_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    'scan: while !rest.is_empty() {
+        for param in SENSITIVE_PARAMS {
+            let at_boundary = out.chars().last().is_none_or(|c| {
+                matches!(c, '?' | '&' | ';' | ':' | '(' | '"' | ',') || c.is_whitespace()
+            });
+            if at_boundary
+                && rest.len() > param.len()
+                && rest[..param.len()].eq_ignore_ascii_case(param)
+                && rest[param.len()..].starts_with('=')
+            {
+                out.push_str(&rest[..=param.len()]);
+                out.push_str(REDACTED);
+                let value = &rest[param.len() + 1..];
+                let end = value
+                    .find(|c: char| matches!(c, '&' | ';' | ')' | '"' | ',') || c.is_whitespace())
+                    .unwrap_or(value.len());
+                rest = &value[end..];
+                continue 'scan;
+            }
+        }
+        let next = rest.chars().next().map_or(1, char::len_utf8);
+        out.push_str(&rest[..next]);
+        rest = &rest[next..];
+    }
+    out
+}
+
+fn is_sensitive(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SENSITIVE_FIELDS.iter().any(|field| name.contains(field))
+}
+
+/// Collects span or event fields as redacted JSON values.
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        let value = if is_sensitive(field.name()) {
+            Value::String(REDACTED.to_string())
+        } else {
+            value
+        };
+        self.0.insert(field.name().to_string(), value);
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::String(redact_text(value)));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, Value::String(redact_text(&format!("{:?}", value))));
+    }
+}
+
+/// Span fields stored in the registry so events can report their enclosing spans.
+struct SpanFields(Map<String, Value>);
+
+struct SinkLayer;
+
+impl<S> Layer<S> for SinkLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn register_callsite(&self, _metadata: &'static Metadata<'static>) -> Interest {
+        // The level can change at runtime, so decide per call in `enabled`.
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, _ctx: Context<'_, S>) -> bool {
+        level_number(metadata.level()) <= LEVEL.load(Ordering::Relaxed)
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Map::new();
+        attrs.record(&mut JsonVisitor(&mut fields));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id)
+            && let Some(fields) = span.extensions_mut().get_mut::<SpanFields>()
+        {
+            values.record(&mut JsonVisitor(&mut fields.0));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(sink) = SINK.read().unwrap().clone() else {
+            return;
+        };
+        let mut fields = Map::new();
+        event.record(&mut JsonVisitor(&mut fields));
+        let message = fields.remove("message").unwrap_or(Value::Null);
+        let spans: Vec<Value> = ctx
+            .event_scope(event)
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .map(|span| {
+                let fields = span
+                    .extensions()
+                    .get::<SpanFields>()
+                    .map(|fields| Value::Object(fields.0.clone()))
+                    .unwrap_or_else(|| json!({}));
+                json!({ "name": span.name(), "fields": fields })
+            })
+            .collect();
+        let metadata = event.metadata();
+        let level = level_number(metadata.level());
+        sink(
+            level,
+            &json!({
+                "level": metadata.level().as_str().to_ascii_lowercase(),
+                "target": metadata.target(),
+                "message": message,
+                "fields": fields,
+                "spans": spans,
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    #[test]
+    fn url_query_values_are_masked() {
+        assert_eq!(
+            redact_text(
+                "https://api.example.test/search?q=a&install_id=7300000000000000001&aid=1967"
+            ),
+            "https://api.example.test/search?q=a&install_id=[redacted]&aid=1967"
+        );
+        assert_eq!(
+            redact_text("error sending request for url (https://a.test/?IID=42&Token=abc)"),
+            "error sending request for url (https://a.test/?IID=[redacted]&Token=[redacted])"
+        );
+        // Only whole parameter names count.
+        assert_eq!(redact_text("?paid=1&monkey=2"), "?paid=1&monkey=2");
+        assert_eq!(redact_text("no parameters here"), "no parameters here");
+    }
+
+    #[test]
+    fn cookie_text_is_masked() {
+        assert_eq!(
+            redact_text("Cookie: sessionid=abc123; sid_tt=def456; theme=dark"),
+            "Cookie: sessionid=[redacted]; sid_tt=[redacted]; theme=dark"
+        );
+        assert_eq!(
+            redact_text("set-cookie:odin_tt=xyz;\tttwid=1%7Cabc"),
+            "set-cookie:odin_tt=[redacted];\tttwid=[redacted]"
+        );
+        assert_eq!(
+            redact_text("install_id=7300000000000000001; device_id=7300000000000000002"),
+            "install_id=[redacted]; device_id=[redacted]"
+        );
+    }
+
+    #[test]
+    fn sensitive_field_names_are_redacted() {
+        for name in [
+            "install_id",
+            "Cookie",
+            "public_key",
+            "api_token",
+            "tt_info",
+            "authorization",
+        ] {
+            assert!(is_sensitive(name), "{}", name);
+        }
+        for name in ["op", "status", "latency_ms", "url"] {
+            assert!(!is_sensitive(name), "{}", name);
+        }
+
+        /// Keeps the fields of the last event, as the sink would see them.
+        struct Capture(Arc<RwLock<Map<String, Value>>>);
+
+        impl<S: Subscriber> Layer<S> for Capture {
+            fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+                event.record(&mut JsonVisitor(&mut self.0.write().unwrap()));
+            }
+        }
+
+        let fields = Arc::new(RwLock::new(Map::new()));
+        let subscriber = Registry::default().with(Capture(fields.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(
+                install_id = 7300000000000000001u64,
+                session_cookie = "sessionid=abc",
+                url = "https://a.test/?iid=42&page=2",
+                status = 200u64,
+                "sent"
+            );
+        });
+        let fields = fields.read().unwrap();
+        assert_eq!(fields["install_id"], REDACTED);
+        assert_eq!(fields["session_cookie"], REDACTED);
+        assert_eq!(fields["url"], "https://a.test/?iid=[redacted]&page=2");
+        assert_eq!(fields["status"], 200);
+        assert_eq!(fields["message"], "sent");
+    }
+
+    #[test]
+    fn sink_may_replace_itself() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        set_log_sink(
+            LEVEL_WARN,
+            Some(Box::new(|_, event| {
+                assert_eq!(event["message"], "first");
+                CALLS.fetch_add(1, Ordering::Relaxed);
+                set_log_sink(LEVEL_OFF, None);
+            })),
+        );
+        tracing::warn!("first");
+        tracing::warn!("second");
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+    }
+}
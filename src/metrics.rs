@@ -0,0 +1,558 @@
+//! In-process metrics for ops and client handles.
+//!
+//! Counters and latency histograms are kept per op name (for every call through the op registry;
+//! names that are not registered share the [`UNKNOWN_OP`] entry) and per live client handle (for
+//! `tn_core_execute_request`). [`snapshot`] returns them as JSON, as
+//! `tn_core_metrics` does; [`prometheus_text`] renders the Prometheus text exposition format, as
+//! served by the daemon's `/metrics` route.
+//!
+//! Upstream byte counts for ops are taken from buffered request bodies and from response bodies
+//! as the caller reads them (after decompression, and only as far as they are read); client
+//! handle byte counts are exact. `rate_limited` counts upstream HTTP 429 responses.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use once_cell::sync::Lazy;
+use reqwest::ResponseBuilderExt;
+use reqwest::blocking::Response;
+use serde_json::{Map, Value, json};
+
+use crate::error::ErrorKind;
+
+/// Entry counting calls to op names that are not registered.
+pub const UNKNOWN_OP: &str = "unknown";
+
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Upper bounds of the latency histogram buckets, in milliseconds.
+const LATENCY_BUCKETS_MS: &[u64] = &[5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000, 30000];
+
+static METRICS: Lazy<Mutex<Metrics>> = Lazy::new(|| Mutex::new(Metrics::default()));
+
+thread_local! {
+    static CURRENT_OP: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+#[derive(Default)]
+struct Metrics {
+    ops: BTreeMap<String, OpStats>,
+    clients: BTreeMap<u64, ClientStats>,
+}
+
+#[derive(Default)]
+struct OpStats {
+    requests: u64,
+    errors: BTreeMap<&'static str, u64>,
+    latency: Histogram,
+    upstream_requests: u64,
+    upstream_errors: u64,
+    rate_limited: u64,
+    retries: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+#[derive(Default)]
+struct ClientStats {
+    requests: u64,
+    errors: u64,
+    latency: Histogram,
+    rate_limited: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum_ms: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; LATENCY_BUCKETS_MS.len()],
+            count: 0,
+            sum_ms: 0.0,
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: Duration) {
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        if let Some(index) = LATENCY_BUCKETS_MS.iter().position(|le| ms <= *le as f64) {
+            self.buckets[index] += 1;
+        }
+        self.count += 1;
+        self.sum_ms += ms;
+    }
+
+    /// Cumulative `(upper bound in ms, count)` pairs, as Prometheus expects.
+    fn cumulative(&self) -> Vec<(u64, u64)> {
+        let mut total = 0;
+        LATENCY_BUCKETS_MS
+            .iter()
+            .zip(&self.buckets)
+            .map(|(le, count)| {
+                total += count;
+                (*le, total)
+            })
+            .collect()
+    }
+
+    fn to_json(&self) -> Value {
+        let buckets: Vec<Value> = self
+            .cumulative()
+            .into_iter()
+            .map(|(le, count)| json!({ "le_ms": le, "count": count }))
+            .collect();
+        json!({ "count": self.count, "sum_ms": self.sum_ms, "buckets": buckets })
+    }
+}
+
+/// Marks the current thread as working for `op` until dropped, so upstream requests made on it
+/// are attributed to that op.
+pub(crate) struct OpScope {
+    previous: Option<String>,
+}
+
+impl Drop for OpScope {
+    fn drop(&mut self) {
+        CURRENT_OP.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+pub(crate) fn enter_op(op: Option<String>) -> OpScope {
+    let previous = CURRENT_OP.with(|current| current.replace(op));
+    OpScope { previous }
+}
+
+/// The op the current thread is working for, to carry into worker threads.
+pub(crate) fn current_op() -> Option<String> {
+    CURRENT_OP.with(|current| current.borrow().clone())
+}
+
+fn with_current_op(update: impl FnOnce(&mut OpStats)) {
+    if let Some(op) = current_op() {
+        update(METRICS.lock().unwrap().ops.entry(op).or_default());
+    }
+}
+
+pub(crate) fn record_op(op: &str, elapsed: Duration, error: Option<ErrorKind>) {
+    let mut metrics = METRICS.lock().unwrap();
+    let stats = metrics.ops.entry(op.to_string()).or_default();
+    stats.requests += 1;
+    stats.latency.observe(elapsed);
+    if let Some(kind) = error {
+        *stats.errors.entry(kind.as_str()).or_default() += 1;
+    }
+}
+
+/// Records an upstream request made by the current op; `status` is `None` on network failure.
+pub(crate) fn record_upstream(status: Option<u16>, bytes_sent: u64) {
+    with_current_op(|stats| {
+        stats.upstream_requests += 1;
+        if status.is_none_or(|status| status >= 400) {
+            stats.upstream_errors += 1;
+        }
+        if status == Some(429) {
+            stats.rate_limited += 1;
+        }
+        stats.bytes_sent += bytes_sent;
+    });
+}
+
+/// Counts the body of `response` against the current op's `bytes_received` as it is read.
+pub(crate) fn count_received(response: Response) -> Response {
+    let Some(op) = current_op() else {
+        return response;
+    };
+    let status = response.status();
+    let version = response.version();
+    let url = response.url().clone();
+    let headers = response.headers().clone();
+    let body = CountingBody {
+        remaining: response.content_length(),
+        source: Mutex::new(response),
+        op,
+        read: 0,
+    };
+    http::Response::builder()
+        .status(status)
+        .version(version)
+        .url(url)
+        .body(reqwest::Body::wrap(body))
+        .map(|mut rebuilt| {
+            *rebuilt.headers_mut() = headers;
+            Response::from(rebuilt)
+        })
+        .expect("parts taken from a valid response")
+}
+
+/// A response body read through from the upstream response, adding the bytes read to its op's
+/// stats once it is dropped.
+struct CountingBody {
+    // Only ever locked through `get_mut`; it makes the body `Sync`.
+    source: Mutex<Response>,
+    remaining: Option<u64>,
+    op: String,
+    read: u64,
+}
+
+impl Body for CountingBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        let this = self.get_mut();
+        let mut chunk = vec![0; READ_CHUNK];
+        let source = this.source.get_mut().unwrap_or_else(|err| err.into_inner());
+        match source.read(&mut chunk) {
+            Ok(0) => Poll::Ready(None),
+            Ok(read) => {
+                chunk.truncate(read);
+                this.read += read as u64;
+                this.remaining = this
+                    .remaining
+                    .map(|remaining| remaining.saturating_sub(read as u64));
+                Poll::Ready(Some(Ok(Frame::data(Bytes::from(chunk)))))
+            }
+            Err(err) => Poll::Ready(Some(Err(err))),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.remaining
+            .map_or_else(SizeHint::default, SizeHint::with_exact)
+    }
+}
+
+impl Drop for CountingBody {
+    fn drop(&mut self) {
+        if self.read > 0 {
+            let mut metrics = METRICS.lock().unwrap();
+            metrics
+                .ops
+                .entry(self.op.clone())
+                .or_default()
+                .bytes_received += self.read;
+        }
+    }
+}
+
+/// Records a retry of an upstream call by the current op.
+pub(crate) fn record_retry() {
+    with_current_op(|stats| stats.retries += 1);
+}
+
+/// Records a request made through a client handle; `status` is `None` on failure.
+pub(crate) fn record_client(
+    handle: u64,
+    elapsed: Duration,
+    status: Option<u16>,
+    bytes_sent: u64,
+    bytes_received: u64,
+) {
+    let mut metrics = METRICS.lock().unwrap();
+    let stats = metrics.clients.entry(handle).or_default();
+    stats.requests += 1;
+    stats.latency.observe(elapsed);
+    if status.is_none_or(|status| status >= 400) {
+        stats.errors += 1;
+    }
+    if status == Some(429) {
+        stats.rate_limited += 1;
+    }
+    stats.bytes_sent += bytes_sent;
+    stats.bytes_received += bytes_received;
+}
+
+/// Drops the stats of a destroyed client handle.
+pub(crate) fn remove_client(handle: u64) {
+    METRICS.lock().unwrap().clients.remove(&handle);
+}
+
+/// All metrics as JSON: `{ops: {name: {...}}, clients: {handle: {...}}}`.
+pub fn snapshot() -> Value {
+    let metrics = METRICS.lock().unwrap();
+    let ops: Map<String, Value> = metrics
+        .ops
+        .iter()
+        .map(|(op, stats)| {
+            let value = json!({
+                "requests": stats.requests,
+                "errors": stats.errors,
+                "latency": stats.latency.to_json(),
+                "upstream_requests": stats.upstream_requests,
+                "upstream_errors": stats.upstream_errors,
+                "rate_limited": stats.rate_limited,
+                "retries": stats.retries,
+                "bytes_sent": stats.bytes_sent,
+                "bytes_received": stats.bytes_received,
+            });
+            (op.clone(), value)
+        })
+        .collect();
+    let clients: Map<String, Value> = metrics
+        .clients
+        .iter()
+        .map(|(handle, stats)| {
+            let value = json!({
+                "requests": stats.requests,
+                "errors": stats.errors,
+                "latency": stats.latency.to_json(),
+                "rate_limited": stats.rate_limited,
+                "bytes_sent": stats.bytes_sent,
+                "bytes_received": stats.bytes_received,
+            });
+            (handle.to_string(), value)
+        })
+        .collect();
+    json!({ "ops": ops, "clients": clients })
+}
+
+/// All metrics in the Prometheus text exposition format (version 0.0.4).
+pub fn prometheus_text() -> String {
+    let metrics = METRICS.lock().unwrap();
+    let mut out = String::new();
+
+    let ops: Vec<(String, &OpStats)> = metrics
+        .ops
+        .iter()
+        .map(|(op, stats)| (format!("op=\"{}\"", escape_label(op)), stats))
+        .collect();
+    counter(
+        &mut out,
+        "tn_core_op_requests_total",
+        "Operation calls.",
+        &ops,
+        |s| s.requests,
+    );
+    header(
+        &mut out,
+        "tn_core_op_errors_total",
+        "Failed operation calls by error kind.",
+        "counter",
+    );
+    for (labels, stats) in &ops {
+        for (kind, count) in &stats.errors {
+            let _ = writeln!(
+                out,
+                "tn_core_op_errors_total{{{},kind=\"{}\"}} {}",
+                labels, kind, count
+            );
+        }
+    }
+    histogram(
+        &mut out,
+        "tn_core_op_duration_seconds",
+        "Operation latency.",
+        &ops,
+        |s| &s.latency,
+    );
+    counter(
+        &mut out,
+        "tn_core_op_upstream_requests_total",
+        "Upstream HTTP requests made by operations.",
+        &ops,
+        |s| s.upstream_requests,
+    );
+    counter(
+        &mut out,
+        "tn_core_op_upstream_errors_total",
+        "Upstream requests that failed or returned an error status.",
+        &ops,
+        |s| s.upstream_errors,
+    );
+    counter(
+        &mut out,
+        "tn_core_op_rate_limited_total",
+        "Upstream HTTP 429 responses.",
+        &ops,
+        |s| s.rate_limited,
+    );
+    counter(
+        &mut out,
+        "tn_core_op_retries_total",
+        "Upstream retries.",
+        &ops,
+        |s| s.retries,
+    );
+    counter(
+        &mut out,
+        "tn_core_op_bytes_sent_total",
+        "Request body bytes sent.",
+        &ops,
+        |s| s.bytes_sent,
+    );
+    counter(
+        &mut out,
+        "tn_core_op_bytes_received_total",
+        "Response bytes received, per Content-Length.",
+        &ops,
+        |s| s.bytes_received,
+    );
+
+    let clients: Vec<(String, &ClientStats)> = metrics
+        .clients
+        .iter()
+        .map(|(handle, stats)| (format!("handle=\"{}\"", handle), stats))
+        .collect();
+    counter(
+        &mut out,
+        "tn_core_client_requests_total",
+        "Requests made through client handles.",
+        &clients,
+        |s| s.requests,
+    );
+    counter(
+        &mut out,
+        "tn_core_client_errors_total",
+        "Client handle requests that failed or returned an error status.",
+        &clients,
+        |s| s.errors,
+    );
+    histogram(
+        &mut out,
+        "tn_core_client_duration_seconds",
+        "Client handle request latency.",
+        &clients,
+        |s| &s.latency,
+    );
+    counter(
+        &mut out,
+        "tn_core_client_rate_limited_total",
+        "HTTP 429 responses to client handle requests.",
+        &clients,
+        |s| s.rate_limited,
+    );
+    counter(
+        &mut out,
+        "tn_core_client_bytes_sent_total",
+        "Request body bytes sent through client handles.",
+        &clients,
+        |s| s.bytes_sent,
+    );
+    counter(
+        &mut out,
+        "tn_core_client_bytes_received_total",
+        "Response body bytes received through client handles.",
+        &clients,
+        |s| s.bytes_received,
+    );
+    out
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn counter<T>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    series: &[(String, &T)],
+    value: impl Fn(&T) -> u64,
+) {
+    header(out, name, help, "counter");
+    for (labels, stats) in series {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value(stats));
+    }
+}
+
+fn histogram<T>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    series: &[(String, &T)],
+    value: impl Fn(&T) -> &Histogram,
+) {
+    header(out, name, help, "histogram");
+    for (labels, stats) in series {
+        let histogram = value(stats);
+        for (le, count) in histogram.cumulative() {
+            let le = le as f64 / 1000.0;
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, le, count);
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, histogram.count
+        );
+        let _ = writeln!(
+            out,
+            "{}_sum{{{}}} {}",
+            name,
+            labels,
+            histogram.sum_ms / 1000.0
+        );
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A body of unknown length, as a chunked upstream response has.
+    struct Chunked(Vec<&'static [u8]>);
+
+    impl Body for Chunked {
+        type Data = Bytes;
+        type Error = io::Error;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+            let next = (!self.0.is_empty()).then(|| Ok(Frame::data(Bytes::from(self.0.remove(0)))));
+            Poll::Ready(next)
+        }
+    }
+
+    fn received(op: &str) -> Value {
+        snapshot()["ops"][op]["bytes_received"].clone()
+    }
+
+    #[test]
+    fn response_bodies_are_counted_as_read() {
+        let _scope = enter_op(Some("metrics_counted_op".to_string()));
+        let chunked = http::Response::new(reqwest::Body::wrap(Chunked(vec![b"hello ", b"world"])));
+        let response = count_received(Response::from(chunked));
+        assert_eq!(response.content_length(), None);
+        assert_eq!(response.text().unwrap(), "hello world");
+        assert_eq!(received("metrics_counted_op"), 11);
+
+        let sized = count_received(Response::from(http::Response::new("sized")));
+        assert_eq!(sized.content_length(), Some(5));
+        drop(sized);
+        assert_eq!(received("metrics_counted_op"), 11);
+
+        let mut partial = count_received(Response::from(http::Response::new("partial body")));
+        let mut buf = [0; 4];
+        partial.read_exact(&mut buf).unwrap();
+        drop(partial);
+        assert_eq!(received("metrics_counted_op"), 23);
+    }
+}
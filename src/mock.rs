@@ -0,0 +1,693 @@
+//! Local stand-in for the Fanqie endpoints and release hosting, for integration tests.
+//!
+//! [`MockServer`] answers every network op with canned responses shaped like the real service:
+//! device register and activation, search, chapter directory, comment stats and lists, signed
+//! session key registration and chapter batches, media files, and a release feed with a
+//! downloadable asset, checksum listing and ed25519 signature. [`MockServer::endpoints`] returns
+//! a [`config::Endpoints`] pointing at it.
+//!
+//! Faults can be injected per path prefix, either with [`MockServer::inject`] or over HTTP for
+//! hosts using the `tn-core-mock` binary:
+//!
+//! - `POST /__mock/faults` with a [`FaultRule`], e.g.
+//!   `{"path_prefix": "/reading/bookapi", "kind": "status", "status": 429, "times": 1}`;
+//! - `DELETE /__mock/faults` clears them;
+//! - `GET /__mock/requests` lists the requests received so far.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STD;
+use ed25519_dalek::{Signer, SigningKey};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::config;
+
+pub const DEVICE_REGISTER: &str = "/service/2/device_register/";
+pub const DEVICE_ACTIVATE: &str = "/service/2/app_alert_check/";
+pub const DIRECTORY: &str = "/api/reader/directory/detail";
+pub const BOOK_PAGE: &str = "/page/";
+pub const SEARCH: &str = "/reading/bookapi/search/page/v/";
+pub const COMMENT_STATS: &str = "/reading/ugc/idea/stats";
+pub const COMMENT_LIST: &str = "/reading/ugc/idea/list";
+pub const REGISTER_KEY: &str = "/reading/crypt/registerkey";
+pub const BATCH_FULL: &str = "/reading/reader/batch_full/v";
+pub const BATCH_REQUEST: &str = "/reading/reader/full/v/";
+pub const MEDIA: &str = "/media/";
+pub const RELEASES: &str = "/repos/tomato-novel/app/releases";
+pub const DOWNLOAD: &str = "/releases/download/";
+pub const LATEST_DOWNLOAD: &str = "/releases/latest/download";
+
+/// Book served by the directory, search and chapter endpoints.
+pub const BOOK_ID: &str = "7143038691944959011";
+/// Release asset offered by the release feed.
+pub const RELEASE_ASSET: &str = "TomatoNovel-v1.4.0-windows.zip";
+pub const RELEASE_VERSION: &str = "1.4.0";
+
+const CONTROL: &str = "/__mock/";
+const FIRST_INSTALL_ID: u64 = 7_300_000_000_000_000_001;
+const SIGNING_SEED: [u8; 32] = [7; 32];
+const MEDIA_ETAG: &str = "\"cover-v1\"";
+/// A 1x1 PNG, served for every media path.
+const PIXEL_PNG_B64: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP8z8BQDwAEhQGAhKmMIQAAAABJRU5ErkJggg==";
+
+static ASSET: Lazy<Vec<u8>> = Lazy::new(|| (0..256 * 1024).map(|i| (i % 251) as u8).collect());
+
+static CHAPTERS: &[(&str, &str)] = &[
+    ("7143038718092067336", "第1章 穿越"),
+    ("7143038718394057228", "第2章 系统觉醒"),
+    ("7143038718700241416", "第3章 初入宗门"),
+];
+
+static BOOKS: &[(&str, &str, &str)] = &[
+    (BOOK_ID, "我在宗门当杂役", "青山客"),
+    ("7160542148325117988", "都市修仙记", "夜半听雨"),
+    ("7198765432109876543", "星海归途", "北冥有鱼"),
+];
+
+/// A misbehaviour applied to matching requests before they are routed.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Fault {
+    /// Wait this long, then answer normally (or with any other matching fault).
+    Delay { ms: u64 },
+    /// Answer with this status and a JSON error body; 429 also sets `Retry-After`.
+    Status { status: u16 },
+    /// Answer 200 with a truncated JSON body.
+    MalformedJson,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FaultRule {
+    /// Applies to request paths starting with this prefix.
+    pub path_prefix: String,
+    #[serde(flatten)]
+    pub fault: Fault,
+    /// Number of requests to affect; unlimited when absent.
+    #[serde(default)]
+    pub times: Option<u32>,
+}
+
+/// A request as the mock received it.
+#[derive(Clone, Debug, Serialize)]
+pub struct ReceivedRequest {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    /// Header names are lowercase.
+    pub headers: HashMap<String, String>,
+    #[serde(skip)]
+    pub body: Vec<u8>,
+}
+
+impl ReceivedRequest {
+    fn query_param(&self, name: &str) -> Option<String> {
+        serde_urlencoded::from_str::<Vec<(String, String)>>(&self.query)
+            .ok()?
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    fn cookie(&self, name: &str) -> Option<String> {
+        self.headers.get("cookie")?.split(';').find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            (key == name).then(|| value.to_string())
+        })
+    }
+
+    fn json_body(&self) -> Option<Value> {
+        serde_json::from_slice(&self.body).ok()
+    }
+}
+
+#[derive(Default)]
+struct State {
+    faults: Mutex<Vec<FaultRule>>,
+    requests: Mutex<Vec<ReceivedRequest>>,
+    issued: AtomicU64,
+}
+
+/// A running mock server; it shuts down when dropped.
+pub struct MockServer {
+    url: String,
+    server: Arc<Server>,
+    state: Arc<State>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Starts a server on an ephemeral port of 127.0.0.1.
+    pub fn start() -> Result<Self, String> {
+        Self::bind("127.0.0.1:0")
+    }
+
+    pub fn bind(addr: &str) -> Result<Self, String> {
+        let server = Arc::new(Server::http(addr).map_err(|err| err.to_string())?);
+        let local: SocketAddr = server
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| "mock server needs a TCP address".to_string())?;
+        let state = Arc::new(State::default());
+        let acceptor = {
+            let server = Arc::clone(&server);
+            let state = Arc::clone(&state);
+            thread::spawn(move || {
+                while let Ok(request) = server.recv() {
+                    let state = Arc::clone(&state);
+                    thread::spawn(move || handle(request, &state));
+                }
+            })
+        };
+        Ok(Self {
+            url: format!("http://{}", local),
+            server,
+            state,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    /// Base URL, e.g. `http://127.0.0.1:40123`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Endpoint configuration pointing every op at this server.
+    pub fn endpoints(&self) -> config::Endpoints {
+        let at = |path: &str| format!("{}{}", self.url, path);
+        config::Endpoints {
+            device_register: at(DEVICE_REGISTER),
+            device_activate: at(DEVICE_ACTIVATE),
+            directory: at(DIRECTORY),
+            book_page: at(BOOK_PAGE),
+            search: at(SEARCH),
+            comment_stats: at(COMMENT_STATS),
+            comment_list: at(COMMENT_LIST),
+            register_key: at(REGISTER_KEY),
+            batch_full: at(&format!("{}?", BATCH_FULL)),
+            batch_request: at(&format!("{}?item_id=", BATCH_REQUEST)),
+        }
+    }
+
+    /// `url` joined with a path such as [`RELEASES`] or `format!("{}cover.png", MEDIA)`.
+    pub fn url_for(&self, path: &str) -> String {
+        format!("{}{}", self.url, path)
+    }
+
+    /// Hex ed25519 public key matching the signatures served for release assets.
+    pub fn release_public_key() -> String {
+        hex(SigningKey::from_bytes(&SIGNING_SEED)
+            .verifying_key()
+            .as_bytes())
+    }
+
+    /// SHA-256 of [`RELEASE_ASSET`], as hex.
+    pub fn release_sha256() -> String {
+        hex(&Sha256::digest(ASSET.as_slice()))
+    }
+
+    pub fn inject(&self, rule: FaultRule) {
+        self.state.faults.lock().unwrap().push(rule);
+    }
+
+    pub fn clear_faults(&self) {
+        self.state.faults.lock().unwrap().clear();
+    }
+
+    /// Requests received so far, oldest first.
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// Blocks until the server stops, which for a running server is never.
+    pub fn wait(mut self) {
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+    }
+}
+
+type Reply = (u16, Vec<(&'static str, String)>, Vec<u8>);
+
+fn handle(mut request: Request, state: &State) {
+    let mut body = Vec::new();
+    let _ = request.as_reader().read_to_end(&mut body);
+    let (path, query) = match request.url().split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (request.url().to_string(), String::new()),
+    };
+    let received = ReceivedRequest {
+        method: request.method().as_str().to_string(),
+        path,
+        query,
+        headers: request
+            .headers()
+            .iter()
+            .map(|h| {
+                (
+                    h.field.as_str().as_str().to_ascii_lowercase(),
+                    h.value.to_string(),
+                )
+            })
+            .collect(),
+        body,
+    };
+
+    let (status, headers, body) = if received.path.starts_with(CONTROL) {
+        control(&received, state)
+    } else {
+        state.requests.lock().unwrap().push(received.clone());
+        match apply_faults(&received.path, state) {
+            Some(reply) => reply,
+            None => route(request.method(), &received, state),
+        }
+    };
+    let mut response = Response::from_data(body).with_status_code(status);
+    for (name, value) in headers {
+        if let Ok(header) = Header::from_bytes(name.as_bytes(), value.as_bytes()) {
+            response = response.with_header(header);
+        }
+    }
+    let _ = request.respond(response);
+}
+
+/// Sleeps for matching delays and returns the reply of the first other matching fault.
+fn apply_faults(path: &str, state: &State) -> Option<Reply> {
+    let mut delay = 0;
+    let mut reply = None;
+    {
+        let mut faults = state.faults.lock().unwrap();
+        for rule in faults.iter_mut() {
+            if !path.starts_with(&rule.path_prefix) || rule.times == Some(0) {
+                continue;
+            }
+            let applied = match &rule.fault {
+                Fault::Delay { ms } => {
+                    delay += ms;
+                    true
+                }
+                Fault::Status { status } if reply.is_none() => {
+                    let mut headers = vec![content_type_json()];
+                    if *status == 429 {
+                        headers.push(("Retry-After", "1".to_string()));
+                    }
+                    let body = json!({ "code": status, "message": "injected fault", "data": null });
+                    reply = Some((*status, headers, body.to_string().into_bytes()));
+                    true
+                }
+                Fault::MalformedJson if reply.is_none() => {
+                    let body = b"{\"code\":0,\"message\":\"SUCCESS\",\"data\":{\"item".to_vec();
+                    reply = Some((200, vec![content_type_json()], body));
+                    true
+                }
+                _ => false,
+            };
+            if applied && let Some(times) = rule.times.as_mut() {
+                *times -= 1;
+            }
+        }
+    }
+    if delay > 0 {
+        thread::sleep(Duration::from_millis(delay));
+    }
+    reply
+}
+
+fn control(request: &ReceivedRequest, state: &State) -> Reply {
+    match (request.method.as_str(), &request.path[CONTROL.len()..]) {
+        ("GET", "requests") => json_reply(200, json!(*state.requests.lock().unwrap())),
+        ("POST", "faults") => match serde_json::from_slice::<FaultRule>(&request.body) {
+            Ok(rule) => {
+                state.faults.lock().unwrap().push(rule);
+                json_reply(200, json!({ "ok": true }))
+            }
+            Err(err) => json_reply(400, json!({ "error": err.to_string() })),
+        },
+        ("DELETE", "faults") => {
+            state.faults.lock().unwrap().clear();
+            json_reply(200, json!({ "ok": true }))
+        }
+        _ => json_reply(404, json!({ "error": "unknown control route" })),
+    }
+}
+
+fn route(method: &Method, request: &ReceivedRequest, state: &State) -> Reply {
+    let path = request.path.as_str();
+    match method {
+        Method::Post if path == DEVICE_REGISTER => device_register(request, state),
+        Method::Get if path == DEVICE_ACTIVATE => device_activate(request),
+        Method::Get if path == DIRECTORY => directory(request),
+        Method::Get if path.starts_with(BOOK_PAGE) => (
+            200,
+            vec![("Content-Type", "text/html; charset=utf-8".to_string())],
+            b"<!DOCTYPE html><html><head><title>fanqie</title></head><body></body></html>".to_vec(),
+        ),
+        Method::Get if path == SEARCH => search(request),
+        Method::Post if path.starts_with(COMMENT_STATS) => comment_stats(request),
+        Method::Post if path.starts_with(COMMENT_LIST) => comment_list(request),
+        Method::Post if path == REGISTER_KEY => register_key(request),
+        Method::Get if path == BATCH_FULL => batch_full(request),
+        Method::Get if path == BATCH_REQUEST => batch_request(request),
+        Method::Get | Method::Head if path.starts_with(MEDIA) => media(request),
+        Method::Get if path == RELEASES => releases(request),
+        Method::Get | Method::Head if path == LATEST_DOWNLOAD => (
+            302,
+            vec![("Location", format!("{}{}", DOWNLOAD, RELEASE_ASSET))],
+            Vec::new(),
+        ),
+        Method::Get | Method::Head if path.starts_with(DOWNLOAD) => {
+            download(request, &path[DOWNLOAD.len()..])
+        }
+        _ => json_reply(
+            404,
+            json!({ "code": 404, "message": "not found", "data": null }),
+        ),
+    }
+}
+
+fn device_register(request: &ReceivedRequest, state: &State) -> Reply {
+    if request.body.is_empty() {
+        return api_error(400, "empty device payload");
+    }
+    let serial = state.issued.fetch_add(1, Ordering::Relaxed);
+    let install_id = FIRST_INSTALL_ID + serial * 2;
+    let device_id = install_id + 1;
+    json_reply(
+        200,
+        json!({
+            "server_time": 1_700_000_000u64 + serial,
+            "device_id": device_id,
+            "install_id": install_id,
+            "device_id_str": device_id.to_string(),
+            "install_id_str": install_id.to_string(),
+            "new_user": 1,
+        }),
+    )
+}
+
+fn device_activate(request: &ReceivedRequest) -> Reply {
+    if request.query_param("tt_info").is_none() || request.query_param("aid").is_none() {
+        return api_error(400, "tt_info and aid are required");
+    }
+    json_reply(200, json!({ "message": "success", "status": 0 }))
+}
+
+fn directory(request: &ReceivedRequest) -> Reply {
+    if request.query_param("bookId").as_deref() != Some(BOOK_ID) {
+        return json_reply(
+            200,
+            json!({ "code": 100, "message": "book not found", "data": null }),
+        );
+    }
+    let chapters: Vec<Value> = CHAPTERS
+        .iter()
+        .enumerate()
+        .map(|(index, (item_id, title))| {
+            json!({
+                "itemId": item_id,
+                "title": title,
+                "volume_name": "第一卷 外门",
+                "firstPassTime": (1_660_000_000 + index * 86_400).to_string(),
+                "isChapterLock": false,
+            })
+        })
+        .collect();
+    ok_reply(json!({
+        "bookInfo": { "bookId": BOOK_ID, "bookName": BOOKS[0].1, "author": BOOKS[0].2 },
+        "allItemIds": CHAPTERS.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+        "volumeNameList": ["第一卷 外门"],
+        "chapterListWithVolume": [chapters],
+    }))
+}
+
+fn search(request: &ReceivedRequest) -> Reply {
+    if request.cookie("install_id").is_none() {
+        return api_error(403, "install_id cookie required");
+    }
+    let query = request.query_param("q").unwrap_or_default();
+    let books: Vec<Value> = BOOKS
+        .iter()
+        .filter(|(_, title, author)| title.contains(&query) || author.contains(&query))
+        .map(|(book_id, title, author)| {
+            json!({
+                "book_id": book_id,
+                "book_name": title,
+                "author": author,
+                "abstract": format!("{}的作品。", author),
+                "category": "玄幻",
+                "creation_status": "1",
+                "word_number": "1024000",
+            })
+        })
+        .collect();
+    ok_reply(json!({
+        "has_more": false,
+        "offset": 0,
+        "search_book_data_list": books,
+    }))
+}
+
+fn comment_stats(request: &ReceivedRequest) -> Reply {
+    let Some(chapter_id) = chapter_segment(&request.path, COMMENT_STATS) else {
+        return api_error(404, "unknown chapter");
+    };
+    if request.query_param("iid").is_none() {
+        return api_error(403, "iid required");
+    }
+    let Some(item_version) = request
+        .json_body()
+        .and_then(|body| body["item_version"].as_str().map(str::to_string))
+    else {
+        return api_error(400, "item_version required");
+    };
+    ok_reply(json!({
+        "chapter_id": chapter_id,
+        "item_version": item_version,
+        "idea_count": { "0": 3, "2": 1, "5": 7 },
+        "total": 11,
+    }))
+}
+
+fn comment_list(request: &ReceivedRequest) -> Reply {
+    let Some(chapter_id) = chapter_segment(&request.path, COMMENT_LIST) else {
+        return api_error(404, "unknown chapter");
+    };
+    let count = request
+        .json_body()
+        .and_then(|body| body["count"].as_u64())
+        .unwrap_or(10)
+        .min(5);
+    let comments: Vec<Value> = (0..count)
+        .map(|index| {
+            json!({
+                "comment_id": format!("7301{:015}", index),
+                "text": format!("第{}条评论：写得真好！", index + 1),
+                "digg_count": 10 - index,
+                "user_info": { "user_name": format!("读者{}", index + 1) },
+            })
+        })
+        .collect();
+    ok_reply(json!({
+        "chapter_id": chapter_id,
+        "comments": comments,
+        "has_more": false,
+    }))
+}
+
+fn register_key(request: &ReceivedRequest) -> Reply {
+    if request.cookie("install_id").is_none() {
+        return api_error(403, "install_id cookie required");
+    }
+    if request.json_body().is_none() {
+        return api_error(400, "JSON body required");
+    }
+    ok_reply(json!({ "key": BASE64_STD.encode([0x5au8; 16]), "keyver": 1 }))
+}
+
+fn batch_full(request: &ReceivedRequest) -> Reply {
+    let ids = request.query_param("item_ids").unwrap_or_default();
+    let chapters: serde_json::Map<String, Value> = ids
+        .split(',')
+        .filter(|id| !id.is_empty())
+        .map(|id| (id.to_string(), chapter_json(id)))
+        .collect();
+    ok_reply(Value::Object(chapters))
+}
+
+fn batch_request(request: &ReceivedRequest) -> Reply {
+    match request.query_param("item_id") {
+        Some(id) if !id.is_empty() => ok_reply(chapter_json(&id)),
+        _ => api_error(400, "item_id required"),
+    }
+}
+
+fn chapter_json(item_id: &str) -> Value {
+    let title = CHAPTERS
+        .iter()
+        .find(|(id, _)| *id == item_id)
+        .map_or("未知章节", |(_, title)| title);
+    json!({
+        "title": title,
+        "content": format!("<p>{}</p><p>山风吹过，少年抬头望向远方。</p>", title),
+        "novel_data": { "book_id": BOOK_ID, "item_id": item_id },
+    })
+}
+
+fn media(request: &ReceivedRequest) -> Reply {
+    if request.headers.get("if-none-match").map(String::as_str) == Some(MEDIA_ETAG) {
+        return (304, vec![("ETag", MEDIA_ETAG.to_string())], Vec::new());
+    }
+    let png = BASE64_STD
+        .decode(PIXEL_PNG_B64)
+        .expect("embedded PNG is valid base64");
+    (
+        200,
+        vec![
+            ("Content-Type", "image/png".to_string()),
+            ("ETag", MEDIA_ETAG.to_string()),
+            ("Cache-Control", "max-age=86400".to_string()),
+        ],
+        png,
+    )
+}
+
+fn releases(request: &ReceivedRequest) -> Reply {
+    let host = request
+        .headers
+        .get("host")
+        .map_or("localhost", String::as_str);
+    let asset = |name: &str, size: usize| {
+        json!({
+            "name": name,
+            "size": size,
+            "browser_download_url": format!("http://{}{}{}", host, DOWNLOAD, name),
+        })
+    };
+    json_reply(
+        200,
+        json!([
+            {
+                "tag_name": "v1.5.0-beta.1",
+                "name": "1.5.0 beta 1",
+                "draft": false,
+                "prerelease": true,
+                "body": "Preview build.",
+                "published_at": "2024-06-01T08:00:00Z",
+                "assets": [asset("TomatoNovel-v1.5.0-beta.1-windows.zip", 1024)],
+            },
+            {
+                "tag_name": format!("v{}", RELEASE_VERSION),
+                "name": RELEASE_VERSION,
+                "draft": false,
+                "prerelease": false,
+                "body": "Bug fixes.",
+                "published_at": "2024-05-01T08:00:00Z",
+                "assets": [asset(RELEASE_ASSET, ASSET.len()), asset("SHA256SUMS", 0)],
+            },
+        ]),
+    )
+}
+
+fn download(request: &ReceivedRequest, name: &str) -> Reply {
+    let binary = || ("Content-Type", "application/octet-stream".to_string());
+    if name == "SHA256SUMS" {
+        let listing = format!("{}  {}\n", MockServer::release_sha256(), RELEASE_ASSET);
+        return (
+            200,
+            vec![("Content-Type", "text/plain".to_string())],
+            listing.into_bytes(),
+        );
+    }
+    if name == format!("{}.sig", RELEASE_ASSET) {
+        let signature = SigningKey::from_bytes(&SIGNING_SEED).sign(&ASSET);
+        return (200, vec![binary()], signature.to_bytes().to_vec());
+    }
+    if name != RELEASE_ASSET {
+        return api_error(404, "no such asset");
+    }
+    let disposition = (
+        "Content-Disposition",
+        format!("attachment; filename=\"{}\"", RELEASE_ASSET),
+    );
+    let start = request
+        .headers
+        .get("range")
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.split('-').next())
+        .and_then(|start| start.parse::<usize>().ok());
+    match start {
+        Some(start) if start >= ASSET.len() => (416, vec![disposition], Vec::new()),
+        Some(start) => (
+            206,
+            vec![
+                binary(),
+                disposition,
+                (
+                    "Content-Range",
+                    format!("bytes {}-{}/{}", start, ASSET.len() - 1, ASSET.len()),
+                ),
+            ],
+            ASSET[start..].to_vec(),
+        ),
+        None => (200, vec![binary(), disposition], ASSET.clone()),
+    }
+}
+
+/// The chapter ID in `{prefix}/{chapter_id}/v1`.
+fn chapter_segment<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    path.strip_prefix(prefix)?
+        .strip_prefix('/')?
+        .strip_suffix("/v1")
+        .filter(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn content_type_json() -> (&'static str, String) {
+    (
+        "Content-Type",
+        "application/json; charset=utf-8".to_string(),
+    )
+}
+
+fn json_reply(status: u16, value: Value) -> Reply {
+    (
+        status,
+        vec![content_type_json()],
+        value.to_string().into_bytes(),
+    )
+}
+
+fn ok_reply(data: Value) -> Reply {
+    json_reply(
+        200,
+        json!({ "code": 0, "message": "SUCCESS", "data": data }),
+    )
+}
+
+fn api_error(status: u16, message: &str) -> Reply {
+    json_reply(
+        status,
+        json!({ "code": status, "message": message, "data": null }),
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,261 @@
+//! Operation registry behind `tn_core_call`.
+//!
+//! Every op the core understands is a [`CoreOperation`] registered here under its name. The
+//! built-in ops are registered on first use; embedders can add their own with
+//! [`register_operation`] and they become reachable through the FFI exactly like the built-ins.
+//!
+//! Payloads are validated before the handler runs (see [`crate::validate`]); failures come back
+//! as a [`CoreError`] listing every offending field.
+//!
+//! Any payload may set `dry_run: true` (the field is reserved for this): the op then returns
+//! `{"dry_run": true, "requests": [...]}`, listing the HTTP requests it would have sent (method,
+//! URL with query, headers and body) without sending them. Ops whose metadata does not mark them
+//! as networked are not run in a dry run.
+//!
+//! Each operation publishes JSON Schemas for its payload (derived from the payload type) and its
+//! result, available through [`describe_operation`], the `describe_op` op and
+//! `tn_core_describe_op`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Instant;
+
+use once_cell::sync::Lazy;
+use schemars::SchemaGenerator;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+
+use crate::dry_run;
+use crate::error::{CoreError, ErrorKind};
+use crate::metrics;
+use crate::validate::{self, Validator};
+
+pub use schemars::{JsonSchema, Schema, json_schema};
+
+/// Descriptive information published alongside an operation.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct OperationMetadata {
+    pub summary: String,
+    pub category: String,
+    /// Whether the op talks to the network (as opposed to local state only).
+    pub network: bool,
+}
+
+/// A single named operation with a typed payload.
+pub trait CoreOperation: Send + Sync + 'static {
+    type Payload: DeserializeOwned + JsonSchema;
+
+    fn name(&self) -> &str;
+
+    fn metadata(&self) -> OperationMetadata {
+        OperationMetadata::default()
+    }
+
+    /// Schema of the value returned on success. Defaults to accepting any JSON value.
+    fn response_schema(&self) -> Schema {
+        Schema::default()
+    }
+
+    /// Semantic checks run after the payload deserialized cleanly. Defaults to none.
+    fn validate(&self, _payload: &Self::Payload, _validator: &mut Validator) {}
+
+    fn handle(&self, payload: Self::Payload) -> Result<Value, String>;
+}
+
+/// Object-safe view of a [`CoreOperation`] taking the raw JSON payload.
+trait ErasedOperation: Send + Sync {
+    fn metadata(&self) -> OperationMetadata;
+    fn request_schema(&self) -> &Schema;
+    fn response_schema(&self) -> &Schema;
+    fn call(&self, payload: &[u8]) -> Result<Value, CoreError>;
+}
+
+/// A registered operation, with its schemas built on first use and kept for every later call.
+struct Registered<O> {
+    op: O,
+    request_schema: OnceLock<Schema>,
+    response_schema: OnceLock<Schema>,
+}
+
+impl<O: CoreOperation> Registered<O> {
+    fn erased(op: O) -> Arc<dyn ErasedOperation> {
+        Arc::new(Self {
+            op,
+            request_schema: OnceLock::new(),
+            response_schema: OnceLock::new(),
+        })
+    }
+}
+
+impl<O: CoreOperation> ErasedOperation for Registered<O> {
+    fn metadata(&self) -> OperationMetadata {
+        self.op.metadata()
+    }
+
+    fn request_schema(&self) -> &Schema {
+        self.request_schema
+            .get_or_init(|| SchemaGenerator::default().into_root_schema_for::<O::Payload>())
+    }
+
+    fn response_schema(&self) -> &Schema {
+        self.response_schema
+            .get_or_init(|| self.op.response_schema())
+    }
+
+    fn call(&self, payload: &[u8]) -> Result<Value, CoreError> {
+        // An empty payload is treated as `{}` so ops without required fields can be called bare.
+        let mut value = if payload.iter().all(u8::is_ascii_whitespace) {
+            Value::Object(Default::default())
+        } else {
+            serde_json::from_slice(payload).map_err(|err| {
+                let mut validator = Validator::new();
+                validator.push("$", format!("invalid JSON: {}", err));
+                CoreError::validation(validator.into_errors())
+            })?
+        };
+
+        let dry_run = take_dry_run(&mut value)?;
+
+        let mut validator = Validator::new();
+        validate::check_schema(self.request_schema().as_value(), &value, &mut validator);
+        validator.finish()?;
+
+        let payload: O::Payload = serde_json::from_value(value).map_err(|err| {
+            let mut validator = Validator::new();
+            validator.push("$", err.to_string());
+            CoreError::validation(validator.into_errors())
+        })?;
+        let mut validator = Validator::new();
+        self.op.validate(&payload, &mut validator);
+        validator.finish()?;
+
+        if dry_run {
+            let network = self.op.metadata().network;
+            return dry_run::run(network, || self.op.handle(payload)).map_err(CoreError::from);
+        }
+        self.op.handle(payload).map_err(CoreError::from)
+    }
+}
+
+/// Removes the reserved `dry_run` flag from a payload object and returns it.
+fn take_dry_run(value: &mut Value) -> Result<bool, CoreError> {
+    let Some(flag) = value.as_object_mut().and_then(|map| map.remove("dry_run")) else {
+        return Ok(false);
+    };
+    flag.as_bool().ok_or_else(|| {
+        let mut validator = Validator::new();
+        validator.push("dry_run", "must be a boolean");
+        CoreError::validation(validator.into_errors())
+    })
+}
+
+static REGISTRY: Lazy<RwLock<HashMap<String, Arc<dyn ErasedOperation>>>> = Lazy::new(|| {
+    let mut registry = Registry::default();
+    crate::api::register_builtin(&mut registry);
+    RwLock::new(registry.ops)
+});
+
+/// Collects operations during start-up before the registry is published.
+#[derive(Default)]
+pub(crate) struct Registry {
+    ops: HashMap<String, Arc<dyn ErasedOperation>>,
+}
+
+impl Registry {
+    pub(crate) fn add<O: CoreOperation>(&mut self, op: O) {
+        self.ops
+            .insert(op.name().to_string(), Registered::erased(op));
+    }
+}
+
+/// Registers `op` under its name. Fails if an operation with that name already exists.
+pub fn register_operation<O: CoreOperation>(op: O) -> Result<(), String> {
+    let name = op.name().to_string();
+    if name.is_empty() {
+        return Err("operation name must not be empty".to_string());
+    }
+    let mut registry = REGISTRY.write().unwrap();
+    if registry.contains_key(&name) {
+        return Err(format!("core operation already registered: {}", name));
+    }
+    registry.insert(name, Registered::erased(op));
+    Ok(())
+}
+
+/// Removes an operation, returning whether it was registered.
+pub fn unregister_operation(name: &str) -> bool {
+    REGISTRY.write().unwrap().remove(name).is_some()
+}
+
+/// Names of all registered operations, sorted.
+pub fn operation_names() -> Vec<String> {
+    let mut names: Vec<String> = REGISTRY.read().unwrap().keys().cloned().collect();
+    names.sort();
+    names
+}
+
+pub fn operation_metadata(name: &str) -> Option<OperationMetadata> {
+    REGISTRY.read().unwrap().get(name).map(|op| op.metadata())
+}
+
+/// Returns name, metadata and request/response schemas of the operation registered as `name`.
+pub fn describe_operation(name: &str) -> Option<Value> {
+    let handler = REGISTRY.read().unwrap().get(name).cloned()?;
+    let metadata = handler.metadata();
+    Some(json!({
+        "name": name,
+        "summary": metadata.summary,
+        "category": metadata.category,
+        "network": metadata.network,
+        "request_schema": handler.request_schema(),
+        "response_schema": handler.response_schema(),
+    }))
+}
+
+/// Whether the payload schema of the operation registered as `name` declares `field`.
+#[cfg(feature = "rpc")]
+pub(crate) fn accepts_field(name: &str, field: &str) -> bool {
+    let Some(handler) = REGISTRY.read().unwrap().get(name).cloned() else {
+        return false;
+    };
+    handler.request_schema().as_value()["properties"]
+        .get(field)
+        .is_some()
+}
+
+/// Runs the operation registered as `op` with a raw JSON payload, inside an `op` span.
+pub fn call_operation(op: &str, payload: &[u8]) -> Result<Value, CoreError> {
+    let span = tracing::info_span!("op", op = %op);
+    let _entered = span.enter();
+    // Clone the handle so long-running ops do not hold the registry lock.
+    let handler = REGISTRY.read().unwrap().get(op).cloned();
+    // Unregistered names share one entry so callers cannot grow the metrics without bound.
+    let label = if handler.is_some() {
+        op
+    } else {
+        metrics::UNKNOWN_OP
+    };
+    let _scope = metrics::enter_op(Some(label.to_string()));
+    let started = Instant::now();
+    let result = match handler {
+        Some(handler) => handler.call(payload),
+        None => Err(CoreError::new(
+            ErrorKind::UnknownOperation,
+            format!("unknown core operation: {}", op),
+        )),
+    };
+    let elapsed = started.elapsed();
+    metrics::record_op(label, elapsed, result.as_ref().err().map(|err| err.kind));
+    let latency_ms = elapsed.as_millis() as u64;
+    match &result {
+        Ok(_) => tracing::info!(latency_ms, "op completed"),
+        Err(err) => tracing::warn!(
+            latency_ms,
+            error_kind = err.kind.as_str(),
+            error = %err.message,
+            "op failed"
+        ),
+    }
+    result
+}
@@ -0,0 +1,20 @@
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+/// Receives progress events emitted by long running operations as JSON values.
+pub type ProgressSink = Box<dyn Fn(&Value) + Send + Sync>;
+
+static SINK: Lazy<RwLock<Option<ProgressSink>>> = Lazy::new(|| RwLock::new(None));
+
+/// Installs (or removes, with `None`) the process wide progress sink.
+pub fn set_progress_sink(sink: Option<ProgressSink>) {
+    *SINK.write().unwrap() = sink;
+}
+
+pub(crate) fn emit(event: Value) {
+    if let Some(sink) = SINK.read().unwrap().as_ref() {
+        sink(&event);
+    }
+}
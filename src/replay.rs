@@ -0,0 +1,406 @@
+//! Offline replay of recorded traffic, configured under `replay` in [`crate::config`].
+//!
+//! The source is a HAR file (such as one written by the recorder in `har`) or a directory of
+//! `.har` files and `.json` fixtures of the form
+//!
+//! ```json
+//! {"request": {"method": "GET", "url": "https://host/path?a=1", "body": "..."},
+//!  "response": {"status": 200, "headers": {"content-type": "application/json"},
+//!               "body": {"any": "json"}, "body_b64": "..."}}
+//! ```
+//!
+//! Requests match on method, URL and query parameters (in any order), and on the body when
+//! `match_body` is set. Recorded values that were redacted match anything. When several
+//! recordings match, they are served in turn, starting over after the last.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STD;
+use once_cell::sync::Lazy;
+use reqwest::blocking::{Request, Response};
+use reqwest::header::{
+    CONTENT_ENCODING, CONTENT_LENGTH, HeaderName, HeaderValue, TRANSFER_ENCODING,
+};
+use reqwest::{Method, ResponseBuilderExt, StatusCode, Url};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::config::{self, Replay, ReplayMode};
+use crate::http::NetworkError;
+
+const REDACTED: &str = "[redacted]";
+
+static LOADED: Lazy<Mutex<Option<Loaded>>> = Lazy::new(|| Mutex::new(None));
+
+/// The parsed source, reloaded when the configured path or its modification time changes.
+struct Loaded {
+    source: String,
+    modified: Option<SystemTime>,
+    recordings: Vec<Recording>,
+    served: Vec<usize>,
+}
+
+struct Recording {
+    method: Method,
+    url: Url,
+    query: Vec<(String, String)>,
+    /// `None` when the body was not recorded in full and so cannot be compared.
+    body: Option<Vec<u8>>,
+    status: StatusCode,
+    headers: Vec<(String, String)>,
+    response_body: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct Fixture {
+    request: FixtureRequest,
+    response: FixtureResponse,
+}
+
+#[derive(Deserialize)]
+struct FixtureRequest {
+    #[serde(default = "default_method")]
+    method: String,
+    url: String,
+    #[serde(default)]
+    body: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct FixtureResponse {
+    #[serde(default = "default_status")]
+    status: u16,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<Value>,
+    #[serde(default)]
+    body_b64: Option<String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+/// What replay decided for a request.
+pub(crate) enum Outcome {
+    /// Replay is off, or passthrough mode found no match: send the request.
+    Network(Request),
+    /// Answered from the recording, or failed in strict mode.
+    Replayed(Result<Response, NetworkError>),
+}
+
+/// Answers `request` from the configured recording when replay is on.
+pub(crate) fn intercept(request: Request) -> Outcome {
+    let settings = config::current().replay.clone();
+    if settings.source.is_empty() {
+        return Outcome::Network(request);
+    }
+    let found = lookup(&settings, &request);
+    match found {
+        Ok(Some(response)) => Outcome::Replayed(Ok(response)),
+        Ok(None) if settings.mode == ReplayMode::Passthrough => Outcome::Network(request),
+        Ok(None) => {
+            tracing::warn!(
+                method = %request.method(),
+                url = %crate::logging::redact_text(request.url().as_str()),
+                "no recorded response matches request"
+            );
+            Outcome::Replayed(Err(failure(&request)))
+        }
+        Err(err) => {
+            tracing::warn!(source = %settings.source, error = %err, "replay source unusable");
+            Outcome::Replayed(Err(failure(&request)))
+        }
+    }
+}
+
+/// Validates and installs replay settings, returning the number of loaded recordings. An empty
+/// `source` turns replay off.
+pub(crate) fn configure(settings: Replay) -> Result<Value, String> {
+    let count = if settings.source.is_empty() {
+        0
+    } else {
+        load(&settings.source)?.len()
+    };
+    let patch = json!({ "replay": settings });
+    let current = config::update(&patch)?;
+    Ok(json!({ "replay": current.replay, "recordings": count }))
+}
+
+fn lookup(settings: &Replay, request: &Request) -> Result<Option<Response>, String> {
+    let mut loaded = LOADED.lock().unwrap();
+    let modified = modified(&settings.source);
+    let stale = loaded
+        .as_ref()
+        .is_none_or(|loaded| loaded.source != settings.source || loaded.modified != modified);
+    if stale {
+        let recordings = load(&settings.source)?;
+        *loaded = Some(Loaded {
+            source: settings.source.clone(),
+            modified,
+            served: vec![0; recordings.len()],
+            recordings,
+        });
+    }
+    let Some(loaded) = loaded.as_mut() else {
+        return Ok(None);
+    };
+
+    let query = query_pairs(request.url());
+    let body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .unwrap_or_default();
+    let chosen = loaded
+        .recordings
+        .iter()
+        .enumerate()
+        .filter(|(_, recording)| {
+            recording.method == request.method()
+                && same_resource(&recording.url, request.url())
+                && same_query(&recording.query, &query)
+                && (!settings.match_body || same_body(recording.body.as_deref(), body))
+        })
+        .min_by_key(|(index, _)| loaded.served[*index])
+        .map(|(index, _)| index);
+    let Some(index) = chosen else {
+        return Ok(None);
+    };
+    loaded.served[index] += 1;
+    let recording = &loaded.recordings[index];
+
+    let mut response = http::Response::builder()
+        .status(recording.status)
+        .url(request.url().clone());
+    for (name, value) in &recording.headers {
+        let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) else {
+            continue;
+        };
+        // The body is stored decoded and possibly truncated.
+        if [CONTENT_LENGTH, CONTENT_ENCODING, TRANSFER_ENCODING].contains(&name) {
+            continue;
+        }
+        response = response.header(name, value);
+    }
+    let response = response
+        .body(recording.response_body.clone())
+        .map_err(|err| err.to_string())?;
+    Ok(Some(Response::from(response)))
+}
+
+/// A `NetworkError` for requests replay cannot answer: a 501 from the recording.
+fn failure(request: &Request) -> NetworkError {
+    let response = http::Response::builder()
+        .status(StatusCode::NOT_IMPLEMENTED)
+        .url(request.url().clone())
+        .body(Vec::new())
+        .expect("static response parts are valid");
+    match Response::from(response).error_for_status() {
+        Err(err) => err,
+        Ok(_) => unreachable!("501 is an error status"),
+    }
+}
+
+/// The newest modification time of the source: for a directory, of the directory itself (which
+/// changes as files come and go) and of every file it loads, which can be edited in place.
+fn modified(source: &str) -> Option<SystemTime> {
+    let path = Path::new(source);
+    let own = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    if !path.is_dir() {
+        return own;
+    }
+    let files = fixture_files(path).ok()?;
+    files
+        .iter()
+        .filter_map(|file| fs::metadata(file).and_then(|meta| meta.modified()).ok())
+        .chain(own)
+        .max()
+}
+
+/// The `.har` and `.json` files in `dir`, in name order.
+fn fixture_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .map_err(|err| format!("{}: {}", dir.display(), err))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| {
+            file.extension()
+                .is_some_and(|ext| ext == "har" || ext == "json")
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn load(source: &str) -> Result<Vec<Recording>, String> {
+    let path = Path::new(source);
+    if !path.is_dir() {
+        return load_file(path);
+    }
+    let mut recordings = Vec::new();
+    for file in fixture_files(path)? {
+        recordings.extend(load_file(&file)?);
+    }
+    Ok(recordings)
+}
+
+fn load_file(path: &Path) -> Result<Vec<Recording>, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let value: Value =
+        serde_json::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+    if value.get("log").is_some() {
+        return Ok(value["log"]["entries"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(from_har_entry)
+            .collect());
+    }
+    let fixture: Fixture =
+        serde_json::from_value(value).map_err(|err| format!("{}: {}", path.display(), err))?;
+    from_fixture(fixture).map(|recording| vec![recording])
+}
+
+fn from_har_entry(entry: &Value) -> Option<Recording> {
+    let request = &entry["request"];
+    let response = &entry["response"];
+    let status = StatusCode::from_u16(response["status"].as_u64()? as u16).ok()?;
+    let url = Url::parse(request["url"].as_str()?).ok()?;
+    let body = match request.get("postData") {
+        None => Some(Vec::new()),
+        Some(post_data) => har_content(post_data),
+    };
+    let headers = response["headers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|header| {
+            Some((
+                header["name"].as_str()?.to_string(),
+                header["value"].as_str()?.to_string(),
+            ))
+        })
+        .collect();
+    Some(Recording {
+        method: request["method"].as_str()?.parse().ok()?,
+        query: query_pairs(&url),
+        url,
+        body,
+        status,
+        headers,
+        response_body: har_content(&response["content"]).unwrap_or_default(),
+    })
+}
+
+/// The bytes of a HAR `content` or `postData` object; `None` when truncated by the recorder.
+fn har_content(content: &Value) -> Option<Vec<u8>> {
+    if content["comment"]
+        .as_str()
+        .is_some_and(|comment| comment.starts_with("truncated"))
+    {
+        return None;
+    }
+    let text = content["text"].as_str().unwrap_or_default();
+    if content["encoding"] == "base64" {
+        BASE64_STD.decode(text).ok()
+    } else {
+        Some(text.as_bytes().to_vec())
+    }
+}
+
+fn from_fixture(fixture: Fixture) -> Result<Recording, String> {
+    let url = Url::parse(&fixture.request.url).map_err(|err| err.to_string())?;
+    let response_body = match (fixture.response.body_b64, fixture.response.body) {
+        (Some(b64), _) => BASE64_STD.decode(b64).map_err(|err| err.to_string())?,
+        (None, Some(body)) => body_bytes(body),
+        (None, None) => Vec::new(),
+    };
+    Ok(Recording {
+        method: fixture
+            .request
+            .method
+            .parse()
+            .map_err(|_| format!("invalid method: {}", fixture.request.method))?,
+        query: query_pairs(&url),
+        url,
+        body: Some(fixture.request.body.map(body_bytes).unwrap_or_default()),
+        status: StatusCode::from_u16(fixture.response.status).map_err(|err| err.to_string())?,
+        headers: fixture.response.headers.into_iter().collect(),
+        response_body,
+    })
+}
+
+/// Strings are used as they are; any other JSON value is serialized.
+fn body_bytes(body: Value) -> Vec<u8> {
+    match body {
+        Value::String(text) => text.into_bytes(),
+        other => other.to_string().into_bytes(),
+    }
+}
+
+fn query_pairs(url: &Url) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    pairs.sort();
+    pairs
+}
+
+fn same_resource(recorded: &Url, actual: &Url) -> bool {
+    recorded.scheme() == actual.scheme()
+        && recorded.host_str() == actual.host_str()
+        && recorded.port_or_known_default() == actual.port_or_known_default()
+        && recorded.path() == actual.path()
+}
+
+fn same_query(recorded: &[(String, String)], actual: &[(String, String)]) -> bool {
+    recorded.len() == actual.len()
+        && recorded.iter().all(|(name, value)| {
+            actual
+                .iter()
+                .any(|(n, v)| n == name && (value == REDACTED || v == value))
+        })
+}
+
+fn same_body(recorded: Option<&[u8]>, actual: &[u8]) -> bool {
+    let Some(recorded) = recorded else {
+        return true;
+    };
+    match (
+        serde_json::from_slice::<Value>(recorded),
+        serde_json::from_slice::<Value>(actual),
+    ) {
+        (Ok(recorded), Ok(actual)) => same_json(&recorded, &actual),
+        _ => recorded == actual,
+    }
+}
+
+fn same_json(recorded: &Value, actual: &Value) -> bool {
+    match (recorded, actual) {
+        (Value::String(text), _) if text == REDACTED => true,
+        (Value::Object(recorded), Value::Object(actual)) => {
+            recorded.len() == actual.len()
+                && recorded.iter().all(|(key, value)| {
+                    actual.get(key).is_some_and(|other| same_json(value, other))
+                })
+        }
+        (Value::Array(recorded), Value::Array(actual)) => {
+            recorded.len() == actual.len()
+                && recorded.iter().zip(actual).all(|(a, b)| same_json(a, b))
+        }
+        _ => recorded == actual,
+    }
+}
@@ -0,0 +1,273 @@
+//! JSON-RPC 2.0 over newline-delimited stdio, for hosts that would rather spawn a child process
+//! than load the `dylib`.
+//!
+//! Each input line is a request, a notification or a batch. Method names are operation names
+//! (`search_books`, `media_fetch_many`, ...) taking their payload as `params`, plus the client
+//! handle functions `client.create`, `client.execute` (`{handle, request}`) and `client.destroy`
+//! (`{handle}`). Requests run concurrently on a worker pool, so responses may come back out of
+//! order and must be matched by `id`.
+//!
+//! Progress events from long operations are written as `progress` notifications. For ops that
+//! take a `progress_id` and were not given one, the request `id` is used, so events can be tied
+//! to the call that caused them.
+
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde_json::{Map, Value, json};
+
+use crate::error::{CoreError, ErrorKind};
+use crate::ffi::{self, HandlePayload};
+use crate::{ops, progress};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const OPERATION_FAILED: i64 = -32000;
+
+const DEFAULT_WORKERS: usize = 8;
+
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl From<CoreError> for RpcError {
+    fn from(err: CoreError) -> Self {
+        let code = match err.kind {
+            ErrorKind::Validation => INVALID_PARAMS,
+            ErrorKind::UnknownOperation => METHOD_NOT_FOUND,
+            ErrorKind::Operation => OPERATION_FAILED,
+        };
+        let mut data = json!({ "error_kind": err.kind });
+        if !err.fields.is_empty() {
+            data["errors"] = json!(err.fields);
+        }
+        Self {
+            code,
+            message: err.message,
+            data: Some(data),
+        }
+    }
+}
+
+/// Serves JSON-RPC on the process's stdin and stdout until stdin is closed.
+pub fn serve_stdio() -> io::Result<()> {
+    serve(io::stdin().lock(), io::stdout(), DEFAULT_WORKERS)
+}
+
+/// Serves JSON-RPC read from `input` until EOF, writing responses and notifications to `output`.
+/// Requests still running at EOF are completed before this returns.
+///
+/// While serving, this installs the process-wide progress sink (see
+/// [`crate::set_progress_sink`]) and removes it again on return.
+pub fn serve<R, W>(input: R, output: W, workers: usize) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write + Send + 'static,
+{
+    let output = Arc::new(Mutex::new(output));
+    let sink_output = Arc::clone(&output);
+    progress::set_progress_sink(Some(Box::new(move |event| {
+        let notification = json!({ "jsonrpc": "2.0", "method": "progress", "params": event });
+        write_line(&sink_output, &notification);
+    })));
+
+    let (sender, receiver) = mpsc::channel::<Value>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    let pool: Vec<_> = (0..workers.max(1))
+        .map(|_| {
+            let receiver = Arc::clone(&receiver);
+            let output = Arc::clone(&output);
+            thread::spawn(move || {
+                loop {
+                    let message = receiver.lock().unwrap().recv();
+                    let Ok(message) = message else {
+                        break;
+                    };
+                    if let Some(response) = handle_message(message) {
+                        write_line(&output, &response);
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let mut result = Ok(());
+    for line in input.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Value>(&line) {
+            Ok(message) => {
+                let _ = sender.send(message);
+            }
+            Err(err) => write_line(
+                &output,
+                &error_response(Value::Null, RpcError::new(PARSE_ERROR, err.to_string())),
+            ),
+        }
+    }
+
+    drop(sender);
+    for worker in pool {
+        let _ = worker.join();
+    }
+    progress::set_progress_sink(None);
+    result
+}
+
+fn write_line<W: Write>(output: &Mutex<W>, value: &Value) {
+    let mut output = output.lock().unwrap();
+    let _ = serde_json::to_writer(&mut *output, value);
+    let _ = output.write_all(b"\n");
+    let _ = output.flush();
+}
+
+/// Handles a request, notification or batch; `None` when nothing should be written back.
+fn handle_message(message: Value) -> Option<Value> {
+    match message {
+        Value::Array(batch) if batch.is_empty() => Some(error_response(
+            Value::Null,
+            RpcError::new(INVALID_REQUEST, "empty batch"),
+        )),
+        Value::Array(batch) => {
+            let responses: Vec<Value> = batch.into_iter().filter_map(handle_request).collect();
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        message => handle_request(message),
+    }
+}
+
+fn handle_request(message: Value) -> Option<Value> {
+    let Value::Object(mut request) = message else {
+        return Some(error_response(
+            Value::Null,
+            RpcError::new(INVALID_REQUEST, "request must be an object"),
+        ));
+    };
+    let id = request.remove("id");
+    let reply_id = id.clone().unwrap_or(Value::Null);
+    if request.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return Some(error_response(
+            reply_id,
+            RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""),
+        ));
+    }
+    let Some(Value::String(method)) = request.remove("method") else {
+        return Some(error_response(
+            reply_id,
+            RpcError::new(INVALID_REQUEST, "method must be a string"),
+        ));
+    };
+    let params = match request.remove("params") {
+        None | Some(Value::Null) => Map::new(),
+        Some(Value::Object(params)) => params,
+        Some(_) => {
+            return Some(error_response(
+                reply_id,
+                RpcError::new(INVALID_PARAMS, "params must be an object"),
+            ));
+        }
+    };
+
+    let result = dispatch(&method, params, id.as_ref());
+    // Notifications (no `id`) never get a response, not even an error.
+    let id = id?;
+    Some(match result {
+        Ok(value) => json!({ "jsonrpc": "2.0", "id": id, "result": value }),
+        Err(err) => error_response(id, err),
+    })
+}
+
+fn dispatch(
+    method: &str,
+    mut params: Map<String, Value>,
+    id: Option<&Value>,
+) -> Result<Value, RpcError> {
+    match method {
+        "client.create" => {
+            let config = from_params(params)?;
+            let handle = ffi::register_client(config).map_err(operation_failed)?;
+            Ok(json!(HandlePayload { handle }))
+        }
+        "client.execute" => {
+            let handle = handle_param(&params)?;
+            let spec = params
+                .remove("request")
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "request is required"))?;
+            let spec = serde_json::from_value(spec)
+                .map_err(|err| RpcError::new(INVALID_PARAMS, err.to_string()))?;
+            let response = ffi::send_request(handle, spec).map_err(operation_failed)?;
+            Ok(json!(response))
+        }
+        "client.destroy" => {
+            let handle = handle_param(&params)?;
+            if ffi::destroy_client(handle) {
+                Ok(json!(HandlePayload { handle }))
+            } else {
+                Err(RpcError::new(INVALID_PARAMS, "invalid client handle"))
+            }
+        }
+        op => {
+            if let Some(id) = id
+                && !params.contains_key("progress_id")
+                && ops::accepts_field(op, "progress_id")
+            {
+                let progress_id = match id {
+                    Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                };
+                params.insert("progress_id".to_string(), Value::String(progress_id));
+            }
+            let payload =
+                serde_json::to_vec(&params).map_err(|err| operation_failed(err.to_string()))?;
+            Ok(ops::call_operation(op, &payload)?)
+        }
+    }
+}
+
+fn from_params<T: serde::de::DeserializeOwned>(params: Map<String, Value>) -> Result<T, RpcError> {
+    serde_json::from_value(Value::Object(params))
+        .map_err(|err| RpcError::new(INVALID_PARAMS, err.to_string()))
+}
+
+fn handle_param(params: &Map<String, Value>) -> Result<u64, RpcError> {
+    params
+        .get("handle")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "handle must be an unsigned integer"))
+}
+
+fn operation_failed(message: String) -> RpcError {
+    RpcError::new(OPERATION_FAILED, message)
+}
+
+fn error_response(id: Value, err: RpcError) -> Value {
+    let mut error = json!({ "code": err.code, "message": err.message });
+    if let Some(data) = err.data {
+        error["data"] = data;
+    }
+    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+}
@@ -0,0 +1,491 @@
+//! Field-level payload validation.
+//!
+//! Payloads are checked in two passes before an operation runs: a structural pass against the
+//! payload's JSON Schema (required fields, JSON types, enum values, unknown fields where the
+//! schema forbids them), then the payload's own [`Validate`] rules (URLs parse, IDs are numeric,
+//! counts are in range). Every problem is collected so callers see them all at once instead of
+//! the first serde error.
+
+use std::fmt::Display;
+
+use reqwest::Url;
+use serde_json::{Map, Value};
+
+use crate::error::{CoreError, FieldError};
+
+/// Semantic checks for a deserialized payload.
+pub trait Validate {
+    fn validate(&self, validator: &mut Validator);
+}
+
+/// Collects field problems for a single payload.
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, field: impl Into<String>, problem: impl Into<String>) -> &mut Self {
+        self.errors.push(FieldError {
+            field: field.into(),
+            problem: problem.into(),
+        });
+        self
+    }
+
+    pub fn non_empty(&mut self, field: &str, value: &str) -> &mut Self {
+        if value.trim().is_empty() {
+            self.push(field, "must not be empty");
+        }
+        self
+    }
+
+    /// Requires an absolute `http` or `https` URL.
+    pub fn url(&mut self, field: &str, value: &str) -> &mut Self {
+        if value.trim().is_empty() {
+            return self.push(field, "must not be empty");
+        }
+        match Url::parse(value.trim()) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => self,
+            Ok(url) => self.push(field, format!("unsupported URL scheme `{}`", url.scheme())),
+            Err(err) => self.push(field, format!("must be a valid URL ({})", err)),
+        }
+    }
+
+    /// Like [`Validator::url`], but `None` and empty strings are accepted.
+    pub fn optional_url(&mut self, field: &str, value: Option<&str>) -> &mut Self {
+        match value.filter(|v| !v.is_empty()) {
+            Some(value) => self.url(field, value),
+            None => self,
+        }
+    }
+
+    /// Requires a non-empty string of ASCII digits, as used for book, chapter and install IDs.
+    pub fn numeric(&mut self, field: &str, value: &str) -> &mut Self {
+        let value = value.trim();
+        if value.is_empty() {
+            self.push(field, "must not be empty")
+        } else if !value.bytes().all(|b| b.is_ascii_digit()) {
+            self.push(field, "must be numeric")
+        } else {
+            self
+        }
+    }
+
+    /// Like [`Validator::numeric`], but `None` and empty strings are accepted.
+    pub fn optional_numeric(&mut self, field: &str, value: Option<&str>) -> &mut Self {
+        match value.filter(|v| !v.is_empty()) {
+            Some(value) => self.numeric(field, value),
+            None => self,
+        }
+    }
+
+    pub fn range<T: PartialOrd + Display>(
+        &mut self,
+        field: &str,
+        value: T,
+        min: T,
+        max: T,
+    ) -> &mut Self {
+        if value < min || value > max {
+            self.push(field, format!("must be between {} and {}", min, max));
+        }
+        self
+    }
+
+    pub fn at_least<T: PartialOrd + Display>(
+        &mut self,
+        field: &str,
+        value: T,
+        min: T,
+    ) -> &mut Self {
+        if value < min {
+            self.push(field, format!("must be at least {}", min));
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn into_errors(self) -> Vec<FieldError> {
+        self.errors
+    }
+
+    /// Returns a validation [`CoreError`] listing every problem, or `Ok` if there were none.
+    pub fn finish(self) -> Result<(), CoreError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(CoreError::validation(self.errors))
+        }
+    }
+}
+
+/// Structural check of `value` against a root schema generated for the payload type.
+pub(crate) fn check_schema(schema: &Value, value: &Value, validator: &mut Validator) {
+    let defs = schema.get("$defs").and_then(Value::as_object);
+    check_node(schema, value, "$", defs, validator);
+}
+
+fn check_node(
+    schema: &Value,
+    value: &Value,
+    path: &str,
+    defs: Option<&Map<String, Value>>,
+    validator: &mut Validator,
+) {
+    let schema = resolve(schema, defs);
+    if let Some(branches) = schema
+        .get("anyOf")
+        .or_else(|| schema.get("oneOf"))
+        .and_then(Value::as_array)
+    {
+        check_branches(branches, value, path, defs, validator);
+        return;
+    }
+    if let Some(expected) = schema.get("type")
+        && !matches_type(expected, value)
+    {
+        validator.push(path, format!("must be {}", describe_type(expected)));
+        return;
+    }
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array)
+        && !allowed.contains(value)
+    {
+        let names: Vec<String> = allowed.iter().map(Value::to_string).collect();
+        validator.push(path, format!("must be one of {}", names.join(", ")));
+        return;
+    }
+    if let Some(number) = value.as_f64() {
+        if let Some(min) = schema.get("minimum").and_then(Value::as_f64)
+            && number < min
+        {
+            validator.push(path, format!("must be at least {}", min));
+        }
+        if let Some(max) = schema.get("maximum").and_then(Value::as_f64)
+            && number > max
+        {
+            validator.push(path, format!("must be at most {}", max));
+        }
+    }
+    if let Some(object) = value.as_object() {
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        for &name in &required {
+            if object.get(name).is_none_or(Value::is_null) {
+                validator.push(child_path(path, name), "is required");
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for (name, field) in object {
+            // A required field left null was reported above.
+            if field.is_null() && required.contains(&name.as_str()) {
+                continue;
+            }
+            let field_path = child_path(path, name);
+            // Fields without a schema of their own fall back on `additionalProperties`.
+            match properties
+                .and_then(|properties| properties.get(name))
+                .or_else(|| schema.get("additionalProperties"))
+            {
+                Some(Value::Bool(false)) => {
+                    validator.push(field_path, "is not a known field");
+                }
+                Some(field_schema) if field_schema.is_object() => {
+                    check_node(field_schema, field, &field_path, defs, validator);
+                }
+                _ => {}
+            }
+        }
+    }
+    if let (Some(items), Some(item_schema)) = (value.as_array(), schema.get("items")) {
+        for (index, item) in items.iter().enumerate() {
+            check_node(
+                item_schema,
+                item,
+                &format!("{}[{}]", path, index),
+                defs,
+                validator,
+            );
+        }
+    }
+}
+
+/// Accepts `value` if any branch does; otherwise reports the problems of the first branch that
+/// is not just `null`, which is how schemars renders `Option<T>`.
+fn check_branches(
+    branches: &[Value],
+    value: &Value,
+    path: &str,
+    defs: Option<&Map<String, Value>>,
+    validator: &mut Validator,
+) {
+    let mut reported = None;
+    for branch in branches {
+        let mut scratch = Validator::new();
+        check_node(branch, value, path, defs, &mut scratch);
+        if scratch.is_empty() {
+            return;
+        }
+        let is_null_branch = resolve(branch, defs).get("type") == Some(&Value::from("null"));
+        if reported.is_none() && !is_null_branch {
+            reported = Some(scratch);
+        }
+    }
+    match reported {
+        Some(scratch) => validator.errors.extend(scratch.errors),
+        None => {
+            validator.push(path, "does not match any allowed form");
+        }
+    }
+}
+
+fn resolve<'a>(schema: &'a Value, defs: Option<&'a Map<String, Value>>) -> &'a Value {
+    schema
+        .get("$ref")
+        .and_then(Value::as_str)
+        .and_then(|reference| reference.strip_prefix("#/$defs/"))
+        .and_then(|name| defs?.get(name))
+        .unwrap_or(schema)
+}
+
+fn child_path(parent: &str, name: &str) -> String {
+    if parent == "$" {
+        name.to_string()
+    } else {
+        format!("{}.{}", parent, name)
+    }
+}
+
+fn matches_type(expected: &Value, value: &Value) -> bool {
+    match expected {
+        Value::String(name) => matches_type_name(name, value),
+        Value::Array(names) => names
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|name| matches_type_name(name, value)),
+        _ => true,
+    }
+}
+
+fn matches_type_name(name: &str, value: &Value) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+fn describe_type(expected: &Value) -> String {
+    let names: Vec<&str> = match expected {
+        Value::String(name) => vec![name.as_str()],
+        Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    let names: Vec<String> = names
+        .into_iter()
+        .map(|name| match name {
+            "null" => "null".to_string(),
+            "integer" => "an integer".to_string(),
+            "array" | "object" => format!("an {}", name),
+            other => format!("a {}", other),
+        })
+        .collect();
+    names.join(" or ")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn problems(schema: Value, value: Value) -> Vec<(String, String)> {
+        let mut validator = Validator::new();
+        check_schema(&schema, &value, &mut validator);
+        validator
+            .into_errors()
+            .into_iter()
+            .map(|error| (error.field, error.problem))
+            .collect()
+    }
+
+    fn problem(field: &str, problem: &str) -> (String, String) {
+        (field.to_string(), problem.to_string())
+    }
+
+    #[test]
+    fn required_fields_must_be_present_and_not_null() {
+        let schema = json!({
+            "type": "object",
+            "properties": { "url": { "type": "string" }, "query": { "type": "string" } },
+            "required": ["url", "query"]
+        });
+        assert_eq!(
+            problems(schema.clone(), json!({ "query": null })),
+            [
+                problem("url", "is required"),
+                problem("query", "is required")
+            ]
+        );
+        assert!(problems(schema, json!({ "url": "u", "query": "q" })).is_empty());
+    }
+
+    #[test]
+    fn type_mismatches_name_the_expected_type() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "count": { "type": "integer", "minimum": 1, "maximum": 10 },
+                "flag": { "type": "boolean" },
+                "name": { "type": ["string", "null"] }
+            }
+        });
+        assert_eq!(
+            problems(
+                schema.clone(),
+                json!({ "count": 1.5, "flag": "yes", "name": 3 })
+            ),
+            [
+                problem("count", "must be an integer"),
+                problem("flag", "must be a boolean"),
+                problem("name", "must be a string or null"),
+            ]
+        );
+        assert_eq!(
+            problems(schema.clone(), json!({ "count": 11 })),
+            [problem("count", "must be at most 10")]
+        );
+        assert!(problems(schema, json!({ "count": 3, "name": null })).is_empty());
+        assert_eq!(
+            problems(json!({ "type": "object" }), json!([])),
+            [problem("$", "must be an object")]
+        );
+    }
+
+    #[test]
+    fn enum_values_are_listed() {
+        let schema = json!({
+            "type": "object",
+            "properties": { "strategy": { "enum": ["round_robin", "least_recently_used"] } }
+        });
+        assert_eq!(
+            problems(schema.clone(), json!({ "strategy": "random" })),
+            [problem(
+                "strategy",
+                r#"must be one of "round_robin", "least_recently_used""#
+            )]
+        );
+        assert!(problems(schema, json!({ "strategy": "round_robin" })).is_empty());
+    }
+
+    #[test]
+    fn branches_accept_any_match_and_report_the_first_non_null_one() {
+        // schemars renders `Option<Kind>` as a `$ref` or `null` branch.
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "kind": { "anyOf": [{ "$ref": "#/$defs/Kind" }, { "type": "null" }] },
+                "id": { "oneOf": [{ "type": "integer" }, { "type": "string" }] }
+            },
+            "$defs": { "Kind": { "type": "string", "enum": ["minisign", "ed25519"] } }
+        });
+        assert!(problems(schema.clone(), json!({ "kind": null, "id": "7" })).is_empty());
+        assert!(problems(schema.clone(), json!({ "kind": "ed25519", "id": 7 })).is_empty());
+        assert_eq!(
+            problems(schema.clone(), json!({ "kind": "rsa" })),
+            [problem("kind", r#"must be one of "minisign", "ed25519""#)]
+        );
+        assert_eq!(
+            problems(schema, json!({ "id": true })),
+            [problem("id", "must be an integer")]
+        );
+
+        let only_null = json!({ "anyOf": [{ "type": "null" }] });
+        assert_eq!(
+            problems(only_null, json!(1)),
+            [problem("$", "does not match any allowed form")]
+        );
+    }
+
+    #[test]
+    fn nested_problems_carry_their_path() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "items": { "type": "array", "items": { "$ref": "#/$defs/Item" } }
+            },
+            "$defs": {
+                "Item": {
+                    "type": "object",
+                    "properties": {
+                        "url": { "type": "string" },
+                        "retry": {
+                            "type": "object",
+                            "properties": { "times": { "type": "integer" } },
+                            "required": ["times"]
+                        }
+                    },
+                    "required": ["url"]
+                }
+            }
+        });
+        let value = json!({
+            "items": [
+                { "url": "a" },
+                { "retry": { "times": "twice" } },
+                { "url": "c", "retry": {} }
+            ]
+        });
+        assert_eq!(
+            problems(schema, value),
+            [
+                problem("items[1].url", "is required"),
+                problem("items[1].retry.times", "must be an integer"),
+                problem("items[2].retry.times", "is required"),
+            ]
+        );
+    }
+
+    #[test]
+    fn additional_properties_are_checked_or_refused() {
+        let map = json!({
+            "type": "object",
+            "properties": {
+                "headers": { "type": "object", "additionalProperties": { "type": "string" } }
+            }
+        });
+        assert_eq!(
+            problems(map.clone(), json!({ "headers": { "a": "1", "b": 2 } })),
+            [problem("headers.b", "must be a string")]
+        );
+        assert!(problems(map, json!({ "headers": {}, "extra": 1 })).is_empty());
+
+        let closed = json!({
+            "type": "object",
+            "properties": { "url": { "type": "string" } },
+            "additionalProperties": false
+        });
+        assert_eq!(
+            problems(closed.clone(), json!({ "url": "u", "uri": "u" })),
+            [problem("uri", "is not a known field")]
+        );
+        assert!(problems(closed, json!({ "url": "u" })).is_empty());
+    }
+}
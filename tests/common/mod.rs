@@ -0,0 +1,12 @@
+//! Fixtures shared by the integration tests that run against the bundled mock server.
+
+use tomato_novel_network_core::mock::{Fault, FaultRule};
+
+/// A mock fault for requests under `path_prefix`, applied `times` times or for good.
+pub fn fault(path_prefix: &str, fault: Fault, times: Option<u32>) -> FaultRule {
+    FaultRule {
+        path_prefix: path_prefix.to_string(),
+        fault,
+        times,
+    }
+}
@@ -0,0 +1,264 @@
+//! Fault injection rules (`cargo test --features fault_injection,testing`).
+//!
+//! Global rules apply to every request in the process, so each test holds [`LOCK`] while it
+//! sends requests, and tests that install global rules clear them again when done.
+
+use std::sync::{Mutex, MutexGuard, Once};
+
+use serde_json::{Value, json};
+use tomato_novel_network_core::blocking::Client;
+use tomato_novel_network_core::faults::{self, FaultConfig, FaultRule};
+use tomato_novel_network_core::mock::{self, MockServer};
+use tomato_novel_network_core::ops::{self, CoreOperation};
+
+const FAULT_HEADER: &str = "x-tn-core-fault";
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+fn lock() -> MutexGuard<'static, ()> {
+    LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Clears the global rules when dropped, so a failing test leaves none behind.
+struct GlobalRules;
+
+impl GlobalRules {
+    fn set(config: FaultConfig) -> Self {
+        faults::set(config).expect("valid rules");
+        GlobalRules
+    }
+}
+
+impl Drop for GlobalRules {
+    fn drop(&mut self) {
+        let _ = faults::set(FaultConfig::default());
+    }
+}
+
+fn status_rule(host: Option<&str>, op: Option<&str>, status: u16, rate: f64) -> FaultRule {
+    FaultRule {
+        host: host.map(str::to_string),
+        op: op.map(str::to_string),
+        status: Some(status),
+        status_rate: rate,
+        ..FaultRule::default()
+    }
+}
+
+fn client(seed: Option<u64>, rules: Vec<FaultRule>) -> Client {
+    Client::builder()
+        .faults(FaultConfig { seed, rules })
+        .build()
+        .expect("build client")
+}
+
+fn status(client: &Client, url: &str) -> u16 {
+    client.get(url).send().expect("send").status().as_u16()
+}
+
+/// Sends one request outside the mock, which only a synthetic status can answer.
+struct Probe(&'static str);
+
+impl CoreOperation for Probe {
+    type Payload = Value;
+
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn handle(&self, _payload: Value) -> Result<Value, String> {
+        let response = Client::builder()
+            .build()
+            .and_then(|client| client.get("http://probe.example.test/").send())
+            .map_err(|err| err.to_string())?;
+        Ok(json!(response.status().as_u16()))
+    }
+}
+
+fn probe(op: &str) -> u16 {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        ops::register_operation(Probe("fault_probe_a")).expect("register probe");
+        ops::register_operation(Probe("fault_probe_b")).expect("register probe");
+    });
+    let result = ops::call_operation(op, b"{}").expect("probe op");
+    result.as_u64().expect("status") as u16
+}
+
+#[test]
+fn host_rules_cover_subdomains_only() {
+    let _lock = lock();
+    let client = client(
+        None,
+        vec![
+            status_rule(Some("example.test"), None, 503, 1.0),
+            status_rule(None, None, 418, 1.0),
+        ],
+    );
+
+    assert_eq!(status(&client, "http://example.test/"), 503);
+    assert_eq!(status(&client, "http://API.Example.TEST/path"), 503);
+    assert_eq!(status(&client, "http://a.b.example.test/"), 503);
+    assert_eq!(status(&client, "http://notexample.test/"), 418);
+    assert_eq!(status(&client, "http://example.test.other/"), 418);
+
+    let response = client.get("http://example.test/").send().unwrap();
+    assert_eq!(response.headers()[FAULT_HEADER], "status");
+    assert_eq!(response.headers()["retry-after"], "1");
+}
+
+#[test]
+fn op_rules_cover_their_op_only() {
+    let _lock = lock();
+    let _rules = GlobalRules::set(FaultConfig {
+        seed: None,
+        rules: vec![
+            status_rule(None, Some("fault_probe_a"), 418, 1.0),
+            status_rule(Some("probe.example.test"), None, 503, 1.0),
+        ],
+    });
+
+    assert_eq!(probe("fault_probe_a"), 418);
+    assert_eq!(probe("fault_probe_b"), 503);
+    assert_eq!(
+        status(&client(None, Vec::new()), "http://probe.example.test/"),
+        503
+    );
+    assert_eq!(faults::current().rules.len(), 2);
+}
+
+#[test]
+fn invalid_rules_are_rejected() {
+    let config = |rule: FaultRule| FaultConfig {
+        seed: None,
+        rules: vec![status_rule(None, None, 500, 1.0), rule],
+    };
+    let invalid = [
+        (
+            FaultRule {
+                drop_rate: -0.1,
+                ..FaultRule::default()
+            },
+            "rules[1].drop_rate",
+        ),
+        (
+            FaultRule {
+                truncate_rate: 1.5,
+                ..FaultRule::default()
+            },
+            "rules[1].truncate_rate",
+        ),
+        (
+            FaultRule {
+                drop_rate: f64::NAN,
+                ..FaultRule::default()
+            },
+            "rules[1].drop_rate",
+        ),
+        (
+            FaultRule {
+                status_rate: 0.5,
+                ..FaultRule::default()
+            },
+            "rules[1].status: required",
+        ),
+        (status_rule(None, None, 99, 0.5), "rules[1].status: not"),
+        (status_rule(None, None, 600, 0.5), "rules[1].status: not"),
+    ];
+    for (rule, expected) in invalid {
+        let err = config(rule.clone()).validate().unwrap_err();
+        assert!(err.starts_with(expected), "{:?}: {}", rule, err);
+    }
+
+    let _lock = lock();
+    let err = faults::set(config(FaultRule {
+        status_rate: 2.0,
+        status: Some(500),
+        ..FaultRule::default()
+    }))
+    .unwrap_err();
+    assert!(err.starts_with("rules[1].status_rate"), "{}", err);
+    assert!(faults::current().rules.is_empty());
+
+    let edges = FaultRule {
+        drop_rate: 0.0,
+        truncate_rate: 1.0,
+        ..status_rule(None, None, 100, 1.0)
+    };
+    assert_eq!(config(edges).validate(), Ok(()));
+}
+
+#[test]
+fn seeded_rules_repeat_and_keep_their_own_sequence() {
+    let _lock = lock();
+    let server = MockServer::start().expect("start mock server");
+    let url = server.url_for(&format!("{}pixel.png", mock::MEDIA));
+    let coin = || vec![status_rule(None, None, 500, 0.5)];
+
+    let run = |client: &Client| (0..64).map(|_| status(client, &url)).collect::<Vec<_>>();
+    let reference = run(&client(Some(7), coin()));
+    assert!(reference.contains(&500) && reference.contains(&200));
+    assert_ne!(run(&client(Some(8), coin())), reference);
+
+    // Two clients with the same seed draw the same outcomes even when their requests interleave.
+    let (first, second) = (client(Some(7), coin()), client(Some(7), coin()));
+    let (firsts, seconds): (Vec<u16>, Vec<u16>) = (0..64)
+        .map(|_| (status(&first, &url), status(&second, &url)))
+        .unzip();
+    assert_eq!(firsts, reference);
+    assert_eq!(seconds, reference);
+
+    // Building and using a seeded client leaves the global sequence alone.
+    let _rules = GlobalRules::set(FaultConfig {
+        seed: Some(7),
+        rules: vec![status_rule(Some("127.0.0.1"), None, 500, 0.5)],
+    });
+    let plain = client(None, Vec::new());
+    let other = client(
+        Some(1),
+        vec![status_rule(Some("localhost"), None, 500, 0.5)],
+    );
+    let other_url = url.replace("127.0.0.1", "localhost");
+    let global: Vec<u16> = (0..64)
+        .map(|_| {
+            status(&other, &other_url);
+            status(&plain, &url)
+        })
+        .collect();
+    assert_eq!(global, reference);
+}
+
+#[test]
+fn truncated_bodies_keep_the_requested_length() {
+    let _lock = lock();
+    let server = MockServer::start().expect("start mock server");
+    let url = server.url_for(&format!("{}pixel.png", mock::MEDIA));
+    let full = client(None, Vec::new()).get(&url).send().unwrap();
+    assert!(full.headers().get(FAULT_HEADER).is_none());
+    let full = full.bytes().unwrap();
+
+    let truncate = |truncate_to| {
+        client(
+            None,
+            vec![FaultRule {
+                truncate_rate: 1.0,
+                truncate_to,
+                ..FaultRule::default()
+            }],
+        )
+        .get(&url)
+        .send()
+        .unwrap()
+    };
+
+    let response = truncate(Some(8));
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()[FAULT_HEADER], "truncate");
+    assert_eq!(response.bytes().unwrap(), full.slice(..8));
+
+    assert_eq!(
+        truncate(None).bytes().unwrap(),
+        full.slice(..full.len() / 2)
+    );
+    assert_eq!(truncate(Some(1 << 40)).bytes().unwrap(), full);
+}
@@ -0,0 +1,221 @@
+//! The client middleware chain, run against the bundled mock server
+//! (`cargo test --features testing`).
+
+mod common;
+
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tomato_novel_network_core::NetworkError;
+use tomato_novel_network_core::blocking::{Client, Request, Response};
+use tomato_novel_network_core::middleware::Middleware;
+use tomato_novel_network_core::mock::{self, Fault, MockServer};
+
+use common::fault;
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+/// Logs every hook it sees, and retries 503s up to three attempts when named "retry".
+struct Trace {
+    name: &'static str,
+    log: Log,
+}
+
+impl Middleware for Trace {
+    fn on_request(&self, request: &mut Request) -> Option<Response> {
+        let layers = match request.headers().get("x-layers") {
+            Some(previous) => format!("{},{}", previous.to_str().unwrap(), self.name),
+            None => self.name.to_string(),
+        };
+        request
+            .headers_mut()
+            .insert("x-layers", layers.parse().unwrap());
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("{} request", self.name));
+        None
+    }
+
+    fn on_response(&self, response: Response) -> Result<Response, NetworkError> {
+        let status = response.status().as_u16();
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", self.name, status));
+        Ok(response)
+    }
+
+    fn on_error(&self, error: NetworkError) -> Result<Response, NetworkError> {
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("{} error", self.name));
+        Err(error)
+    }
+
+    fn retry(&self, attempt: u32, outcome: &Result<Response, NetworkError>) -> Option<Duration> {
+        let unavailable = outcome.as_ref().is_ok_and(|r| r.status().as_u16() == 503);
+        (self.name == "retry" && unavailable && attempt < 3).then_some(Duration::ZERO)
+    }
+}
+
+/// Answers every request itself, or recovers from failed ones, with a canned response.
+struct Canned {
+    answer_requests: bool,
+}
+
+fn canned(status: u16, body: &'static str) -> Response {
+    let response = http::Response::builder().status(status).body(body).unwrap();
+    Response::from(reqwest::blocking::Response::from(response))
+}
+
+impl Middleware for Canned {
+    fn on_request(&self, _request: &mut Request) -> Option<Response> {
+        self.answer_requests.then(|| canned(203, "from cache"))
+    }
+
+    fn on_error(&self, _error: NetworkError) -> Result<Response, NetworkError> {
+        Ok(canned(200, "recovered"))
+    }
+}
+
+fn client(log: &Log, layers: &[&'static str], canned: Option<Canned>) -> Client {
+    let mut builder = Client::builder();
+    for &name in layers {
+        builder = builder.middleware(Trace {
+            name,
+            log: log.clone(),
+        });
+    }
+    if let Some(canned) = canned {
+        builder = builder.middleware(canned);
+    }
+    builder.build().unwrap()
+}
+
+fn media_url(server: &MockServer) -> String {
+    server.url_for(&format!("{}cover.png", mock::MEDIA))
+}
+
+#[test]
+fn layers_run_in_order_and_retry() {
+    let server = MockServer::start().unwrap();
+    server.inject(fault(mock::MEDIA, Fault::Status { status: 503 }, Some(2)));
+    let log = Log::default();
+    let client = client(&log, &["outer", "retry", "inner"], None);
+
+    let response = client.get(media_url(&server)).send().unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "outer request",
+            "retry request",
+            "inner request",
+            "inner 503",
+            "inner request",
+            "inner 503",
+            "inner request",
+            "inner 200",
+            "retry 200",
+            "outer 200",
+        ]
+    );
+
+    let received = server.requests();
+    assert_eq!(received.len(), 3);
+    assert_eq!(received[2].headers["x-layers"], "outer,retry,inner");
+}
+
+#[test]
+fn retries_resend_the_same_body_unless_it_is_streamed() {
+    let server = MockServer::start().unwrap();
+    server.inject(fault(mock::MEDIA, Fault::Status { status: 503 }, Some(2)));
+    let client = client(&Log::default(), &["retry"], None);
+
+    client
+        .post(media_url(&server))
+        .body("same body")
+        .send()
+        .unwrap();
+    let received = server.requests();
+    assert_eq!(received.len(), 3);
+    assert!(received.iter().all(|request| request.body == b"same body"));
+
+    server.inject(fault(mock::MEDIA, Fault::Status { status: 503 }, Some(2)));
+    let streamed = reqwest::blocking::Body::new(Cursor::new(b"streamed".to_vec()));
+    let response = client
+        .post(media_url(&server))
+        .body(streamed)
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 503);
+    assert_eq!(server.requests().len(), 4);
+}
+
+#[test]
+fn on_request_can_answer_without_sending() {
+    let server = MockServer::start().unwrap();
+    let log = Log::default();
+    let client = client(
+        &log,
+        &["outer"],
+        Some(Canned {
+            answer_requests: true,
+        }),
+    );
+
+    let response = client.get(media_url(&server)).send().unwrap();
+    assert_eq!(response.status().as_u16(), 203);
+    assert_eq!(response.text().unwrap(), "from cache");
+    assert_eq!(*log.lock().unwrap(), ["outer request", "outer 203"]);
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn on_error_can_recover_with_a_response() {
+    let log = Log::default();
+    let recovering = client(
+        &log,
+        &["outer", "inner"],
+        Some(Canned {
+            answer_requests: false,
+        }),
+    );
+
+    // Nothing listens on port 1, so the request fails to connect.
+    let response = recovering
+        .get("http://127.0.0.1:1/missing")
+        .timeout(Duration::from_secs(5))
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.text().unwrap(), "recovered");
+    assert_eq!(
+        *log.lock().unwrap(),
+        ["outer request", "inner request", "inner 200", "outer 200"]
+    );
+
+    // Without a layer to recover, every layer sees the error on the way out.
+    log.lock().unwrap().clear();
+    let failing = client(&log, &["outer", "inner"], None);
+    let Err(err) = failing
+        .get("http://127.0.0.1:1/missing")
+        .timeout(Duration::from_secs(5))
+        .send()
+    else {
+        panic!("nothing should answer on port 1");
+    };
+    assert!(err.is_connect(), "{}", err);
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "outer request",
+            "inner request",
+            "inner error",
+            "outer error"
+        ]
+    );
+}
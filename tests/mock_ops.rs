@@ -0,0 +1,626 @@
+//! Runs every built-in op against the bundled mock server (`cargo test --features testing`).
+//!
+//! Ops that fall back to the configured endpoints share [`MOCK`], which the core configuration
+//! points at. Fault injection tests start a server of their own so faults cannot leak into other
+//! tests running in parallel.
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64_STD;
+use once_cell::sync::Lazy;
+use serde_json::{Value, json};
+use tomato_novel_network_core::config::{self, CoreConfig};
+use tomato_novel_network_core::mock::{self, Fault, MockServer};
+use tomato_novel_network_core::{CoreError, ErrorKind, ops};
+
+use common::fault;
+
+static MOCK: Lazy<MockServer> = Lazy::new(|| {
+    let server = MockServer::start().expect("start mock server");
+    config::set(CoreConfig {
+        endpoints: server.endpoints(),
+        ..CoreConfig::default()
+    });
+    server
+});
+
+#[cfg(feature = "media_normalize")]
+const PIXEL_PNG_B64: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP8z8BQDwAEhQGAhKmMIQAAAABJRU5ErkJggg==";
+const CHAPTER_ID: &str = "7143038718092067336";
+
+fn call(op: &str, payload: Value) -> Result<Value, CoreError> {
+    Lazy::force(&MOCK);
+    ops::call_operation(op, payload.to_string().as_bytes())
+}
+
+fn ok(op: &str, payload: Value) -> Value {
+    call(op, payload).unwrap_or_else(|err| panic!("{} failed: {}", op, err.message))
+}
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tn-core-mock-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("create scratch dir");
+    dir
+}
+
+#[test]
+fn iid_register_binds_issued_install_id() {
+    let response = ok(
+        "iid_register",
+        json!({ "body_b64": BASE64_STD.encode(b"device"), "profile": "android_app" }),
+    );
+    let install_id = response["install_id_str"].as_str().expect("install_id_str");
+    assert!(response["device_id"].as_u64().is_some());
+
+    let profiles = ok("profile_list", json!({}));
+    assert_eq!(profiles["bindings"][install_id], "android_app");
+}
+
+#[test]
+fn iid_activate_returns_upstream_status() {
+    let response = ok("iid_activate", json!({ "tt_info": "dGVzdA==" }));
+    assert_eq!(response["message"], "success");
+    assert_eq!(ok("iid_activate", json!({ "tt_info": "" })), Value::Null);
+}
+
+#[test]
+fn iid_pool_feeds_ops_and_tracks_failures() {
+    let server = MockServer::start().unwrap();
+    ok(
+        "iid_pool_configure",
+        json!({ "strategy": "round_robin", "max_failures": 2, "cooldown_ms": 60_000 }),
+    );
+    let added = ok(
+        "iid_pool_add",
+        json!({ "install_ids": ["7300000000000000201", "7300000000000000202"] }),
+    );
+    assert_eq!(added["size"], 2);
+    let acquired = ok("iid_pool_acquire", json!({}));
+    assert!(acquired["install_id"].as_str().is_some());
+
+    server.inject(fault(mock::SEARCH, Fault::Status { status: 403 }, Some(1)));
+    let search = json!({ "url": server.url_for(mock::SEARCH), "query": "修仙" });
+    assert!(call("search_books", search.clone()).is_err());
+    assert!(ok("search_books", search)["data"]["search_book_data_list"].is_array());
+    let cookies: Vec<String> = server
+        .requests()
+        .iter()
+        .filter_map(|request| request.headers.get("cookie").cloned())
+        .collect();
+    assert_eq!(cookies.len(), 2);
+    assert_ne!(cookies[0], cookies[1]);
+
+    let failed = cookies[0].trim_start_matches("install_id=");
+    let health = |install_id: &str| {
+        let status = ok("iid_pool_status", json!({}));
+        status["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|entry| entry["install_id"] == install_id)
+            .map(|entry| entry["healthy"].clone())
+    };
+    assert_eq!(health(failed), Some(json!(false)));
+    ok(
+        "iid_pool_report",
+        json!({ "install_id": failed, "status": 200 }),
+    );
+    assert_eq!(health(failed), Some(json!(true)));
+
+    let removed = ok(
+        "iid_pool_remove",
+        json!({ "install_ids": ["7300000000000000201", "7300000000000000202"] }),
+    );
+    assert_eq!(removed["size"], 0);
+}
+
+#[test]
+fn profile_bind_and_unbind() {
+    let install_id = "7300000000000000301";
+    let bound = ok(
+        "profile_bind",
+        json!({ "install_id": install_id, "profile": "desktop_web" }),
+    );
+    assert_eq!(bound["profile"], "desktop_web");
+    assert_eq!(
+        ok("profile_list", json!({}))["bindings"][install_id],
+        "desktop_web"
+    );
+    let unbound = ok("profile_unbind", json!({ "install_id": install_id }));
+    assert_eq!(unbound["profile"], "desktop_web");
+}
+
+#[test]
+fn describe_op_lists_every_operation() {
+    let described = ok("describe_op", json!({}));
+    let described = described["ops"].as_array().expect("array of descriptions");
+    for name in ops::operation_names() {
+        assert!(
+            described.iter().any(|op| op["name"] == name.as_str()),
+            "{} not described",
+            name
+        );
+    }
+}
+
+#[test]
+fn search_books_filters_by_query() {
+    let response = ok(
+        "search_books",
+        json!({ "query": "修仙", "install_id": "7300000000000000401" }),
+    );
+    let books = response["data"]["search_book_data_list"]
+        .as_array()
+        .unwrap();
+    assert_eq!(books.len(), 1);
+    assert_eq!(books[0]["book_name"], "都市修仙记");
+    assert_eq!(ok("search_books", json!({ "query": " " })), Value::Null);
+}
+
+#[test]
+fn book_directory_detail_lists_chapters() {
+    let response = ok(
+        "book_directory_detail",
+        json!({ "book_id": mock::BOOK_ID, "install_id": "7300000000000000501" }),
+    );
+    assert_eq!(response["code"], 0);
+    assert_eq!(response["data"]["allItemIds"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn book_directory_detail_retries_after_warm_up() {
+    let server = MockServer::start().unwrap();
+    server.inject(fault(
+        mock::DIRECTORY,
+        Fault::Status { status: 503 },
+        Some(1),
+    ));
+    let response = ok(
+        "book_directory_detail",
+        json!({ "url": server.url_for(mock::DIRECTORY), "book_id": mock::BOOK_ID }),
+    );
+    assert_eq!(response["data"]["bookInfo"]["bookId"], mock::BOOK_ID);
+    let directory_calls = server
+        .requests()
+        .iter()
+        .filter(|request| request.path == mock::DIRECTORY)
+        .count();
+    assert_eq!(directory_calls, 2);
+}
+
+#[test]
+fn review_comment_stats_and_list() {
+    let stats = ok(
+        "review_comment_stats",
+        json!({
+            "chapter_id": CHAPTER_ID,
+            "item_version": "4f1c",
+            "install_id": "7300000000000000601",
+        }),
+    );
+    assert_eq!(stats["data"]["total"], 11);
+
+    let list = ok(
+        "review_comment_list",
+        json!({
+            "chapter_id": CHAPTER_ID,
+            "install_id": "7300000000000000601",
+            "business_param": {},
+            "comment_source": 0,
+            "comment_type": 0,
+            "count": 3,
+            "group_type": 2,
+            "sort": 0,
+        }),
+    );
+    assert_eq!(list["data"]["comments"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn signed_session_register_key_is_remembered() {
+    let install_id = "7300000000000000701";
+    let response = ok(
+        "signed_session_register_key",
+        json!({ "install_id": install_id, "body": { "content": "c2lnbmVk", "keyver": 1 } }),
+    );
+    assert!(response["data"]["key"].as_str().is_some());
+    let session = ok("session_store_get", json!({}));
+    let holders = session["signed_keys"].as_array().unwrap();
+    assert!(holders.iter().any(|holder| holder == install_id));
+    assert!(!session.to_string().contains("c2lnbmVk"));
+}
+
+#[test]
+fn signed_session_batch_full_and_request() {
+    let full = ok(
+        "signed_session_batch_full",
+        json!({
+            "query": format!("item_ids={}&aid=1967", CHAPTER_ID),
+            "headers": { "x-tt-trace": "1" },
+        }),
+    );
+    assert_eq!(full["data"][CHAPTER_ID]["title"], "第1章 穿越");
+
+    let bodies = ok(
+        "signed_session_batch_request",
+        json!({ "chapter_ids": [CHAPTER_ID, "7143038718394057228"] }),
+    );
+    let bodies = bodies.as_array().unwrap();
+    assert_eq!(bodies.len(), 2);
+    let second: Value = serde_json::from_str(bodies[1].as_str().unwrap()).unwrap();
+    assert_eq!(second["data"]["title"], "第2章 系统觉醒");
+}
+
+#[test]
+fn session_store_put_and_get_cookies() {
+    let put = ok(
+        "session_store_put",
+        json!({ "cookies": { "mock_session_cookie": "abc" } }),
+    );
+    assert!(put["cookies"].as_u64().unwrap() >= 1);
+    assert_eq!(
+        ok("session_store_get", json!({}))["cookies"]["mock_session_cookie"],
+        "[redacted]"
+    );
+    assert_eq!(
+        ok("session_store_get", json!({ "reveal": true }))["cookies"]["mock_session_cookie"],
+        "abc"
+    );
+    ok(
+        "session_store_put",
+        json!({ "remove_cookies": ["mock_session_cookie"] }),
+    );
+    assert!(
+        ok("session_store_get", json!({}))["cookies"]
+            .get("mock_session_cookie")
+            .is_none()
+    );
+}
+
+#[test]
+fn media_fetch_and_fetch_many() {
+    let url = MOCK.url_for(&format!("{}cover.png", mock::MEDIA));
+    let fetched = ok("media_fetch", json!({ "url": url, "cache": false }));
+    assert_eq!(fetched["status"], 200);
+    assert_eq!(fetched["content_type"], "image/png");
+
+    let dir = scratch("media-many");
+    let many = ok(
+        "media_fetch_many",
+        json!({
+            "items": [
+                { "url": url, "dest_path": dir.join("a.png") },
+                { "url": MOCK.url_for(&format!("{}b.png", mock::MEDIA)), "dest_path": dir.join("b.png") },
+            ],
+            "cache": false,
+        }),
+    );
+    assert_eq!(many["succeeded"], 2);
+    assert!(dir.join("b.png").exists());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn media_cache_revalidates_and_serves_stale() {
+    let server = MockServer::start().unwrap();
+    let url = server.url_for(&format!("{}cached.png", mock::MEDIA));
+    let dir = scratch("media-cache");
+    let configured = ok("media_cache_configure", json!({ "dir": dir }));
+    assert_eq!(configured["enabled"], true);
+
+    let first = ok("media_fetch", json!({ "url": url }));
+    assert_eq!(first["cache"], "miss");
+    let second = ok("media_fetch", json!({ "url": url }));
+    assert_eq!(second["cache"], "revalidated");
+    server.inject(fault(mock::MEDIA, Fault::Status { status: 502 }, None));
+    let third = ok("media_fetch", json!({ "url": url }));
+    assert_eq!(third["cache"], "stale");
+
+    assert!(
+        ok("media_cache_stats", json!({}))["entries"]
+            .as_u64()
+            .unwrap()
+            >= 1
+    );
+    let purged = ok("media_cache_purge", json!({ "url": url }));
+    assert_eq!(purged["removed"], 1);
+
+    // With room for a single body, storing a second URL evicts the least recently used one.
+    server.clear_faults();
+    ok("media_fetch", json!({ "url": url }));
+    let size = ok("media_cache_stats", json!({}))["bytes"]
+        .as_u64()
+        .unwrap();
+    let configured = ok(
+        "media_cache_configure",
+        json!({ "dir": dir, "max_bytes": size }),
+    );
+    assert_eq!(configured["entries"], 1);
+    let other = server.url_for(&format!("{}other.png", mock::MEDIA));
+    assert_eq!(ok("media_fetch", json!({ "url": other }))["cache"], "miss");
+    let stats = ok("media_cache_stats", json!({}));
+    assert_eq!(stats["entries"], 1);
+    assert_eq!(stats["bytes"], size);
+    assert_eq!(
+        ok("media_fetch", json!({ "url": other }))["cache"],
+        "revalidated"
+    );
+    assert_eq!(ok("media_fetch", json!({ "url": url }))["cache"], "miss");
+    ok("media_cache_configure", json!({ "dir": "" }));
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[cfg(feature = "media_normalize")]
+#[test]
+fn media_normalize_image_reencodes_png() {
+    let response = ok(
+        "media_normalize_image",
+        json!({ "body_b64": PIXEL_PNG_B64, "max_dimension": 16 }),
+    );
+    assert_eq!(response["width"], 1);
+}
+
+#[test]
+fn version_fetch_filename_follows_redirect() {
+    let response = ok(
+        "version_fetch_filename",
+        json!({ "url": MOCK.url_for(mock::LATEST_DOWNLOAD) }),
+    );
+    assert_eq!(response["filename"], mock::RELEASE_ASSET);
+}
+
+#[test]
+fn update_check_from_releases_and_filename() {
+    let releases_url = MOCK.url_for(mock::RELEASES);
+    let stable = ok(
+        "update_check",
+        json!({ "current_version": "1.3.0", "releases_url": releases_url }),
+    );
+    assert_eq!(stable["latest"], mock::RELEASE_VERSION);
+    assert_eq!(stable["update_available"], true);
+    assert_eq!(stable["filename"], mock::RELEASE_ASSET);
+
+    let prerelease = ok(
+        "update_check",
+        json!({
+            "current_version": "1.4.0",
+            "releases_url": releases_url,
+            "channel": "prerelease",
+        }),
+    );
+    assert_eq!(prerelease["latest"], "1.5.0-beta.1");
+
+    let filename = ok(
+        "update_check",
+        json!({ "current_version": "1.4.0", "url": MOCK.url_for(mock::LATEST_DOWNLOAD) }),
+    );
+    assert_eq!(filename["source"], "filename");
+    assert_eq!(filename["update_available"], false);
+}
+
+#[test]
+fn download_release_verifies_checksum_and_signature() {
+    let dir = scratch("release");
+    let asset_url = MOCK.url_for(&format!("{}{}", mock::DOWNLOAD, mock::RELEASE_ASSET));
+    let dest = dir.join(mock::RELEASE_ASSET);
+    // A partial download from an earlier attempt, to be resumed.
+    let partial: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    fs::write(dir.join(format!("{}.part", mock::RELEASE_ASSET)), partial).unwrap();
+
+    let response = ok(
+        "download_release",
+        json!({
+            "url": asset_url,
+            "dest_path": dest,
+            "checksum_url": MOCK.url_for(&format!("{}SHA256SUMS", mock::DOWNLOAD)),
+            "signature_kind": "ed25519",
+            "public_key": MockServer::release_public_key(),
+        }),
+    );
+    assert_eq!(response["resumed_from"], 1000);
+    assert_eq!(response["sha256_verified"], true);
+    assert_eq!(response["signature_verified"], true);
+    assert!(dest.exists());
+
+    let tampered = call(
+        "download_release",
+        json!({
+            "url": asset_url,
+            "dest_path": dir.join("copy.zip"),
+            "sha256": "00".repeat(32),
+            "signature_kind": "ed25519",
+            "public_key": MockServer::release_public_key(),
+        }),
+    );
+    assert!(tampered.is_err());
+    assert!(!dir.join("copy.zip").exists());
+
+    let unsigned = call(
+        "download_release",
+        json!({ "url": asset_url, "dest_path": dir.join("copy.zip"), "sha256": "00".repeat(32) }),
+    )
+    .unwrap_err();
+    assert_eq!(unsigned.kind, ErrorKind::Validation);
+
+    let no_signature = call(
+        "download_release",
+        json!({
+            "url": asset_url,
+            "dest_path": dir.join("copy.zip"),
+            "sha256": MockServer::release_sha256(),
+            "public_key": MockServer::release_public_key(),
+        }),
+    )
+    .unwrap_err();
+    assert!(
+        no_signature.message.contains("signature"),
+        "{}",
+        no_signature.message
+    );
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn rate_limited_search_surfaces_429() {
+    let server = MockServer::start().unwrap();
+    server.inject(fault(mock::SEARCH, Fault::Status { status: 429 }, Some(1)));
+    let search = json!({
+        "url": server.url_for(mock::SEARCH),
+        "query": "星海",
+        "install_id": "7300000000000000801",
+    });
+    let err = call("search_books", search.clone()).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Operation);
+    assert!(err.message.contains("429"), "{}", err.message);
+    assert!(ok("search_books", search)["data"]["search_book_data_list"].is_array());
+}
+
+#[test]
+fn server_error_fails_comment_stats() {
+    let server = MockServer::start().unwrap();
+    server.inject(fault(
+        mock::COMMENT_STATS,
+        Fault::Status { status: 500 },
+        None,
+    ));
+    let err = call(
+        "review_comment_stats",
+        json!({
+            "base_url": server.url_for(mock::COMMENT_STATS),
+            "chapter_id": CHAPTER_ID,
+            "item_version": "1",
+            "install_id": "7300000000000000901",
+        }),
+    )
+    .unwrap_err();
+    assert!(err.message.contains("500"), "{}", err.message);
+}
+
+#[test]
+fn malformed_json_fails_batch_full() {
+    let server = MockServer::start().unwrap();
+    server.inject(fault(mock::BATCH_FULL, Fault::MalformedJson, None));
+    let err = call(
+        "signed_session_batch_full",
+        json!({
+            "base_url": format!("{}?", server.url_for(mock::BATCH_FULL)),
+            "query": format!("item_ids={}", CHAPTER_ID),
+            "headers": {},
+        }),
+    )
+    .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Operation);
+}
+
+#[test]
+fn forbidden_register_key_fails() {
+    let server = MockServer::start().unwrap();
+    server.inject(fault(
+        mock::REGISTER_KEY,
+        Fault::Status { status: 403 },
+        None,
+    ));
+    let err = call(
+        "signed_session_register_key",
+        json!({
+            "url": server.url_for(mock::REGISTER_KEY),
+            "install_id": "7300000000000001001",
+            "body": {},
+        }),
+    )
+    .unwrap_err();
+    assert!(err.message.contains("403"), "{}", err.message);
+}
+
+#[test]
+fn slow_media_times_out() {
+    let server = MockServer::start().unwrap();
+    server.inject(fault(mock::MEDIA, Fault::Delay { ms: 1_500 }, None));
+    let url = server.url_for(&format!("{}slow.png", mock::MEDIA));
+    let err = call(
+        "media_fetch",
+        json!({ "url": url, "timeout_ms": 200, "cache": false }),
+    )
+    .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Operation);
+
+    server.clear_faults();
+    assert_eq!(
+        ok("media_fetch", json!({ "url": url, "cache": false }))["status"],
+        200
+    );
+}
+
+#[test]
+fn dry_run_returns_requests_without_sending_them() {
+    let server = MockServer::start().unwrap();
+    let url = server.url_for(&format!("{}cover.png", mock::MEDIA));
+    let response = ok(
+        "media_fetch",
+        json!({ "url": url, "cache": false, "dry_run": true }),
+    );
+    assert_eq!(response["dry_run"], true);
+    let request = &response["requests"][0];
+    assert_eq!(request["method"], "GET");
+    assert_eq!(request["url"], url);
+    assert!(request["headers"]["host"].as_str().is_some());
+
+    let head = ok(
+        "version_fetch_filename",
+        json!({ "url": server.url_for(mock::LATEST_DOWNLOAD), "dry_run": true }),
+    );
+    assert_eq!(head["requests"][0]["method"], "HEAD");
+
+    let dir = std::env::temp_dir().join(format!("tn-core-mock-{}-dry", std::process::id()));
+    let download = ok(
+        "download_release",
+        json!({
+            "url": server.url_for(&format!("{}{}", mock::DOWNLOAD, mock::RELEASE_ASSET)),
+            "dest_path": dir.join(mock::RELEASE_ASSET),
+            "sha256": MockServer::release_sha256(),
+            "public_key": MockServer::release_public_key(),
+            "dry_run": true,
+        }),
+    );
+    assert_eq!(download["requests"][0]["headers"]["accept"], "*/*");
+    assert!(!dir.exists());
+
+    let register = ok(
+        "iid_register",
+        json!({
+            "url": server.url_for(mock::DEVICE_REGISTER),
+            "body_b64": BASE64_STD.encode(b"device"),
+            "dry_run": true,
+        }),
+    );
+    let request = &register["requests"][0];
+    assert_eq!(request["method"], "POST");
+    assert_eq!(request["body"], "device");
+    assert_eq!(request["headers"]["content-length"], "6");
+
+    let install_id = "7300000000000001101";
+    let searched = ok(
+        "search_books",
+        json!({
+            "url": server.url_for(mock::SEARCH),
+            "query": "修仙",
+            "install_id": install_id,
+            "dry_run": true,
+        }),
+    );
+    assert_eq!(searched["requests"].as_array().map(Vec::len), Some(1));
+
+    let local = ok("profile_list", json!({ "dry_run": true }));
+    assert_eq!(local["requests"], json!([]));
+    let bindings = &ok("profile_list", json!({}))["bindings"];
+    assert!(bindings.get(install_id).is_none(), "{}", bindings);
+    assert!(server.requests().is_empty());
+
+    let err = call("profile_list", json!({ "dry_run": "yes" })).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Validation);
+}
@@ -0,0 +1,185 @@
+//! Offline replay against the fixtures in `tests/fixtures/replay`.
+//!
+//! Replay settings live in the global configuration, so each test holds [`LOCK`] while it
+//! installs its own.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+use serde_json::{Value, json};
+use tomato_novel_network_core::blocking::Client;
+use tomato_novel_network_core::config::{self, CoreConfig, Replay, ReplayMode};
+use tomato_novel_network_core::{NetworkError, NetworkResult};
+
+const API: &str = "https://api.example.test";
+
+static LOCK: Mutex<()> = Mutex::new(());
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/replay")
+}
+
+fn replay(source: &Path, mode: ReplayMode, match_body: bool) -> MutexGuard<'static, ()> {
+    let guard = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    config::set(CoreConfig {
+        replay: Replay {
+            source: source.to_string_lossy().into_owned(),
+            mode,
+            match_body,
+        },
+        ..CoreConfig::default()
+    });
+    guard
+}
+
+fn get(path: &str) -> NetworkResult<Value> {
+    Client::builder()
+        .build()?
+        .get(format!("{}{}", API, path))
+        .send()?
+        .error_for_status()?
+        .json()
+}
+
+fn post(path: &str, body: &str) -> Result<(u16, String), NetworkError> {
+    let response = Client::builder()
+        .build()?
+        .post(format!("{}{}", API, path))
+        .body(body.to_string())
+        .send()?;
+    Ok((response.status().as_u16(), response.text()?))
+}
+
+fn unmatched<T>(result: NetworkResult<T>) {
+    let Err(err) = result else {
+        panic!("request was answered");
+    };
+    assert_eq!(
+        err.status().map(|status| status.as_u16()),
+        Some(501),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn query_parameters_match_in_any_order() {
+    let _replay = replay(&fixtures(), ReplayMode::Strict, false);
+    assert_eq!(get("/search?a=1&b=2").unwrap(), json!({ "hits": 1 }));
+    assert_eq!(get("/search?b=2&a=1").unwrap(), json!({ "hits": 1 }));
+    unmatched(get("/search?a=1&b=3"));
+    unmatched(get("/search?a=1"));
+    unmatched(get("/search?a=1&b=2&c=3"));
+}
+
+#[test]
+fn redacted_values_match_anything() {
+    let _replay = replay(&fixtures(), ReplayMode::Strict, true);
+    assert_eq!(
+        get("/item?install_id=7300000000000000001&id=7").unwrap(),
+        json!({ "item": 7 })
+    );
+    unmatched(get("/item?install_id=7300000000000000001&id=8"));
+
+    let registered = post(
+        "/register",
+        r#"{ "tags": ["a", "b"], "install_id": "7300000000000000001", "device": "phone" }"#,
+    );
+    assert_eq!(
+        registered.unwrap(),
+        (201, r#"{"registered":true}"#.to_string())
+    );
+}
+
+#[test]
+fn json_bodies_compare_as_values() {
+    let matching = replay(&fixtures(), ReplayMode::Strict, true);
+    unmatched(post(
+        "/register",
+        r#"{"device":"tablet","install_id":"1","tags":["a","b"]}"#,
+    ));
+    unmatched(post(
+        "/register",
+        r#"{"device":"phone","install_id":"1","tags":["b","a"]}"#,
+    ));
+    unmatched(post(
+        "/register",
+        r#"{"device":"phone","install_id":"1","tags":["a","b"],"extra":true}"#,
+    ));
+
+    drop(matching);
+
+    // Bodies are ignored unless `match_body` is set.
+    let _replay = replay(&fixtures(), ReplayMode::Strict, false);
+    assert_eq!(post("/register", "anything").unwrap().0, 201);
+}
+
+#[test]
+fn duplicate_recordings_are_served_in_turn() {
+    let _replay = replay(&fixtures(), ReplayMode::Strict, false);
+    let pages: Vec<Value> = (0..4)
+        .map(|_| get("/page").unwrap()["page"].clone())
+        .collect();
+    assert_eq!(pages, [json!(1), json!(2), json!(1), json!(2)]);
+}
+
+#[test]
+fn truncated_bodies_match_any_body() {
+    let _replay = replay(&fixtures(), ReplayMode::Strict, true);
+    assert_eq!(
+        post("/upload", "a body much longer than what was recorded").unwrap(),
+        (200, "stored".to_string())
+    );
+}
+
+#[test]
+fn strict_mode_fails_and_passthrough_sends_unmatched_requests() {
+    // Nothing listens on port 1, so a request that reaches the network fails to connect.
+    let unreachable = || {
+        Client::builder()
+            .build()?
+            .get("http://127.0.0.1:1/missing")
+            .timeout(Duration::from_secs(5))
+            .send()
+    };
+
+    let strict = replay(&fixtures(), ReplayMode::Strict, false);
+    unmatched(unreachable());
+    drop(strict);
+
+    let _passthrough = replay(&fixtures(), ReplayMode::Passthrough, false);
+    let Err(err) = unreachable() else {
+        panic!("nothing should answer on port 1");
+    };
+    assert!(err.is_connect(), "{}", err);
+    assert_eq!(get("/search?a=1&b=2").unwrap(), json!({ "hits": 1 }));
+}
+
+#[test]
+fn edited_fixtures_are_reloaded() {
+    let dir = std::env::temp_dir().join(format!("tn-core-replay-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let fixture = dir.join("search.json");
+    fs::copy(fixtures().join("search.json"), &fixture).unwrap();
+
+    let _replay = replay(&dir, ReplayMode::Strict, false);
+    assert_eq!(get("/search?a=1&b=2").unwrap(), json!({ "hits": 1 }));
+
+    // Rewriting a file in place leaves the directory's own modification time alone.
+    let edited = fs::read_to_string(&fixture)
+        .unwrap()
+        .replace(r#""hits": 1"#, r#""hits": 2"#);
+    fs::write(&fixture, edited).unwrap();
+    File::options()
+        .write(true)
+        .open(&fixture)
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(60))
+        .unwrap();
+    assert_eq!(get("/search?a=1&b=2").unwrap(), json!({ "hits": 2 }));
+
+    let _ = fs::remove_dir_all(dir);
+}